@@ -0,0 +1,360 @@
+//! Headless runner: executes a ROM without a window or audio device and reports the
+//! outcome through the process exit status. Intended for CI and batch test ROM runs.
+use mimalloc::MiMalloc;
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+use rust_gbe::bootrom::BootRom;
+use rust_gbe::camera::CameraImage;
+use rust_gbe::device::Device;
+use rust_gbe::gdb::{GdbServer, SessionEnd};
+use rust_gbe::link::TcpLink;
+use rust_gbe::movie::{Movie, MoviePlayer};
+use rust_gbe::printer::Printer;
+use rust_gbe::trace::{TraceFilter, Tracer};
+use rust_gbe::{HardwareModel, PpuRenderer};
+use std::path::Path;
+use std::time::Duration;
+
+const EXITCODE_SUCCESS: i32 = 0;
+const EXITCODE_FAILURE: i32 = 1;
+const EXITCODE_CPULOADFAILS: i32 = 2;
+const EXITCODE_USAGE: i32 = 3;
+
+// 154 lines of 456 dots; Device::do_cycle reports ticks at single speed.
+const TICKS_PER_FRAME: u32 = 70224;
+const DEFAULT_FRAMES: u32 = 3600;
+
+const USAGE: &str = "\
+Usage: rust-gbe-headless [OPTIONS] <ROM>
+
+Options:
+  --model <MODEL>         Hardware to emulate: DMG, MGB, SGB, CGB or AGB (default: CGB,
+                          or DMG with a 256 byte boot ROM)
+  --dmg                   Same as --model DMG
+  --cgb                   Same as --model CGB
+  --boot-rom <FILE>       Run a DMG/MGB/SGB or CGB/AGB boot ROM first
+  --fifo                  Use the dot-based pixel FIFO renderer instead of the scanline one
+  --frames <N>            Stop after N frames (default: 3600)
+  --until-serial <TEXT>   Succeed as soon as the serial output contains TEXT
+  --fail-serial <TEXT>    Fail as soon as the serial output contains TEXT
+  --expect-checksum <N>   Fail unless the final frame checksum equals N
+  --print-serial          Echo serial output to stdout while running
+  --link-listen <ADDR>    Wait for another emulator to connect a link cable on ADDR
+  --link-connect <ADDR>   Connect a link cable to an emulator listening on ADDR
+  --printer               Attach a Game Boy Printer; printouts are saved as PNGs next to the ROM
+  --camera-image <FILE>   Show a PNG or BMP image to a Pocket Camera (default: a test pattern)
+  --trace <FILE>          Log every executed instruction in Gameboy Doctor format to FILE
+  --trace-pc <START-END>  Only trace instructions with PC in START-END (hex)
+  --trace-bank <N>        Only trace ROM instructions while ROM bank N (hex) is mapped
+  --play-movie <FILE>     Replay an input movie and fail unless it ends on the recorded frame
+  --gdb <ADDR>            Wait for a GDB client on ADDR and let it control execution until it detaches
+  -h, --help              Show this message
+
+Exit status: 0 on success, 1 on failure or timeout, 2 if the ROM cannot be loaded,
+3 on invalid arguments.";
+
+struct Options {
+    rom: String,
+    model: Option<HardwareModel>,
+    boot_rom: Option<String>,
+    fifo: bool,
+    frames: u32,
+    until_serial: Vec<String>,
+    fail_serial: Vec<String>,
+    expect_checksum: Option<u32>,
+    print_serial: bool,
+    link_listen: Option<String>,
+    link_connect: Option<String>,
+    printer: bool,
+    camera_image: Option<String>,
+    gdb: Option<String>,
+    play_movie: Option<String>,
+    trace: Option<String>,
+    trace_filter: TraceFilter,
+}
+
+fn main() {
+    std::process::exit(real_main());
+}
+
+fn real_main() -> i32 {
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(opts)) => opts,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return EXITCODE_SUCCESS;
+        }
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            return EXITCODE_USAGE;
+        }
+    };
+
+    let mut device = match construct_device(&opts) {
+        Ok(device) => device,
+        Err(msg) => {
+            eprintln!("Could not load {}: {}", opts.rom, msg);
+            return EXITCODE_CPULOADFAILS;
+        }
+    };
+    device.enable_serial_capture();
+    if opts.fifo {
+        device.set_renderer(PpuRenderer::Fifo);
+    }
+
+    let link = match (&opts.link_listen, &opts.link_connect) {
+        (Some(addr), _) => Some(TcpLink::listen(addr.as_str())),
+        (_, Some(addr)) => Some(TcpLink::connect(addr.as_str(), Duration::from_secs(10))),
+        _ => None,
+    };
+    match link {
+        Some(Ok(link)) => device.set_serial_link(Box::new(link)),
+        Some(Err(e)) => {
+            eprintln!("Could not connect link cable: {}", e);
+            return EXITCODE_FAILURE;
+        }
+        None => {}
+    }
+    if opts.printer {
+        device.set_serial_link(Box::new(Printer::new(&opts.rom)));
+    }
+    if let Some(path) = &opts.camera_image {
+        match CameraImage::load(Path::new(path)) {
+            Ok(image) => device.set_camera_image(&image),
+            Err(e) => {
+                eprintln!("Could not load camera image {}: {}", path, e);
+                return EXITCODE_FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &opts.trace {
+        match Tracer::to_file(path, opts.trace_filter) {
+            Ok(tracer) => device.start_trace(tracer),
+            Err(e) => {
+                eprintln!("Could not create trace file {}: {}", path, e);
+                return EXITCODE_FAILURE;
+            }
+        }
+    }
+
+    if let Some(addr) = &opts.gdb {
+        eprintln!("Waiting for GDB to connect on {}", addr);
+        let session = GdbServer::accept(addr.as_str()).and_then(|mut gdb| gdb.run(&mut device));
+        match session {
+            Ok(SessionEnd::Detached) => {}
+            Ok(SessionEnd::Killed) => {
+                println!("FAIL: killed by debugger");
+                return EXITCODE_FAILURE;
+            }
+            Err(e) => {
+                eprintln!("GDB session failed: {}", e);
+                return EXITCODE_FAILURE;
+            }
+        }
+    }
+
+    let mut player = None;
+    if let Some(path) = &opts.play_movie {
+        match Movie::load(path).and_then(|movie| MoviePlayer::new(movie, &mut device)) {
+            Ok(p) => player = Some(p),
+            Err(e) => {
+                eprintln!("Could not play movie {}: {}", path, e);
+                return EXITCODE_FAILURE;
+            }
+        }
+    }
+
+    let mut serial = Vec::new();
+    let mut frame = 0;
+    let mut matched = None;
+    // A movie runs to its end regardless of --frames.
+    let running = |frame: u32, player: &Option<MoviePlayer>| match player {
+        Some(p) => !p.is_finished(),
+        None => frame < opts.frames,
+    };
+    while running(frame, &player) && matched.is_none() {
+        let mut ticks = 0;
+        while ticks < TICKS_PER_FRAME {
+            ticks += match player.as_mut() {
+                Some(p) if p.is_finished() => break,
+                Some(p) => p.do_cycle(&mut device),
+                None => device.do_cycle(),
+            };
+        }
+        frame += 1;
+
+        let output = device.take_serial_output();
+        if output.is_empty() {
+            continue;
+        }
+        if opts.print_serial {
+            print!("{}", String::from_utf8_lossy(&output));
+        }
+        serial.extend_from_slice(&output);
+        let text = String::from_utf8_lossy(&serial);
+        if let Some(t) = opts.fail_serial.iter().find(|t| text.contains(t.as_str())) {
+            matched = Some(Err(format!("serial output contains \"{}\"", t)));
+        } else if let Some(t) = opts.until_serial.iter().find(|t| text.contains(t.as_str())) {
+            matched = Some(Ok(format!("serial output contains \"{}\"", t)));
+        }
+    }
+
+    if opts.print_serial && !serial.is_empty() {
+        println!();
+    }
+    if let Err(e) = device.stop_trace() {
+        eprintln!("Could not write trace: {}", e);
+    }
+
+    let checksum = frame_checksum(device.get_gpu_data());
+    println!(
+        "{}: {} frames, {} mode on {}, frame checksum {}",
+        opts.rom,
+        frame,
+        if device.is_cgb_mode() { "CGB" } else { "DMG" },
+        device.model().name(),
+        checksum
+    );
+
+    let verdict = match matched {
+        Some(verdict) => verdict,
+        None if !opts.until_serial.is_empty() => Err(format!(
+            "serial output did not match within {} frames",
+            opts.frames
+        )),
+        None => Ok(format!("ran {} frames", frame)),
+    };
+    let verdict = verdict.and_then(|reason| match &player {
+        Some(p) => p
+            .verify(&device)
+            .map(|_| format!("movie replayed identically in {} frames", frame))
+            .map_err(str::to_string),
+        None => Ok(reason),
+    });
+    let verdict = verdict.and_then(|reason| match opts.expect_checksum {
+        Some(expected) if expected != checksum => Err(format!(
+            "frame checksum {} does not match expected {}",
+            checksum, expected
+        )),
+        _ => Ok(reason),
+    });
+
+    match verdict {
+        Ok(reason) => {
+            println!("PASS: {}", reason);
+            EXITCODE_SUCCESS
+        }
+        Err(reason) => {
+            println!("FAIL: {}", reason);
+            EXITCODE_FAILURE
+        }
+    }
+}
+
+fn construct_device(opts: &Options) -> Result<Device, &'static str> {
+    // Load into memory so batch runs never create or overwrite save files next to the ROM.
+    let data = std::fs::read(&opts.rom).map_err(|_| "Could not read ROM")?;
+    let boot_rom = match &opts.boot_rom {
+        Some(path) => Some(BootRom::load(Path::new(path))?),
+        None => None,
+    };
+    let model = opts.model.unwrap_or(match &boot_rom {
+        Some(boot_rom) if !boot_rom.is_cgb() => HardwareModel::Dmg,
+        _ => HardwareModel::Cgb,
+    });
+    Device::new_from_buffer_with_model(data, model, boot_rom, false, None)
+}
+
+/// Same position-weighted sum the core's regression tests use to fingerprint a frame.
+fn frame_checksum(frame: &[u8]) -> u32 {
+    frame
+        .iter()
+        .enumerate()
+        .fold(0u32, |sum, (i, &v)| sum.wrapping_add((v as u32).wrapping_mul(i as u32)))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut rom = None;
+    let mut opts = Options {
+        rom: String::new(),
+        model: None,
+        boot_rom: None,
+        fifo: false,
+        frames: DEFAULT_FRAMES,
+        until_serial: Vec::new(),
+        fail_serial: Vec::new(),
+        expect_checksum: None,
+        print_serial: false,
+        link_listen: None,
+        link_connect: None,
+        printer: false,
+        camera_image: None,
+        gdb: None,
+        play_movie: None,
+        trace: None,
+        trace_filter: TraceFilter::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("Missing value for {}", name));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--model" => {
+                let v = value(&arg)?;
+                opts.model =
+                    Some(HardwareModel::from_name(&v).ok_or(format!("Unknown model: {}", v))?);
+            }
+            "--dmg" => opts.model = Some(HardwareModel::Dmg),
+            "--cgb" => opts.model = Some(HardwareModel::Cgb),
+            "--boot-rom" => opts.boot_rom = Some(value(&arg)?),
+            "--fifo" => opts.fifo = true,
+            "--frames" => {
+                let v = value(&arg)?;
+                opts.frames = v.parse().map_err(|_| format!("Invalid frame count: {}", v))?;
+            }
+            "--until-serial" => opts.until_serial.push(value(&arg)?),
+            "--fail-serial" => opts.fail_serial.push(value(&arg)?),
+            "--expect-checksum" => {
+                let v = value(&arg)?;
+                opts.expect_checksum =
+                    Some(v.parse().map_err(|_| format!("Invalid checksum: {}", v))?);
+            }
+            "--print-serial" => opts.print_serial = true,
+            "--link-listen" => opts.link_listen = Some(value(&arg)?),
+            "--link-connect" => opts.link_connect = Some(value(&arg)?),
+            "--printer" => opts.printer = true,
+            "--camera-image" => opts.camera_image = Some(value(&arg)?),
+            "--gdb" => opts.gdb = Some(value(&arg)?),
+            "--play-movie" => opts.play_movie = Some(value(&arg)?),
+            "--trace" => opts.trace = Some(value(&arg)?),
+            "--trace-pc" => {
+                let v = value(&arg)?;
+                let range = v.split_once('-').and_then(|(start, end)| {
+                    Some((
+                        u16::from_str_radix(start, 16).ok()?,
+                        u16::from_str_radix(end, 16).ok()?,
+                    ))
+                });
+                opts.trace_filter.pc = Some(range.ok_or(format!("Invalid PC range: {}", v))?);
+            }
+            "--trace-bank" => {
+                let v = value(&arg)?;
+                opts.trace_filter.bank =
+                    Some(usize::from_str_radix(&v, 16).map_err(|_| format!("Invalid bank: {}", v))?);
+            }
+            s if s.starts_with('-') => return Err(format!("Unknown option: {}", s)),
+            _ if rom.is_some() => return Err(format!("Unexpected argument: {}", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+
+    let links = [opts.link_listen.is_some(), opts.link_connect.is_some(), opts.printer];
+    if links.iter().filter(|&&l| l).count() > 1 {
+        return Err("--link-listen, --link-connect and --printer are mutually exclusive".to_string());
+    }
+    opts.rom = rom.ok_or("Missing ROM path")?;
+    Ok(Some(opts))
+}
@@ -0,0 +1,104 @@
+//! User-supplied boot ROMs. The DMG and MGB ROMs are 256 bytes mapped at 0x0000-0x00FF;
+//! the CGB and AGB ROMs are 2304 bytes, mapped at 0x0000-0x00FF and 0x0200-0x08FF around
+//! the cartridge header. Writing to 0xFF50 unmaps them.
+use crate::StrResult;
+use std::path::Path;
+
+const DMG_SIZE: usize = 0x100;
+const CGB_SIZE: usize = 0x900;
+
+#[derive(Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> StrResult<BootRom> {
+        match data.len() {
+            DMG_SIZE | CGB_SIZE => Ok(BootRom { data }),
+            _ => Err("Boot ROM must be 256 (DMG/MGB) or 2304 (CGB/AGB) bytes"),
+        }
+    }
+
+    pub fn load(path: &Path) -> StrResult<BootRom> {
+        let data = std::fs::read(path).map_err(|_| "Could not read boot ROM")?;
+        BootRom::new(data)
+    }
+
+    /// True for CGB/AGB boot ROMs, which only run on the color models.
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_SIZE
+    }
+
+    pub(crate) fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+    use crate::gbmode::HardwareModel;
+
+    fn cart() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        rom
+    }
+
+    // Jumps to `entry` and finishes with LD A,$01; LDH ($50),A at 0x00FC, falling
+    // through to the cartridge entry point.
+    fn boot_rom(size: usize, entry: u16, code: &[u8]) -> BootRom {
+        let mut data = vec![0; size];
+        data[0..3].copy_from_slice(&[0xC3, entry as u8, (entry >> 8) as u8]);
+        data[entry as usize..entry as usize + code.len()].copy_from_slice(code);
+        data[0xFC..0x100].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        BootRom::new(data).unwrap()
+    }
+
+    #[test]
+    fn runs_until_unmapped() {
+        assert!(BootRom::new(vec![0; 0x200]).is_err());
+        let model = HardwareModel::Dmg;
+        let boot = boot_rom(0x100, 0x80, &[0x31, 0xFE, 0xFF, 0xC3, 0xFC, 0x00]);
+        let cgb = HardwareModel::Cgb;
+        assert!(Device::new_from_buffer_with_model(cart(), cgb, Some(boot.clone()), true, None).is_err());
+        let mut device = Device::new_from_buffer_with_model(cart(), model, Some(boot), true, None).unwrap();
+        assert_eq!(device.registers().pc, 0x0000);
+        assert_eq!(device.read_memory(0x0000), 0xC3);
+        for _ in 0..4 {
+            device.do_cycle();
+        }
+        assert!(device.boot_rom_mapped());
+        device.do_cycle();
+        assert!(!device.boot_rom_mapped());
+        assert_eq!(device.registers().pc, 0x0100);
+        assert_eq!(device.registers().sp, 0xFFFE);
+        assert_eq!(device.read_memory(0x0000), 0x00);
+    }
+
+    #[test]
+    fn cgb_boot_rom_leaves_compatibility_palette() {
+        let code = [
+            0x3E, 0x80, 0xE0, 0x68, // BCPS: index 0, auto-increment
+            0x3E, 0x1F, 0xE0, 0x69, 0xAF, 0xE0, 0x69, // BG color 0 = pure red
+            0x3E, 0xE4, 0xE0, 0x47, // BGP
+            0x3E, 0x04, 0xE0, 0x4C, // KEY0: DMG compatibility mode
+            0x3E, 0x91, 0xE0, 0x40, // LCD on
+            0xC3, 0xFC, 0x00,
+        ];
+        let boot = boot_rom(0x900, 0x200, &code);
+        let model = HardwareModel::Cgb;
+        let mut device = Device::new_from_buffer_with_model(cart(), model, Some(boot), true, None).unwrap();
+        assert!(device.is_cgb_mode());
+        let mut ticks = 0;
+        while ticks < 3 * 70224 {
+            ticks += device.do_cycle();
+        }
+        assert!(!device.boot_rom_mapped());
+        assert!(!device.is_cgb_mode());
+        let pixel = &device.get_gpu_data()[..3];
+        assert!(pixel[0] > 150 && pixel[1] == 0, "{:?}", pixel);
+    }
+}
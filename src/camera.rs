@@ -0,0 +1,225 @@
+//! Images seen by the Pocket Camera sensor: a still picture loaded from a PNG or BMP file,
+//! or a generated test pattern. Either way the sensor gets 128x112 8-bit luminance.
+use crate::StrResult;
+use std::path::Path;
+
+pub const SENSOR_W: usize = 128;
+pub const SENSOR_H: usize = 112;
+
+#[derive(Clone, PartialEq, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct CameraImage {
+    // Row-major luminance, 0 = black.
+    pixels: Vec<u8>,
+}
+
+impl Default for CameraImage {
+    fn default() -> CameraImage {
+        CameraImage::test_pattern()
+    }
+}
+
+impl CameraImage {
+    /// Grey bars across the top, a checkerboard at the bottom and a ring in the middle,
+    /// so exposure, dithering and edge enhancement all have something to show.
+    pub fn test_pattern() -> CameraImage {
+        let mut pixels = Vec::with_capacity(SENSOR_W * SENSOR_H);
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let (dx, dy) = (x as i32 - 64, y as i32 - 56);
+                let r2 = dx * dx + dy * dy;
+                let v = if y < 24 {
+                    (x / 16 * 255 / 7) as u8
+                } else if y >= 96 {
+                    if (x / 8 + y / 8) % 2 == 0 { 0xFF } else { 0x00 }
+                } else if (18 * 18..28 * 28).contains(&r2) {
+                    0x20
+                } else {
+                    (0x60 + x) as u8
+                };
+                pixels.push(v);
+            }
+        }
+        CameraImage { pixels }
+    }
+
+    /// Loads a PNG or BMP file, cropped to the sensor's aspect ratio and scaled to fit.
+    pub fn load(path: &Path) -> StrResult<CameraImage> {
+        let data = std::fs::read(path).map_err(|_| "Could not read camera image")?;
+        let (width, height, luma) = if data.starts_with(b"\x89PNG") {
+            decode_png(&data)?
+        } else if data.starts_with(b"BM") {
+            decode_bmp(&data)?
+        } else {
+            return Err("Camera image must be a PNG or BMP file");
+        };
+        Ok(CameraImage::from_luma(width, height, &luma))
+    }
+
+    /// Crops `luma` (row-major, `width` x `height`) to the sensor's aspect ratio and
+    /// averages it down, or samples it up, to the sensor size.
+    pub fn from_luma(width: usize, height: usize, luma: &[u8]) -> CameraImage {
+        let (crop_w, crop_h) = if width * SENSOR_H > height * SENSOR_W {
+            (height * SENSOR_W / SENSOR_H, height)
+        } else {
+            (width, width * SENSOR_H / SENSOR_W)
+        };
+        let (x0, y0) = ((width - crop_w) / 2, (height - crop_h) / 2);
+        // Source pixels covered by sensor pixel `i` along an axis, at least one.
+        let span = |i: usize, crop: usize, size: usize| {
+            let start = i * crop / size;
+            (start, ((i + 1) * crop / size).max(start + 1))
+        };
+        let mut pixels = Vec::with_capacity(SENSOR_W * SENSOR_H);
+        for y in 0..SENSOR_H {
+            let (top, bottom) = span(y, crop_h, SENSOR_H);
+            for x in 0..SENSOR_W {
+                let (left, right) = span(x, crop_w, SENSOR_W);
+                let mut sum = 0u32;
+                for sy in top..bottom {
+                    let row = &luma[(y0 + sy) * width + x0..];
+                    sum += row[left..right].iter().map(|&v| v as u32).sum::<u32>();
+                }
+                pixels.push((sum / ((bottom - top) * (right - left)) as u32) as u8);
+            }
+        }
+        CameraImage { pixels }
+    }
+
+    /// Row-major luminance at the sensor size.
+    pub fn luma(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y.min(SENSOR_H - 1) * SENSOR_W + x.min(SENSOR_W - 1)]
+    }
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+fn decode_png(data: &[u8]) -> StrResult<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|_| "Invalid PNG file")?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|_| "Invalid PNG file")?;
+    let channels = info.color_type.samples();
+    let luma = buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|p| match p.len() {
+            1 | 2 => p[0],
+            _ => luma(p[0], p[1], p[2]),
+        })
+        .collect();
+    Ok((info.width as usize, info.height as usize, luma))
+}
+
+// Uncompressed 8-bit palettized, 24-bit and 32-bit bitmaps.
+fn decode_bmp(data: &[u8]) -> StrResult<(usize, usize, Vec<u8>)> {
+    const INVALID: &str = "Invalid or unsupported BMP file";
+    let u16_at = |i: usize| data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |i: usize| data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let offset = u32_at(10).ok_or(INVALID)? as usize;
+    let header_size = u32_at(14).ok_or(INVALID)? as usize;
+    let width = u32_at(18).ok_or(INVALID)? as i32;
+    let height = u32_at(22).ok_or(INVALID)? as i32;
+    let bpp = u16_at(28).ok_or(INVALID)? as usize;
+    let compression = u32_at(30).ok_or(INVALID)?;
+    // Only uncompressed pixels; BI_BITFIELDS (3) would need its channel masks honoured.
+    if width <= 0 || height == 0 || compression != 0 || !matches!(bpp, 8 | 24 | 32) {
+        return Err(INVALID);
+    }
+    let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+    let palette = &data[(14 + header_size).min(data.len())..offset.min(data.len())];
+    let line_len = width.checked_mul(bpp / 8).ok_or(INVALID)?;
+    let stride = line_len.div_ceil(4) * 4;
+    // Check the pixel data is all there before allocating for it; the last row may be unpadded.
+    let end = stride
+        .checked_mul(rows - 1)
+        .and_then(|n| n.checked_add(offset))
+        .and_then(|n| n.checked_add(line_len))
+        .ok_or(INVALID)?;
+    if end > data.len() {
+        return Err(INVALID);
+    }
+    let mut luma_rows = vec![0; width * rows];
+    for row in 0..rows {
+        let start = offset + row * stride;
+        let line = &data[start..start + line_len];
+        // Bottom-up unless the height is negative.
+        let y = if height > 0 { rows - 1 - row } else { row };
+        for x in 0..width {
+            luma_rows[y * width + x] = match bpp {
+                8 => {
+                    let i = line[x] as usize * 4;
+                    let entry = palette.get(i..i + 3).ok_or(INVALID)?;
+                    luma(entry[2], entry[1], entry[0])
+                }
+                _ => {
+                    let p = &line[x * bpp / 8..];
+                    luma(p[2], p[1], p[0])
+                }
+            };
+        }
+    }
+    Ok((width, rows, luma_rows))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn loads_bmp_and_scales_to_sensor() {
+        // 256x224 24-bit bottom-up bitmap: black left half, white right half.
+        let (w, h) = (256usize, 224usize);
+        let stride = w * 3;
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&((54 + stride * h) as u32).to_le_bytes());
+        bmp.extend_from_slice(&[0; 4]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&(w as i32).to_le_bytes());
+        bmp.extend_from_slice(&(h as i32).to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        for _ in 0..h {
+            for x in 0..w {
+                bmp.extend_from_slice(&[if x < w / 2 { 0 } else { 0xFF }; 3]);
+            }
+        }
+        let path = std::env::temp_dir().join(format!("rust-gbe-camera-{}.bmp", std::process::id()));
+        std::fs::write(&path, &bmp).unwrap();
+        let image = CameraImage::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((image.pixel(0, 0), image.pixel(63, 111)), (0x00, 0x00));
+        assert_eq!((image.pixel(64, 0), image.pixel(127, 111)), (0xFF, 0xFF));
+    }
+
+    #[test]
+    fn rejects_truncated_and_bitfields_bmp() {
+        let header = |w: i32, h: i32, bpp: u16, compression: u32| {
+            let mut bmp = b"BM".to_vec();
+            bmp.extend_from_slice(&[0; 8]);
+            bmp.extend_from_slice(&54u32.to_le_bytes());
+            bmp.extend_from_slice(&40u32.to_le_bytes());
+            bmp.extend_from_slice(&w.to_le_bytes());
+            bmp.extend_from_slice(&h.to_le_bytes());
+            bmp.extend_from_slice(&1u16.to_le_bytes());
+            bmp.extend_from_slice(&bpp.to_le_bytes());
+            bmp.extend_from_slice(&compression.to_le_bytes());
+            bmp.extend_from_slice(&[0; 20]);
+            bmp
+        };
+        assert!(decode_bmp(&header(i32::MAX, i32::MIN, 32, 0)).is_err());
+
+        let mut bmp = header(2, 2, 32, 3);
+        bmp.extend_from_slice(&[0; 16]);
+        assert!(decode_bmp(&bmp).is_err());
+        bmp[30] = 0;
+        assert!(decode_bmp(&bmp).is_ok());
+    }
+}
@@ -1,4 +1,6 @@
+use rust_gbe::HardwareModel;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -74,9 +76,22 @@ pub struct Config {
     #[serde(default)] pub fullscreen: bool,
     #[serde(default)] pub dmg_palette_preset: DmgPalettePreset,
     #[serde(default="default_custom_palette")] pub dmg_palette_custom: [[u8; 3]; 4],
+    #[serde(default="default_rewind_seconds")] pub rewind_seconds: u32, // 0 disables rewind
+    #[serde(default)] pub dmg_boot_rom: Option<String>,
+    #[serde(default)] pub cgb_boot_rom: Option<String>,
+    #[serde(default)] pub skip_boot_rom: bool,
+    #[serde(default)] pub hardware_model: Option<String>, // model name; None runs everything as CGB
+    #[serde(default)] pub rom_hardware_models: HashMap<String, String>, // ROM path -> model name
+    #[serde(default)] pub fifo_renderer: bool,
+    #[serde(default="default_rtc_catch_up")] pub rtc_catch_up: bool, // advance cartridge clocks by the time since the last save
 }
 
 fn default_volume() -> u8 { 100 }
+fn default_rewind_seconds() -> u32 { 10 }
+fn default_rtc_catch_up() -> bool { true }
+
+/// Rewind history lengths offered in the menu, in seconds.
+pub const REWIND_CHOICES: &[u32] = &[0, 10, 30, 60, 120];
 
 impl Default for Config {
     fn default() -> Self {
@@ -90,6 +105,14 @@ impl Default for Config {
             fullscreen: false,
             dmg_palette_preset: DmgPalettePreset::default(),
             dmg_palette_custom: default_custom_palette(),
+            rewind_seconds: default_rewind_seconds(),
+            dmg_boot_rom: None,
+            cgb_boot_rom: None,
+            skip_boot_rom: false,
+            hardware_model: None,
+            rom_hardware_models: HashMap::new(),
+            fifo_renderer: false,
+            rtc_catch_up: default_rtc_catch_up(),
         }
     }
 }
@@ -101,6 +124,25 @@ impl Config {
     }
     pub fn save(&self, path: &PathBuf) { if let Ok(data) = serde_json::to_string_pretty(self) { let _ = fs::write(path, data); } }
 
+    /// Model chosen for this ROM, else the global choice, else CGB.
+    pub fn hardware_model_for(&self, rom: &Path) -> HardwareModel {
+        self.rom_hardware_model(rom)
+            .or_else(|| self.hardware_model.as_deref().and_then(HardwareModel::from_name))
+            .unwrap_or_default()
+    }
+
+    pub fn rom_hardware_model(&self, rom: &Path) -> Option<HardwareModel> {
+        self.rom_hardware_models.get(rom.to_string_lossy().as_ref()).and_then(|m| HardwareModel::from_name(m))
+    }
+
+    pub fn set_rom_hardware_model(&mut self, rom: &Path, model: Option<HardwareModel>) {
+        let key = rom.to_string_lossy().into_owned();
+        match model {
+            Some(m) => { self.rom_hardware_models.insert(key, m.name().to_string()); }
+            None => { self.rom_hardware_models.remove(&key); }
+        }
+    }
+
     /// Insert `p` at the front of recent_roms, deduplicating and truncating to 8.
     pub fn push_recent(&mut self, p: &Path) {
         let s = p.to_string_lossy().into_owned();
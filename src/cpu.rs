@@ -1,7 +1,12 @@
-use crate::mbc;
+use crate::bootrom::BootRom;
+use crate::debugger::CpuRegisters;
+use crate::gbmode::HardwareModel;
+use crate::mbc::{self, MBC};
 use crate::mmu::MMU;
 use crate::register::CpuFlag::{C, H, N, Z};
 use crate::register::Registers;
+use crate::serial::SerialLink;
+use crate::trace::Tracer;
 use crate::StrResult;
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -13,14 +18,21 @@ pub struct CPU {
     ime: bool,
     setdi: u32,
     setei: u32,
+    // M-cycles the current instruction has used so far, and how many of those the rest
+    // of the machine has been advanced by.
+    cycles: u32,
+    synced: u32,
+    gputicks: u32,
+    #[rkyv(with = rkyv::with::Skip)]
+    pub tracer: Option<Box<Tracer>>,
 }
 
 impl CPU {
     pub fn new(
         cart: mbc::Cartridge,
-        _serial_callback: Option<Box<()>>,
+        serial_link: Option<Box<dyn SerialLink>>,
     ) -> StrResult<CPU> {
-        let cpu_mmu = MMU::new(cart, None)?;
+        let cpu_mmu = MMU::new(cart, serial_link)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(CPU {
             reg: registers,
@@ -29,15 +41,19 @@ impl CPU {
             ime: true,
             setdi: 0,
             setei: 0,
+            cycles: 0,
+            synced: 0,
+            gputicks: 0,
+            tracer: None,
             mmu: cpu_mmu,
         })
     }
 
     pub fn new_cgb(
         cart: mbc::Cartridge,
-        _serial_callback: Option<Box<()>>,
+        serial_link: Option<Box<dyn SerialLink>>,
     ) -> StrResult<CPU> {
-        let cpu_mmu = MMU::new_cgb(cart, None)?;
+        let cpu_mmu = MMU::new_cgb(cart, serial_link)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(CPU {
             reg: registers,
@@ -46,13 +62,78 @@ impl CPU {
             ime: true,
             setdi: 0,
             setei: 0,
+            cycles: 0,
+            synced: 0,
+            gputicks: 0,
+            tracer: None,
             mmu: cpu_mmu,
         })
     }
 
+    /// Emulates `model`, starting at 0x0000 with `boot_rom` mapped if one is given.
+    pub fn new_with_model(
+        cart: mbc::Cartridge,
+        serial_link: Option<Box<dyn SerialLink>>,
+        model: HardwareModel,
+        boot_rom: Option<BootRom>,
+    ) -> StrResult<CPU> {
+        let cpu_mmu = MMU::new_with_model(cart, serial_link, model, boot_rom)?;
+        let (registers, ime) = if cpu_mmu.boot_rom_mapped() {
+            (Registers::power_on(), false)
+        } else {
+            (Registers::for_model(model, cpu_mmu.gbmode), true)
+        };
+        Ok(CPU {
+            reg: registers,
+            halted: false,
+            halt_bug: false,
+            ime,
+            setdi: 0,
+            setei: 0,
+            cycles: 0,
+            synced: 0,
+            gputicks: 0,
+            tracer: None,
+            mmu: cpu_mmu,
+        })
+    }
+
+    /// Runs one instruction (or interrupt dispatch), advancing the timer, PPU and APU up
+    /// to each memory access as it happens.
     pub fn do_cycle(&mut self) -> u32 {
-        let ticks = self.docycle() * 4;
-        return self.mmu.do_cycle(ticks);
+        self.cycles = 0;
+        self.synced = 0;
+        self.gputicks = 0;
+        let cycles = self.docycle();
+        self.cycles = self.cycles.max(cycles);
+        self.sync();
+        self.gputicks
+    }
+
+    // Catches the MMU up to the start of the current M-cycle.
+    fn sync(&mut self) {
+        if self.cycles > self.synced {
+            self.gputicks += self.mmu.do_cycle((self.cycles - self.synced) * 4);
+            self.synced = self.cycles;
+        }
+    }
+
+    // Each memory access takes one M-cycle and sees the machine as it is when it starts.
+    fn read(&mut self, address: u16) -> u8 {
+        self.sync();
+        self.cycles += 1;
+        self.mmu.rb(address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.sync();
+        self.cycles += 1;
+        self.mmu.wb(address, value);
+    }
+
+    // An internal M-cycle that delays the accesses after it.
+    fn idle(&mut self) {
+        self.cycles += 1;
     }
 
     fn docycle(&mut self) -> u32 {
@@ -71,7 +152,7 @@ impl CPU {
     }
 
     fn fetchbyte(&mut self) -> u8 {
-        let b = self.mmu.rb(self.reg.pc);
+        let b = self.read(self.reg.pc);
         if self.halt_bug {
             self.halt_bug = false;
         } else {
@@ -81,9 +162,10 @@ impl CPU {
     }
 
     fn fetchword(&mut self) -> u16 {
-        let w = self.mmu.rw(self.reg.pc);
-        self.reg.pc += 2;
-        w
+        let lo = self.read(self.reg.pc) as u16;
+        let hi = self.read(self.reg.pc.wrapping_add(1)) as u16;
+        self.reg.pc = self.reg.pc.wrapping_add(2);
+        (hi << 8) | lo
     }
 
     fn updateime(&mut self) {
@@ -126,6 +208,7 @@ impl CPU {
             panic!("Invalid interrupt triggered");
         }
         self.mmu.intf &= !(1 << n);
+        self.idle();
         let pc = self.reg.pc;
         self.pushstack(pc);
         self.reg.pc = 0x0040 | ((n as u16) << 3);
@@ -133,19 +216,27 @@ impl CPU {
         4
     }
 
+    // Pushes take an internal M-cycle before writing the high byte, then the low byte.
     fn pushstack(&mut self, value: u16) {
-        self.reg.sp = self.reg.sp.wrapping_sub(2);
-        self.mmu.ww(self.reg.sp, value);
+        self.idle();
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write(self.reg.sp, (value >> 8) as u8);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.write(self.reg.sp, value as u8);
     }
 
     fn popstack(&mut self) -> u16 {
-        let res = self.mmu.rw(self.reg.sp);
+        let lo = self.read(self.reg.sp) as u16;
+        let hi = self.read(self.reg.sp.wrapping_add(1)) as u16;
         self.reg.sp = self.reg.sp.wrapping_add(2);
-        res
+        (hi << 8) | lo
     }
 
     // Table based dispatcher
     fn call(&mut self) -> u32 {
+        if self.tracer.is_some() {
+            self.trace();
+        }
         let opcode = self.fetchbyte();
         OPCODE_TABLE[opcode as usize](self, opcode)
     }
@@ -311,18 +402,59 @@ impl CPU {
         self.reg.a = a;
     }
 
+    fn trace(&mut self) {
+        let regs = self.debug_registers();
+        let pcmem = std::array::from_fn(|i| self.mmu.peek(regs.pc.wrapping_add(i as u16)));
+        let bank = self.mmu.mbc.rombank(regs.pc);
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.log(&regs, bank, pcmem);
+        }
+    }
+
+    pub fn debug_registers(&self) -> CpuRegisters {
+        CpuRegisters {
+            a: self.reg.a,
+            f: self.reg.af() as u8,
+            b: self.reg.b,
+            c: self.reg.c,
+            d: self.reg.d,
+            e: self.reg.e,
+            h: self.reg.h,
+            l: self.reg.l,
+            sp: self.reg.sp,
+            pc: self.reg.pc,
+            ime: self.ime,
+            halted: self.halted,
+        }
+    }
+
+    pub fn set_debug_registers(&mut self, regs: &CpuRegisters) {
+        self.reg.setaf(((regs.a as u16) << 8) | regs.f as u16);
+        self.reg.b = regs.b;
+        self.reg.c = regs.c;
+        self.reg.d = regs.d;
+        self.reg.e = regs.e;
+        self.reg.h = regs.h;
+        self.reg.l = regs.l;
+        self.reg.sp = regs.sp;
+        self.reg.pc = regs.pc;
+        self.ime = regs.ime;
+        self.halted = regs.halted;
+    }
+
     pub fn read_byte(&mut self, address: u16) -> u8 {
-        self.mmu.rb(address)
+        self.mmu.peek(address)
     }
     pub fn write_byte(&mut self, address: u16, byte: u8) {
-        self.mmu.wb(address, byte)
+        self.mmu.poke(address, byte)
     }
 
     pub fn read_wide(&mut self, address: u16) -> u16 {
-        self.mmu.rw(address)
+        (self.mmu.peek(address) as u16) | ((self.mmu.peek(address.wrapping_add(1)) as u16) << 8)
     }
     pub fn write_wide(&mut self, address: u16, wide: u16) {
-        self.mmu.ww(address, wide)
+        self.mmu.poke(address, (wide & 0xFF) as u8);
+        self.mmu.poke(address.wrapping_add(1), (wide >> 8) as u8);
     }
 }
 
@@ -363,22 +495,22 @@ fn op_rot_a(cpu: &mut CPU, op: u8) -> u32 {
 // LD r,r' (and LD r,(HL)) group 0x40-0x7F excluding 0x76 HALT already mapped.
 fn op_ld_r_r(cpu: &mut CPU, op: u8) -> u32 {
     let hl = cpu.reg.hl();
-    let read_hl = |cpu: &mut CPU| cpu.mmu.rb(hl);
+    let read_hl = |cpu: &mut CPU| cpu.read(hl);
     // decode dest/source indices: bits 3-5 dest, 0-2 src (A=7,B=0,C=1,D=2,E=3,H=4,L=5,(HL)=6)
     let dest = (op >> 3) & 0x07; let src = op & 0x07;
     let val = match src { 0 => cpu.reg.b, 1 => cpu.reg.c, 2 => cpu.reg.d, 3 => cpu.reg.e, 4 => cpu.reg.h, 5 => cpu.reg.l, 6 => read_hl(cpu), 7 => cpu.reg.a, _ => unreachable!() };
     match dest { 0 => cpu.reg.b = val, 1 => cpu.reg.c = val, 2 => cpu.reg.d = val, 3 => cpu.reg.e = val, 4 => cpu.reg.h = val, 5 => cpu.reg.l = val, 6 => { // LD (HL),r
-            cpu.mmu.wb(hl, val);
+            cpu.write(hl, val);
         }, 7 => cpu.reg.a = val, _ => unreachable!() };
 
     if dest == 6 || src == 6 { 2 } else { 1 }
 }
 
 // LD r,d8 already handled for specific opcodes
-fn op_ld_hl_d8(cpu: &mut CPU, _op: u8) -> u32 { let v = cpu.fetchbyte(); let hl = cpu.reg.hl(); cpu.mmu.wb(hl, v); 3 }
+fn op_ld_hl_d8(cpu: &mut CPU, _op: u8) -> u32 { let v = cpu.fetchbyte(); let hl = cpu.reg.hl(); cpu.write(hl, v); 3 }
 
 // ALU helpers mapping register code to value (including (HL))
-fn read_reg_by_code(cpu: &mut CPU, code: u8) -> u8 { match code { 0 => cpu.reg.b, 1 => cpu.reg.c, 2 => cpu.reg.d, 3 => cpu.reg.e, 4 => cpu.reg.h, 5 => cpu.reg.l, 6 => cpu.mmu.rb(cpu.reg.hl()), 7 => cpu.reg.a, _ => unreachable!() } }
+fn read_reg_by_code(cpu: &mut CPU, code: u8) -> u8 { match code { 0 => cpu.reg.b, 1 => cpu.reg.c, 2 => cpu.reg.d, 3 => cpu.reg.e, 4 => cpu.reg.h, 5 => cpu.reg.l, 6 => cpu.read(cpu.reg.hl()), 7 => cpu.reg.a, _ => unreachable!() } }
 
 // ADD/ADC/SUB/SBC/AND/XOR/OR/CP r (0x80-0xBF)
 fn op_alu_r(cpu: &mut CPU, op: u8) -> u32 {
@@ -471,17 +603,17 @@ fn op_pop_rr(cpu: &mut CPU, op: u8) -> u32 {
 }
 
 // 0x08 LD (nn),SP and 0xFA LD A,(nn) and 0xEA LD (nn),A handled separately
-fn op_store_nn_sp(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.mmu.ww(a, cpu.reg.sp); 5 }
-fn op_ld_a_nn(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.reg.a = cpu.mmu.rb(a); 4 }
-fn op_ld_nn_a(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.mmu.wb(a, cpu.reg.a); 4 }
+fn op_store_nn_sp(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.write(a, cpu.reg.sp as u8); cpu.write(a.wrapping_add(1), (cpu.reg.sp >> 8) as u8); 5 }
+fn op_ld_a_nn(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.reg.a = cpu.read(a); 4 }
+fn op_ld_nn_a(cpu: &mut CPU, _op:u8) -> u32 { let a = cpu.fetchword(); cpu.write(a, cpu.reg.a); 4 }
 
 // LDH (0xE0,0xF0,0xE2,0xF2)
 fn op_ldh(cpu: &mut CPU, op: u8) -> u32 {
     match op {
-        0xE0 => { let off = cpu.fetchbyte() as u16; cpu.mmu.wb(0xFF00 + off, cpu.reg.a); 3 },
-        0xF0 => { let off = cpu.fetchbyte() as u16; cpu.reg.a = cpu.mmu.rb(0xFF00 + off); 3 },
-        0xE2 => { cpu.mmu.wb(0xFF00 + cpu.reg.c as u16, cpu.reg.a); 2 },
-        0xF2 => { cpu.reg.a = cpu.mmu.rb(0xFF00 + cpu.reg.c as u16); 2 },
+        0xE0 => { let off = cpu.fetchbyte() as u16; cpu.write(0xFF00 + off, cpu.reg.a); 3 },
+        0xF0 => { let off = cpu.fetchbyte() as u16; cpu.reg.a = cpu.read(0xFF00 + off); 3 },
+        0xE2 => { cpu.write(0xFF00 + cpu.reg.c as u16, cpu.reg.a); 2 },
+        0xF2 => { cpu.reg.a = cpu.read(0xFF00 + cpu.reg.c as u16); 2 },
         _ => unreachable!(),
     }
 }
@@ -521,6 +653,7 @@ fn op_ret(cpu: &mut CPU, op: u8) -> u32 {
     0xD9 => { let v = cpu.popstack(); cpu.reg.pc = v; cpu.setei = 1; 4 },
         0xC0 | 0xC8 | 0xD0 | 0xD8 => {
             let cond = match op { 0xC0 => !cpu.reg.getflag(Z), 0xC8 => cpu.reg.getflag(Z), 0xD0 => !cpu.reg.getflag(C), 0xD8 => cpu.reg.getflag(C), _ => unreachable!() };
+            cpu.idle();
         if cond { let v = cpu.popstack(); cpu.reg.pc = v; 5 } else { 2 }
         }
         _ => unreachable!(),
@@ -528,25 +661,25 @@ fn op_ret(cpu: &mut CPU, op: u8) -> u32 {
 }
 
 // RST t (0xC7,CF,D7,DF,E7,EF,F7,FF)
-fn op_rst(cpu: &mut CPU, op: u8) -> u32 { let target = match op { 0xC7 => 0x00, 0xCF => 0x08, 0xD7 => 0x10, 0xDF => 0x18, 0xE7 => 0x20, 0xEF => 0x28, 0xF7 => 0x30, 0xFF => 0x38, _ => unreachable!() }; let pc = cpu.reg.pc; cpu.reg.sp = cpu.reg.sp.wrapping_sub(1); cpu.mmu.wb(cpu.reg.sp, (pc >> 8) as u8); cpu.reg.sp = cpu.reg.sp.wrapping_sub(1); cpu.mmu.wb(cpu.reg.sp, pc as u8); cpu.reg.pc = target; 4 }
+fn op_rst(cpu: &mut CPU, op: u8) -> u32 { let target = match op { 0xC7 => 0x00, 0xCF => 0x08, 0xD7 => 0x10, 0xDF => 0x18, 0xE7 => 0x20, 0xEF => 0x28, 0xF7 => 0x30, 0xFF => 0x38, _ => unreachable!() }; let pc = cpu.reg.pc; cpu.pushstack(pc); cpu.reg.pc = target; 4 }
 
 // Misc single opcodes: DAA(0x27), CPL(0x2F), SCF(0x37), CCF(0x3F), DI(0xF3), EI(0xFB), STOP(0x10)
 fn op_misc(cpu: &mut CPU, op: u8) -> u32 { match op { 0x27 => { cpu.alu_daa(); 1 }, 0x2F => { cpu.reg.a = !cpu.reg.a; cpu.reg.flag(H,true); cpu.reg.flag(N,true); 1 }, 0x37 => { cpu.reg.flag(C,true); cpu.reg.flag(H,false); cpu.reg.flag(N,false); 1 }, 0x3F => { let c = cpu.reg.getflag(C); cpu.reg.flag(C,!c); cpu.reg.flag(H,false); cpu.reg.flag(N,false); 1 }, 0xF3 => { cpu.ime = false; 1 }, 0xFB => { cpu.setei = 2; 1 }, 0x10 => { cpu.mmu.switch_speed(); 1 }, _ => unreachable!() } }
 
 // Simple LD A,(rr) and LD (rr),A for BC/DE plus HL +/- (0x0A,0x1A,0x02,0x12,0x22,0x2A,0x32,0x3A)
-fn op_ld_a_rr_ind(cpu: &mut CPU, op:u8) -> u32 { match op { 0x0A => cpu.reg.a = cpu.mmu.rb(cpu.reg.bc()), 0x1A => cpu.reg.a = cpu.mmu.rb(cpu.reg.de()), _=> unreachable!()}; 2 }
-fn op_ld_rr_ind_a(cpu: &mut CPU, op:u8) -> u32 { match op { 0x02 => cpu.mmu.wb(cpu.reg.bc(), cpu.reg.a), 0x12 => cpu.mmu.wb(cpu.reg.de(), cpu.reg.a), _=> unreachable!()}; 2 }
-fn op_ld_hl_incdec_a(cpu:&mut CPU, op:u8) -> u32 { match op { 0x22 => { cpu.mmu.wb(cpu.reg.hli(), cpu.reg.a); }, 0x2A => { cpu.reg.a = cpu.mmu.rb(cpu.reg.hli()); }, 0x32 => { cpu.mmu.wb(cpu.reg.hld(), cpu.reg.a); }, 0x3A => { cpu.reg.a = cpu.mmu.rb(cpu.reg.hld()); }, _=> unreachable!()}; 2 }
+fn op_ld_a_rr_ind(cpu: &mut CPU, op:u8) -> u32 { match op { 0x0A => cpu.reg.a = cpu.read(cpu.reg.bc()), 0x1A => cpu.reg.a = cpu.read(cpu.reg.de()), _=> unreachable!()}; 2 }
+fn op_ld_rr_ind_a(cpu: &mut CPU, op:u8) -> u32 { match op { 0x02 => cpu.write(cpu.reg.bc(), cpu.reg.a), 0x12 => cpu.write(cpu.reg.de(), cpu.reg.a), _=> unreachable!()}; 2 }
+fn op_ld_hl_incdec_a(cpu:&mut CPU, op:u8) -> u32 { match op { 0x22 => { let a = cpu.reg.hli(); cpu.write(a, cpu.reg.a); }, 0x2A => { let a = cpu.reg.hli(); cpu.reg.a = cpu.read(a); }, 0x32 => { let a = cpu.reg.hld(); cpu.write(a, cpu.reg.a); }, 0x3A => { let a = cpu.reg.hld(); cpu.reg.a = cpu.read(a); }, _=> unreachable!()}; 2 }
 
 // INC (HL) 0x34 , DEC (HL) 0x35
-fn op_incdec_hl_mem(cpu:&mut CPU, op:u8) -> u32 { let addr = cpu.reg.hl(); let v = cpu.mmu.rb(addr); let v2 = if op==0x34 { cpu.alu_inc(v) } else { cpu.alu_dec(v) }; cpu.mmu.wb(addr, v2); 3 }
+fn op_incdec_hl_mem(cpu:&mut CPU, op:u8) -> u32 { let addr = cpu.reg.hl(); let v = cpu.read(addr); let v2 = if op==0x34 { cpu.alu_inc(v) } else { cpu.alu_dec(v) }; cpu.write(addr, v2); 3 }
 
 // CB prefix table
 type CbHandler = fn(&mut CPU, u8) -> u32;
-fn cb_rot(cpu:&mut CPU, op:u8)->u32 { let target = op & 0x07; let group = op >> 3; let mut v = match target {0=>cpu.reg.b,1=>cpu.reg.c,2=>cpu.reg.d,3=>cpu.reg.e,4=>cpu.reg.h,5=>cpu.reg.l,6=>cpu.mmu.rb(cpu.reg.hl()),7=>cpu.reg.a,_=>unreachable!()}; v = match group {0=>cpu.alu_rlc(v),1=>cpu.alu_rrc(v),2=>cpu.alu_rl(v),3=>cpu.alu_rr(v),4=>cpu.alu_sla(v),5=>cpu.alu_sra(v),6=>cpu.alu_swap(v),7=>cpu.alu_srl(v),_=>unreachable!()}; if target==6 { cpu.mmu.wb(cpu.reg.hl(), v); 4 } else { match target {0=>cpu.reg.b=v,1=>cpu.reg.c=v,2=>cpu.reg.d=v,3=>cpu.reg.e=v,4=>cpu.reg.h=v,5=>cpu.reg.l=v,7=>cpu.reg.a=v,_=>{} }; 2 } }
-fn cb_bit(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let target = op & 0x07; let v = if target==6 { cpu.mmu.rb(cpu.reg.hl()) } else { match target {0=>cpu.reg.b,1=>cpu.reg.c,2=>cpu.reg.d,3=>cpu.reg.e,4=>cpu.reg.h,5=>cpu.reg.l,7=>cpu.reg.a,_=>unreachable!()} }; cpu.alu_bit(v, bit); if target==6 {3} else {2} }
-fn cb_res(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let mask = !(1<<bit); let target = op & 0x07; if target==6 { let addr=cpu.reg.hl(); let mut v=cpu.mmu.rb(addr); v &= mask; cpu.mmu.wb(addr,v); 4 } else { let reg = match target {0=>&mut cpu.reg.b,1=>&mut cpu.reg.c,2=>&mut cpu.reg.d,3=>&mut cpu.reg.e,4=>&mut cpu.reg.h,5=>&mut cpu.reg.l,7=>&mut cpu.reg.a,_=>unreachable!()}; *reg &= mask; 2 } }
-fn cb_set(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let mask = 1<<bit; let target = op & 0x07; if target==6 { let addr=cpu.reg.hl(); let mut v=cpu.mmu.rb(addr); v |= mask; cpu.mmu.wb(addr,v); 4 } else { let reg = match target {0=>&mut cpu.reg.b,1=>&mut cpu.reg.c,2=>&mut cpu.reg.d,3=>&mut cpu.reg.e,4=>&mut cpu.reg.h,5=>&mut cpu.reg.l,7=>&mut cpu.reg.a,_=>unreachable!()}; *reg |= mask; 2 } }
+fn cb_rot(cpu:&mut CPU, op:u8)->u32 { let target = op & 0x07; let group = op >> 3; let mut v = match target {0=>cpu.reg.b,1=>cpu.reg.c,2=>cpu.reg.d,3=>cpu.reg.e,4=>cpu.reg.h,5=>cpu.reg.l,6=>cpu.read(cpu.reg.hl()),7=>cpu.reg.a,_=>unreachable!()}; v = match group {0=>cpu.alu_rlc(v),1=>cpu.alu_rrc(v),2=>cpu.alu_rl(v),3=>cpu.alu_rr(v),4=>cpu.alu_sla(v),5=>cpu.alu_sra(v),6=>cpu.alu_swap(v),7=>cpu.alu_srl(v),_=>unreachable!()}; if target==6 { cpu.write(cpu.reg.hl(), v); 4 } else { match target {0=>cpu.reg.b=v,1=>cpu.reg.c=v,2=>cpu.reg.d=v,3=>cpu.reg.e=v,4=>cpu.reg.h=v,5=>cpu.reg.l=v,7=>cpu.reg.a=v,_=>{} }; 2 } }
+fn cb_bit(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let target = op & 0x07; let v = if target==6 { cpu.read(cpu.reg.hl()) } else { match target {0=>cpu.reg.b,1=>cpu.reg.c,2=>cpu.reg.d,3=>cpu.reg.e,4=>cpu.reg.h,5=>cpu.reg.l,7=>cpu.reg.a,_=>unreachable!()} }; cpu.alu_bit(v, bit); if target==6 {3} else {2} }
+fn cb_res(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let mask = !(1<<bit); let target = op & 0x07; if target==6 { let addr=cpu.reg.hl(); let mut v=cpu.read(addr); v &= mask; cpu.write(addr,v); 4 } else { let reg = match target {0=>&mut cpu.reg.b,1=>&mut cpu.reg.c,2=>&mut cpu.reg.d,3=>&mut cpu.reg.e,4=>&mut cpu.reg.h,5=>&mut cpu.reg.l,7=>&mut cpu.reg.a,_=>unreachable!()}; *reg &= mask; 2 } }
+fn cb_set(cpu:&mut CPU, op:u8)->u32 { let bit = (op>>3)&0x07; let mask = 1<<bit; let target = op & 0x07; if target==6 { let addr=cpu.reg.hl(); let mut v=cpu.read(addr); v |= mask; cpu.write(addr,v); 4 } else { let reg = match target {0=>&mut cpu.reg.b,1=>&mut cpu.reg.c,2=>&mut cpu.reg.d,3=>&mut cpu.reg.e,4=>&mut cpu.reg.h,5=>&mut cpu.reg.l,7=>&mut cpu.reg.a,_=>unreachable!()}; *reg |= mask; 2 } }
 static CB_TABLE: [CbHandler;256] = { let mut t:[CbHandler;256] = [cb_rot;256]; let mut i=0; while i<256 { t[i]= if i<0x40 { cb_rot } else if i<0x80 { cb_bit } else if i<0xC0 { cb_res } else { cb_set }; i+=1;} t };
 fn op_cb(cpu:&mut CPU,_:u8)->u32 { let opc = cpu.fetchbyte(); CB_TABLE[opc as usize](cpu, opc) }
 
@@ -729,4 +862,21 @@ mod test {
             "Color mode instruction timing test failed"
         );
     }
+
+    #[test]
+    fn memory_access_mid_instruction() {
+        let mut rom = vec![0; 0x8000];
+        let code = [
+            0x3E, 0x05, // LD A,$05
+            0xE0, 0x07, // LDH (TAC),A: TIMA counts every 4 M-cycles from the write
+            0xFA, 0x05, 0xFF, // LD A,(TIMA): read in the 4th M-cycle
+        ];
+        rom[0x100..0x100 + code.len()].copy_from_slice(&code);
+        let cart = mbc::Cartridge::from_buffer(rom, true).unwrap();
+        let mut c = CPU::new(cart, None).unwrap();
+        assert_eq!(c.do_cycle() + c.do_cycle(), 5 * 4);
+        // 1 M-cycle after the write in LDH, plus 3 before the read.
+        assert_eq!(c.do_cycle(), 4 * 4);
+        assert_eq!(c.reg.a, 1);
+    }
 }
@@ -0,0 +1,368 @@
+//! Breakpoints, watchpoints and stepping. Driven through the `Device` debugger methods.
+use crate::cpu::CPU;
+use crate::disasm::SymbolTable;
+use crate::mbc::MBC;
+use crate::StrResult;
+pub use crate::register::CpuFlag;
+
+/// Stop before executing the instruction at `address`. With `bank` set, only when that
+/// ROM bank is mapped there (addresses outside 0x0000-0x7FFF ignore the bank).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u16,
+    pub bank: Option<usize>,
+}
+
+impl std::str::FromStr for Breakpoint {
+    type Err = &'static str;
+
+    /// Parses `ADDR` or `BANK:ADDR`, both in hex (e.g. `0150` or `03:4A2F`).
+    fn from_str(s: &str) -> Result<Breakpoint, &'static str> {
+        let (bank, address) = match s.trim().split_once(':') {
+            Some((bank, address)) => (Some(bank), address),
+            None => (None, s.trim()),
+        };
+        let address = parse_hex(address).ok_or("Invalid breakpoint address")?;
+        let bank = match bank {
+            Some(b) => Some(usize::from_str_radix(b.trim(), 16).map_err(|_| "Invalid ROM bank")?),
+            None => None,
+        };
+        Ok(Breakpoint { address, bank })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Stop after an instruction reads or writes any address in `start..=end`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    /// Parses `ADDR` or `START-END` in hex.
+    pub fn parse(s: &str, kind: WatchKind) -> StrResult<Watchpoint> {
+        let (start, end) = s.trim().split_once('-').unwrap_or((s.trim(), s.trim()));
+        match (parse_hex(start), parse_hex(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(Watchpoint { start, end, kind }),
+            _ => Err("Invalid watchpoint range"),
+        }
+    }
+
+    pub fn matches(&self, address: u16, write: bool) -> bool {
+        let kind = matches!(
+            (self.kind, write),
+            (WatchKind::ReadWrite, _) | (WatchKind::Read, false) | (WatchKind::Write, true)
+        );
+        kind && (self.start..=self.end).contains(&address)
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    u16::from_str_radix(s, 16).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// Requested with `Device::debug_break`.
+    Break,
+    Breakpoint { address: u16, bank: usize },
+    Watchpoint { address: u16, value: u8, write: bool },
+    /// A step, step over or step out finished.
+    Step,
+}
+
+/// CPU registers and interrupt state as seen by the debugger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuRegisters {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub halted: bool,
+}
+
+impl CpuRegisters {
+    pub fn flag(&self, flag: CpuFlag) -> bool {
+        self.f & flag as u8 != 0
+    }
+
+    pub fn set_flag(&mut self, flag: CpuFlag, set: bool) {
+        if set {
+            self.f |= flag as u8;
+        } else {
+            self.f &= !(flag as u8);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum StepMode {
+    Run,
+    Into,
+    Over { return_pc: u16, sp: u16 },
+    Out { sp: u16 },
+}
+
+pub(crate) struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    step: StepMode,
+    stopped: Option<StopReason>,
+    // Breakpoint address to ignore once, so resuming from a breakpoint makes progress.
+    resume_pc: Option<u16>,
+    pub symbols: SymbolTable,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            step: StepMode::Run,
+            stopped: None,
+            resume_pc: None,
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) {
+        if !self.breakpoints.contains(&bp) {
+            self.breakpoints.push(bp);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, bp: Breakpoint) {
+        self.breakpoints.retain(|b| *b != bp);
+    }
+
+    pub fn stopped(&self) -> Option<StopReason> {
+        self.stopped
+    }
+
+    pub fn stop(&mut self, reason: StopReason) {
+        self.step = StepMode::Run;
+        self.stopped = Some(reason);
+    }
+
+    pub fn resume(&mut self, cpu: &CPU) {
+        self.start(StepMode::Run, cpu);
+    }
+
+    pub fn step_into(&mut self, cpu: &CPU) {
+        self.start(StepMode::Into, cpu);
+    }
+
+    /// Like `step_into`, but runs a CALL or RST until it returns.
+    pub fn step_over(&mut self, cpu: &mut CPU) {
+        let regs = cpu.debug_registers();
+        let length = match cpu.mmu.peek(regs.pc) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => 1,
+            _ => 0,
+        };
+        let mode = match length {
+            0 => StepMode::Into,
+            n => StepMode::Over {
+                return_pc: regs.pc.wrapping_add(n),
+                sp: regs.sp,
+            },
+        };
+        self.start(mode, cpu);
+    }
+
+    /// Runs until the current function returns to its caller.
+    pub fn step_out(&mut self, cpu: &CPU) {
+        let sp = cpu.debug_registers().sp;
+        self.start(StepMode::Out { sp }, cpu);
+    }
+
+    fn start(&mut self, mode: StepMode, cpu: &CPU) {
+        self.step = mode;
+        self.stopped = None;
+        self.resume_pc = Some(cpu.debug_registers().pc);
+    }
+
+    /// True when `execute` has to be used instead of running the CPU directly.
+    pub fn is_active(&self, cpu: &CPU) -> bool {
+        self.stopped.is_some()
+            || self.step != StepMode::Run
+            || !self.breakpoints.is_empty()
+            || !cpu.mmu.watchpoints.is_empty()
+    }
+
+    /// Runs one CPU step unless a breakpoint is hit first. Returns the ticks taken,
+    /// 0 while stopped.
+    pub fn execute(&mut self, cpu: &mut CPU) -> u32 {
+        if self.stopped.is_some() {
+            return 0;
+        }
+
+        let before = cpu.debug_registers();
+        if self.resume_pc.take() != Some(before.pc) {
+            let bank = if before.pc < 0x8000 { cpu.mmu.mbc.rombank(before.pc) } else { 0 };
+            let hit = self.breakpoints.iter().any(|bp| {
+                bp.address == before.pc
+                    && (before.pc >= 0x8000 || bp.bank.is_none_or(|b| b == bank))
+            });
+            if hit {
+                self.stop(StopReason::Breakpoint {
+                    address: before.pc,
+                    bank,
+                });
+                return 0;
+            }
+        }
+        let opcode = cpu.mmu.peek(before.pc);
+
+        let ticks = cpu.do_cycle();
+
+        if let Some(hit) = cpu.mmu.watch_hit.take() {
+            self.stop(hit);
+            return ticks;
+        }
+        let after = cpu.debug_registers();
+        let done = match self.step {
+            StepMode::Run => false,
+            StepMode::Into => true,
+            StepMode::Over { return_pc, sp } => after.pc == return_pc && after.sp >= sp,
+            StepMode::Out { sp } => {
+                matches!(opcode, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9) && after.sp > sp
+            }
+        };
+        if done {
+            self.stop(StopReason::Step);
+        }
+        ticks
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+
+    fn test_device() -> Device {
+        let mut rom = vec![0; 0x8000];
+        let program: &[(usize, &[u8])] = &[
+            (0x100, &[0x00]),             // NOP
+            (0x101, &[0xCD, 0x00, 0x02]), // CALL $0200
+            (0x104, &[0x3E, 0x42]),       // LD A,$42
+            (0x106, &[0xEA, 0x00, 0xC0]), // LD ($C000),A
+            (0x109, &[0x18, 0xFE]),       // JR -2
+            (0x200, &[0x04]),             // INC B
+            (0x201, &[0xC9]),             // RET
+        ];
+        for (addr, bytes) in program {
+            rom[*addr..*addr + bytes.len()].copy_from_slice(bytes);
+        }
+        Device::new_from_buffer(rom, true, None).unwrap()
+    }
+
+    #[test]
+    fn breakpoint_stops_before_instruction() {
+        let mut device = test_device();
+        let b = device.registers().b;
+        device.add_breakpoint(Breakpoint { address: 0x104, bank: None });
+
+        let reason = device.run_until_stop(10_000);
+        assert_eq!(reason, Some(StopReason::Breakpoint { address: 0x104, bank: 0 }));
+        assert_eq!(device.registers().pc, 0x104);
+        assert_eq!(device.registers().b, b.wrapping_add(1));
+        assert_eq!(device.do_cycle(), 0);
+
+        // Continuing must step past the breakpoint it stopped on.
+        device.debug_continue();
+        assert_eq!(device.run_until_stop(10_000), None);
+    }
+
+    #[test]
+    fn breakpoint_in_other_bank_is_ignored() {
+        let mut device = test_device();
+        device.add_breakpoint(Breakpoint { address: 0x104, bank: Some(1) });
+        assert_eq!(device.run_until_stop(10_000), None);
+    }
+
+    #[test]
+    fn step_over_and_step_out() {
+        let mut device = test_device();
+        device.debug_step();
+        assert_eq!(device.run_until_stop(10_000), Some(StopReason::Step));
+        assert_eq!(device.registers().pc, 0x101);
+
+        device.debug_step_over();
+        device.run_until_stop(10_000);
+        assert_eq!(device.registers().pc, 0x104);
+
+        let mut device = test_device();
+        device.add_breakpoint(Breakpoint { address: 0x200, bank: None });
+        device.run_until_stop(10_000);
+        device.debug_step_out();
+        assert_eq!(device.run_until_stop(10_000), Some(StopReason::Step));
+        assert_eq!(device.registers().pc, 0x104);
+    }
+
+    #[test]
+    fn write_watchpoint_reports_value() {
+        let mut device = test_device();
+        device.add_watchpoint(Watchpoint { start: 0xC000, end: 0xC0FF, kind: WatchKind::Write });
+
+        let reason = device.run_until_stop(10_000);
+        assert_eq!(
+            reason,
+            Some(StopReason::Watchpoint { address: 0xC000, value: 0x42, write: true })
+        );
+        assert_eq!(device.registers().pc, 0x109);
+    }
+
+    #[test]
+    fn parse_breakpoints_and_watchpoints() {
+        assert_eq!("0150".parse(), Ok(Breakpoint { address: 0x150, bank: None }));
+        assert_eq!("1F:$4A2F".parse(), Ok(Breakpoint { address: 0x4A2F, bank: Some(0x1F) }));
+        assert!("zz".parse::<Breakpoint>().is_err());
+        assert_eq!(
+            Watchpoint::parse("C000-C0FF", WatchKind::Read),
+            Ok(Watchpoint { start: 0xC000, end: 0xC0FF, kind: WatchKind::Read })
+        );
+        assert!(Watchpoint::parse("C0FF-C000", WatchKind::Read).is_err());
+    }
+
+    #[test]
+    fn registers_can_be_modified() {
+        let mut device = test_device();
+        let mut regs = device.registers();
+        regs.a = 0x99;
+        regs.set_flag(CpuFlag::Z, false);
+        regs.set_flag(CpuFlag::C, true);
+        regs.pc = 0x104;
+        device.set_registers(&regs);
+
+        let regs = device.registers();
+        assert_eq!((regs.a, regs.pc), (0x99, 0x104));
+        assert!(!regs.flag(CpuFlag::Z) && regs.flag(CpuFlag::C));
+    }
+}
@@ -0,0 +1,238 @@
+//! Debugger window: shows the state reported by the emulator thread and sends it commands.
+use std::sync::mpsc::Sender;
+
+use rust_gbe::debugger::{Breakpoint, CpuFlag, StopReason, WatchKind, Watchpoint};
+
+use crate::emulator::{DebugCommand, DebugSnapshot, GBEvent};
+
+pub struct DebuggerWindow {
+    pub open: bool,
+    snapshot: Option<Box<DebugSnapshot>>,
+    breakpoint_input: String,
+    watchpoint_input: String,
+    watch_kind: WatchKind,
+    error: Option<&'static str>,
+}
+
+impl DebuggerWindow {
+    pub fn new() -> Self {
+        DebuggerWindow {
+            open: false,
+            snapshot: None,
+            breakpoint_input: String::new(),
+            watchpoint_input: String::new(),
+            watch_kind: WatchKind::Write,
+            error: None,
+        }
+    }
+
+    pub fn update(&mut self, snapshot: DebugSnapshot) {
+        // Execution stopping on its own (breakpoint, watchpoint) brings the window up.
+        if matches!(snapshot.stop, Some(reason) if reason != StopReason::Break) {
+            self.open = true;
+        }
+        self.snapshot = Some(Box::new(snapshot));
+    }
+
+    pub fn open(&mut self, sender: &Sender<GBEvent>) {
+        self.open = true;
+        let _ = sender.send(GBEvent::Debug(DebugCommand::Refresh));
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, sender: &Sender<GBEvent>) {
+        if !self.open {
+            return;
+        }
+        let send = |cmd| {
+            let _ = sender.send(GBEvent::Debug(cmd));
+        };
+        let mut open = self.open;
+        egui::Window::new("Debugger").open(&mut open).show(ctx, |ui| {
+            let stop = self.snapshot.as_ref().and_then(|s| s.stop);
+            ui.horizontal(|ui| {
+                if stop.is_some() {
+                    if ui.button("Continue").clicked() {
+                        send(DebugCommand::Continue);
+                    }
+                } else if ui.button("Break").clicked() {
+                    send(DebugCommand::Break);
+                }
+                ui.add_enabled_ui(stop.is_some(), |ui| {
+                    if ui.button("Step").clicked() {
+                        send(DebugCommand::Step);
+                    }
+                    if ui.button("Step Over").clicked() {
+                        send(DebugCommand::StepOver);
+                    }
+                    if ui.button("Step Out").clicked() {
+                        send(DebugCommand::StepOut);
+                    }
+                });
+            });
+            ui.label(match stop {
+                None => "Running".to_string(),
+                Some(StopReason::Break) => "Stopped".to_string(),
+                Some(StopReason::Step) => "Stopped after step".to_string(),
+                Some(StopReason::Breakpoint { address, bank }) => {
+                    format!("Breakpoint at {:02X}:{:04X}", bank, address)
+                }
+                Some(StopReason::Watchpoint { address, value, write }) => format!(
+                    "Watchpoint: {} {:02X} at {:04X}",
+                    if write { "wrote" } else { "read" },
+                    value,
+                    address
+                ),
+            });
+            ui.separator();
+
+            if let Some(snapshot) = self.snapshot.as_mut() {
+                show_registers(ui, snapshot, stop.is_some(), &send);
+                ui.separator();
+                show_disassembly(ui, snapshot);
+                ui.separator();
+            }
+
+            ui.label("Breakpoints (ADDR or BANK:ADDR, hex)");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.breakpoint_input);
+                if ui.button("Add").clicked() {
+                    match self.breakpoint_input.parse::<Breakpoint>() {
+                        Ok(bp) => {
+                            send(DebugCommand::AddBreakpoint(bp));
+                            self.breakpoint_input.clear();
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            });
+            for bp in self.snapshot.iter().flat_map(|s| s.breakpoints.iter()) {
+                ui.horizontal(|ui| {
+                    ui.monospace(match bp.bank {
+                        Some(bank) => format!("{:02X}:{:04X}", bank, bp.address),
+                        None => format!("{:04X}", bp.address),
+                    });
+                    if ui.small_button("Remove").clicked() {
+                        send(DebugCommand::RemoveBreakpoint(*bp));
+                    }
+                });
+            }
+            ui.separator();
+
+            ui.label("Watchpoints (ADDR or START-END, hex)");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.watchpoint_input);
+                egui::ComboBox::from_id_salt("watch_kind")
+                    .selected_text(watch_kind_label(self.watch_kind))
+                    .show_ui(ui, |ui| {
+                        for kind in [WatchKind::Read, WatchKind::Write, WatchKind::ReadWrite] {
+                            ui.selectable_value(&mut self.watch_kind, kind, watch_kind_label(kind));
+                        }
+                    });
+                if ui.button("Add").clicked() {
+                    match Watchpoint::parse(&self.watchpoint_input, self.watch_kind) {
+                        Ok(wp) => {
+                            send(DebugCommand::AddWatchpoint(wp));
+                            self.watchpoint_input.clear();
+                            self.error = None;
+                        }
+                        Err(e) => self.error = Some(e),
+                    }
+                }
+            });
+            for wp in self.snapshot.iter().flat_map(|s| s.watchpoints.iter()) {
+                ui.horizontal(|ui| {
+                    ui.monospace(format!(
+                        "{:04X}-{:04X} {}",
+                        wp.start,
+                        wp.end,
+                        watch_kind_label(wp.kind)
+                    ));
+                    if ui.small_button("Remove").clicked() {
+                        send(DebugCommand::RemoveWatchpoint(*wp));
+                    }
+                });
+            }
+
+            if let Some(e) = self.error {
+                ui.colored_label(egui::Color32::RED, e);
+            }
+        });
+        self.open = open;
+    }
+}
+
+fn show_registers(
+    ui: &mut egui::Ui,
+    snapshot: &mut DebugSnapshot,
+    editable: bool,
+    send: &dyn Fn(DebugCommand),
+) {
+    let regs = &mut snapshot.registers;
+    let mut changed = false;
+    ui.add_enabled_ui(editable, |ui| {
+        egui::Grid::new("registers").num_columns(4).show(ui, |ui| {
+            for (name, value) in [("A", &mut regs.a), ("F", &mut regs.f)] {
+                ui.label(name);
+                changed |= ui.add(egui::DragValue::new(value).hexadecimal(2, false, true)).changed();
+            }
+            ui.end_row();
+            for (name, value) in [("B", &mut regs.b), ("C", &mut regs.c)] {
+                ui.label(name);
+                changed |= ui.add(egui::DragValue::new(value).hexadecimal(2, false, true)).changed();
+            }
+            ui.end_row();
+            for (name, value) in [("D", &mut regs.d), ("E", &mut regs.e)] {
+                ui.label(name);
+                changed |= ui.add(egui::DragValue::new(value).hexadecimal(2, false, true)).changed();
+            }
+            ui.end_row();
+            for (name, value) in [("H", &mut regs.h), ("L", &mut regs.l)] {
+                ui.label(name);
+                changed |= ui.add(egui::DragValue::new(value).hexadecimal(2, false, true)).changed();
+            }
+            ui.end_row();
+            for (name, value) in [("SP", &mut regs.sp), ("PC", &mut regs.pc)] {
+                ui.label(name);
+                changed |= ui.add(egui::DragValue::new(value).hexadecimal(4, false, true)).changed();
+            }
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            for (name, flag) in [("Z", CpuFlag::Z), ("N", CpuFlag::N), ("H", CpuFlag::H), ("C", CpuFlag::C)] {
+                let mut set = regs.flag(flag);
+                if ui.checkbox(&mut set, name).changed() {
+                    regs.set_flag(flag, set);
+                    changed = true;
+                }
+            }
+            changed |= ui.checkbox(&mut regs.ime, "IME").changed();
+            changed |= ui.checkbox(&mut regs.halted, "HALT").changed();
+        });
+    });
+    ui.label(format!("ROM bank {:02X}", snapshot.rom_bank));
+    if changed {
+        regs.f &= 0xF0;
+        send(DebugCommand::SetRegisters(*regs));
+    }
+}
+
+fn show_disassembly(ui: &mut egui::Ui, snapshot: &DebugSnapshot) {
+    egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+        for (i, ins) in snapshot.disassembly.iter().enumerate() {
+            if let Some(label) = &ins.label {
+                ui.monospace(format!("{}:", label));
+            }
+            let line = format!("{} {}", if i == 0 { ">" } else { " " }, ins);
+            ui.monospace(line);
+        }
+    });
+}
+
+fn watch_kind_label(kind: WatchKind) -> &'static str {
+    match kind {
+        WatchKind::Read => "Read",
+        WatchKind::Write => "Write",
+        WatchKind::ReadWrite => "Read/Write",
+    }
+}
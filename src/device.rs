@@ -1,8 +1,16 @@
 use crate::apu;
+use crate::bootrom::BootRom;
+use crate::camera::CameraImage;
 use crate::cpu::CPU;
-use crate::gbmode::GbMode;
+use crate::debugger::{Breakpoint, CpuRegisters, Debugger, StopReason, Watchpoint};
+use crate::disasm::{self, Instruction, SymbolTable};
+use crate::gbmode::{GbMode, HardwareModel};
+use crate::gpu::PpuRenderer;
 use crate::keypad::KeypadKey;
 use crate::mbc::{self, MBC};
+use crate::save_file;
+use crate::serial::SerialLink;
+use crate::trace::Tracer;
 use crate::StrResult;
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -11,6 +19,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 pub struct Device {
     cpu: CPU,
     save_state: Option<String>,
+    debugger: Debugger,
 }
 
 const SAVE_STATE_MAGIC_V1: &[u8; 8] = b"RGBEST01";
@@ -52,22 +61,20 @@ fn encode_cpu_state_with_preview(
 ) -> StrResult<(Vec<u8>, SaveStatePreview)> {
     let payload =
         rkyv::to_bytes::<rkyv::rancor::Error>(cpu).map_err(|_| "Failed to serialize CPU state")?;
-    let thumbnail_rgb =
-        thumbnail_rgb.filter(|data| data.len() == crate::gpu::SCREEN_W * crate::gpu::SCREEN_H * 3);
+    // Thumbnails are frames as shown: the Game Boy screen or the SGB picture with border.
+    let sizes = [
+        (crate::gpu::SCREEN_W, crate::gpu::SCREEN_H),
+        (crate::SGB_SCREEN_W, crate::SGB_SCREEN_H),
+    ];
+    let thumbnail_size = thumbnail_rgb
+        .and_then(|data| sizes.into_iter().find(|(w, h)| data.len() == w * h * 3));
+    let thumbnail_rgb = thumbnail_rgb.filter(|_| thumbnail_size.is_some());
     let thumbnail_len = thumbnail_rgb.map_or(0, <[u8]>::len);
     let thumbnail_len_u32 = u32::try_from(thumbnail_len).map_err(|_| "Thumbnail is too large")?;
     let payload_len_u64 = u64::try_from(payload.len()).map_err(|_| "Save state is too large")?;
     let saved_at_unix_secs = current_unix_secs();
-    let thumbnail_width = if thumbnail_rgb.is_some() {
-        crate::gpu::SCREEN_W as u16
-    } else {
-        0
-    };
-    let thumbnail_height = if thumbnail_rgb.is_some() {
-        crate::gpu::SCREEN_H as u16
-    } else {
-        0
-    };
+    let (thumbnail_width, thumbnail_height) =
+        thumbnail_size.map_or((0, 0), |(w, h)| (w as u16, h as u16));
 
     let mut data = Vec::with_capacity(SAVE_STATE_V2_HEADER_LEN + thumbnail_len + payload.len());
     data.extend_from_slice(SAVE_STATE_MAGIC_V2);
@@ -246,20 +253,20 @@ pub fn read_save_state_preview(path: impl AsRef<Path>) -> Option<SaveStatePrevie
     })
 }
 
+fn write_exit_state(cpu: &CPU, path: &str) -> StrResult<()> {
+    let data = encode_cpu_state(cpu)?;
+    save_file::write_atomic(Path::new(path), &data, save_file::BACKUPS)
+        .map_err(|_| "Failed to write save state file")
+}
+
+// Only a fallback; frontends call `save_battery_ram_on_exit` and `save_state_on_exit` so
+// they can report failures.
 impl Drop for Device {
     fn drop(&mut self) {
-        if let Some(path) = &self.save_state {
-            let mut file = match std::fs::File::create(path) {
-                Ok(f) => f,
-                Err(_) => return,
-            };
-            use std::io::Write;
-            match encode_cpu_state(&self.cpu) {
-                Ok(data) => {
-                    let _ = file.write_all(&data);
-                }
-                Err(_) => return,
-            }
+        if let Some(path) = &self.save_state
+            && let Err(e) = write_exit_state(&self.cpu, path)
+        {
+            eprintln!("Could not save state to {}: {}", path, e);
         }
     }
 }
@@ -324,6 +331,7 @@ mod test {
         let device = Device {
             cpu,
             save_state: Some(save_dir.join("game.state").to_string_lossy().to_string()),
+            debugger: Debugger::new(),
         };
         let thumbnail = vec![9; crate::gpu::SCREEN_W * crate::gpu::SCREEN_H * 3];
 
@@ -400,7 +408,7 @@ mod test {
         std::fs::write(&rom_path, test_rom()).unwrap();
 
         let expected_save_path = rom_path
-            .with_extension("gbsave")
+            .with_extension("sav")
             .to_string_lossy()
             .to_string();
         let cart = mbc::Cartridge::from_file(rom_path.clone(), true).unwrap();
@@ -426,6 +434,7 @@ mod test {
         let device = Device {
             cpu,
             save_state: Some(save_dir.join("game.state").to_string_lossy().to_string()),
+            debugger: Debugger::new(),
         };
 
         assert_eq!(
@@ -447,6 +456,7 @@ mod test {
         let device = Device {
             cpu,
             save_state: None,
+            debugger: Debugger::new(),
         };
 
         assert_eq!(
@@ -455,9 +465,106 @@ mod test {
         );
 
         let _ = std::fs::remove_file(rom_path);
-        let _ = std::fs::remove_file(rom_dir.join("game.gbsave"));
+        let _ = std::fs::remove_file(rom_dir.join("game.sav"));
         let _ = std::fs::remove_dir(rom_dir);
     }
+
+    #[test]
+    fn legacy_gbsave_migrates_and_sav_imports() {
+        let rom_dir =
+            std::env::temp_dir().join(format!("rust_gbe_sav_test_{}", std::process::id()));
+        std::fs::create_dir_all(&rom_dir).unwrap();
+        let rom_path = rom_dir.join("game.gb");
+        let mut rom = test_rom();
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+        let legacy: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        std::fs::write(rom_dir.join("game.gbsave"), &legacy).unwrap();
+
+        let cart = mbc::Cartridge::from_file(rom_path.clone(), true).unwrap();
+        let mut device = Device {
+            cpu: CPU::new(cart, None).unwrap(),
+            save_state: None,
+            debugger: Debugger::new(),
+        };
+        assert_eq!(device.dumpram(), legacy);
+        assert_eq!(std::fs::read(rom_dir.join("game.sav")).unwrap(), legacy);
+
+        let other = rom_dir.join("other.sav");
+        std::fs::write(&other, vec![0x5A; 0x2000]).unwrap();
+        device.import_battery_ram(&other).unwrap();
+        assert_eq!(std::fs::read(rom_dir.join("game.sav")).unwrap(), vec![0x5A; 0x2000]);
+        std::fs::write(&other, [0; 16]).unwrap();
+        assert!(device.import_battery_ram(&other).is_err());
+        device.export_battery_ram(&other).unwrap();
+        assert_eq!(std::fs::read(&other).unwrap(), vec![0x5A; 0x2000]);
+
+        drop(device);
+        let _ = std::fs::remove_dir_all(rom_dir);
+    }
+
+    #[test]
+    fn restoring_snapshots_keeps_save_file_and_backups() {
+        let rom_dir =
+            std::env::temp_dir().join(format!("rust_gbe_snapshot_sav_test_{}", std::process::id()));
+        std::fs::create_dir_all(&rom_dir).unwrap();
+        let rom_path = rom_dir.join("game.gb");
+        let mut rom = test_rom();
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        std::fs::write(&rom_path, rom).unwrap();
+        let sav = rom_dir.join("game.sav");
+        std::fs::write(&sav, vec![1; 0x2000]).unwrap();
+
+        let cart = mbc::Cartridge::from_file(rom_path.clone(), true).unwrap();
+        let mut device = Device {
+            cpu: CPU::new(cart, None).unwrap(),
+            save_state: None,
+            debugger: Debugger::new(),
+        };
+        device.save_battery_ram_silent().unwrap();
+        let snapshot = device.snapshot().unwrap();
+        device.write_byte(0x0000, 0x0A);
+        device.write_byte(0xA000, 2);
+        device.restore_snapshot(&snapshot).unwrap();
+        device.restore_snapshot(&snapshot).unwrap();
+
+        let backup = rom_dir.join("game.sav.1");
+        assert_eq!(std::fs::read(&backup).unwrap(), vec![1; 0x2000]);
+        assert!(!rom_dir.join("game.sav.2").exists());
+        assert_eq!(device.cpu.mmu.mbc.get_save_path(), Some(sav.to_string_lossy().to_string()));
+
+        // A movie for another game is rejected before the save file is detached.
+        let movie = crate::movie::Movie {
+            rom_title: "OTHER".to_string(),
+            model: device.model(),
+            renderer: device.renderer(),
+            boot_rom: false,
+            camera_image: None,
+            rtc_start: 0,
+            start: crate::movie::MovieStart::State(snapshot.clone()),
+            events: Vec::new(),
+            length: 0,
+            final_hash: 0,
+        };
+        assert!(crate::movie::MoviePlayer::new(movie, &mut device).is_err());
+        assert_eq!(device.cpu.mmu.mbc.get_save_path(), Some(sav.to_string_lossy().to_string()));
+        drop(device);
+        assert_eq!(std::fs::read(&backup).unwrap(), vec![1; 0x2000]);
+        assert!(!rom_dir.join("game.sav.2").exists());
+
+        // A movie detaches the save file before restoring its embedded state.
+        let mut device = Device {
+            cpu: CPU::new(mbc::Cartridge::from_file(rom_path, true).unwrap(), None).unwrap(),
+            save_state: None,
+            debugger: Debugger::new(),
+        };
+        device.detach_save_file();
+        device.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(device.cpu.mmu.mbc.get_save_path(), None);
+        let _ = std::fs::remove_dir_all(rom_dir);
+    }
 }
 
 impl Device {
@@ -472,6 +579,7 @@ impl Device {
         Some(Box::new(Device {
             cpu,
             save_state: Some(path.to_string()),
+            debugger: Debugger::new(),
         }))
     }
 
@@ -484,6 +592,7 @@ impl Device {
         CPU::new(cart, None).map(|cpu| Device {
             cpu: cpu,
             save_state,
+            debugger: Debugger::new(),
         })
     }
 
@@ -496,6 +605,7 @@ impl Device {
         CPU::new_cgb(cart, None).map(|cpu| Device {
             cpu: cpu,
             save_state,
+            debugger: Debugger::new(),
         })
     }
 
@@ -508,6 +618,7 @@ impl Device {
         CPU::new(cart, None).map(|cpu| Device {
             cpu: cpu,
             save_state,
+            debugger: Debugger::new(),
         })
     }
 
@@ -520,13 +631,199 @@ impl Device {
         CPU::new_cgb(cart, None).map(|cpu| Device {
             cpu: cpu,
             save_state,
+            debugger: Debugger::new(),
+        })
+    }
+
+    /// Emulates `model`. With a boot ROM, which must be for the same console family,
+    /// it runs before the game; otherwise the game starts from the post-boot state.
+    pub fn new_with_model(
+        romname: &str,
+        model: HardwareModel,
+        boot_rom: Option<BootRom>,
+        skip_checksum: bool,
+        save_state: Option<String>,
+    ) -> StrResult<Device> {
+        let cart = mbc::Cartridge::from_file(romname.into(), skip_checksum)?;
+        CPU::new_with_model(cart, None, model, boot_rom).map(|cpu| Device {
+            cpu,
+            save_state,
+            debugger: Debugger::new(),
+        })
+    }
+
+    pub fn new_from_buffer_with_model(
+        romdata: Vec<u8>,
+        model: HardwareModel,
+        boot_rom: Option<BootRom>,
+        skip_checksum: bool,
+        save_state: Option<String>,
+    ) -> StrResult<Device> {
+        let cart = mbc::Cartridge::from_buffer(romdata, skip_checksum)?;
+        CPU::new_with_model(cart, None, model, boot_rom).map(|cpu| Device {
+            cpu,
+            save_state,
+            debugger: Debugger::new(),
         })
     }
 
+    pub fn model(&self) -> HardwareModel {
+        self.cpu.mmu.model
+    }
+
+    /// True until the boot ROM unmaps itself by writing to 0xFF50.
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.cpu.mmu.boot_rom_mapped()
+    }
+
+    pub fn renderer(&self) -> PpuRenderer {
+        self.cpu.mmu.gpu.renderer
+    }
+
+    pub fn set_renderer(&mut self, renderer: PpuRenderer) {
+        self.cpu.mmu.gpu.set_renderer(renderer);
+    }
+
     pub fn do_cycle(&mut self) -> u32 {
+        if self.debugger.is_active(&self.cpu) {
+            return self.debugger.execute(&mut self.cpu);
+        }
         self.cpu.do_cycle()
     }
 
+    /// Runs until the debugger stops or `max_ticks` have passed; returns why it stopped.
+    pub fn run_until_stop(&mut self, max_ticks: u64) -> Option<StopReason> {
+        let mut ticks = 0;
+        while ticks < max_ticks && self.debugger.stopped().is_none() {
+            ticks += self.do_cycle() as u64;
+        }
+        self.debugger.stopped()
+    }
+
+    /// Why execution is stopped, or None while running. `do_cycle` does nothing while stopped.
+    pub fn debug_stop_reason(&self) -> Option<StopReason> {
+        self.debugger.stopped()
+    }
+
+    pub fn debug_break(&mut self) {
+        self.debugger.stop(StopReason::Break);
+    }
+
+    pub fn debug_continue(&mut self) {
+        self.debugger.resume(&self.cpu);
+    }
+
+    pub fn debug_step(&mut self) {
+        self.debugger.step_into(&self.cpu);
+    }
+
+    pub fn debug_step_over(&mut self) {
+        self.debugger.step_over(&mut self.cpu);
+    }
+
+    pub fn debug_step_out(&mut self) {
+        self.debugger.step_out(&self.cpu);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        self.debugger.breakpoints()
+    }
+
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) {
+        self.debugger.add_breakpoint(bp);
+    }
+
+    pub fn remove_breakpoint(&mut self, bp: Breakpoint) {
+        self.debugger.remove_breakpoint(bp);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.cpu.mmu.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, wp: Watchpoint) {
+        if !self.cpu.mmu.watchpoints.contains(&wp) {
+            self.cpu.mmu.watchpoints.push(wp);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, wp: Watchpoint) {
+        self.cpu.mmu.watchpoints.retain(|w| *w != wp);
+    }
+
+    pub fn registers(&self) -> CpuRegisters {
+        self.cpu.debug_registers()
+    }
+
+    pub fn set_registers(&mut self, regs: &CpuRegisters) {
+        self.cpu.set_debug_registers(regs);
+    }
+
+    /// Logs every executed instruction matching the tracer's filter, replacing any
+    /// trace already running.
+    pub fn start_trace(&mut self, tracer: Tracer) {
+        self.cpu.tracer = Some(Box::new(tracer));
+    }
+
+    /// Stops tracing and flushes the log.
+    pub fn stop_trace(&mut self) -> std::io::Result<()> {
+        match self.cpu.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads memory as the CPU sees it, without triggering watchpoints.
+    pub fn read_memory(&mut self, address: u16) -> u8 {
+        self.cpu.mmu.peek(address)
+    }
+
+    /// Writes memory as the CPU would, without triggering watchpoints.
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        self.cpu.mmu.poke(address, value)
+    }
+
+    /// ROM bank mapped at `address`, for bank-qualified breakpoints.
+    pub fn rom_bank(&self, address: u16) -> usize {
+        self.cpu.mmu.mbc.rombank(address)
+    }
+
+    /// Replaces the labels used by `disassemble` with those in an RGBDS or no$gmb
+    /// symbol file. Returns the number of symbols loaded.
+    pub fn load_symbols(&mut self, path: &Path) -> StrResult<usize> {
+        self.debugger.symbols = SymbolTable::load(path)?;
+        Ok(self.debugger.symbols.len())
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.debugger.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.debugger.symbols
+    }
+
+    /// Decodes `count` instructions starting at `address`, reading through the
+    /// current memory mapping without triggering watchpoints.
+    pub fn disassemble(&mut self, address: u16, count: usize) -> Vec<Instruction> {
+        let low_bank = self.cpu.mmu.mbc.rombank(0x0000);
+        let high_bank = self.cpu.mmu.mbc.rombank(0x4000);
+        let bank_of = |a: u16| match a {
+            0x0000..=0x3FFF => low_bank,
+            0x4000..=0x7FFF => high_bank,
+            _ => 0,
+        };
+        let mmu = &mut self.cpu.mmu;
+        let mut address = address;
+        let mut result = Vec::with_capacity(count);
+        for _ in 0..count {
+            let ins = disasm::disassemble(|a| mmu.peek(a), bank_of, address, Some(&self.debugger.symbols));
+            address = address.wrapping_add(ins.bytes.len() as u16);
+            result.push(ins);
+        }
+        result
+    }
+
     pub fn check_and_reset_gpu_updated(&mut self) -> bool {
         let result = self.cpu.mmu.gpu.updated;
         self.cpu.mmu.gpu.updated = false;
@@ -537,6 +834,43 @@ impl Device {
         self.cpu.mmu.gpu.front_buffer()
     }
 
+    /// Size of `display_data`: the Game Boy screen, or the SGB picture with its border.
+    pub fn display_size(&self) -> (usize, usize) {
+        match self.cpu.mmu.sgb {
+            Some(_) => (crate::SGB_SCREEN_W, crate::SGB_SCREEN_H),
+            None => (crate::SCREEN_W, crate::SCREEN_H),
+        }
+    }
+
+    /// What the player sees: `get_gpu_data`, or on the SGB the colorized picture with border.
+    pub fn display_data(&self) -> &[u8] {
+        match &self.cpu.mmu.sgb {
+            Some(sgb) => sgb.screen(),
+            None => self.get_gpu_data(),
+        }
+    }
+
+    /// Start recording every byte the game sends over the serial port. Test ROMs
+    /// (e.g. blargg's) report their results this way.
+    pub fn enable_serial_capture(&mut self) {
+        self.cpu.mmu.serial.enable_capture();
+    }
+
+    /// Bytes sent over the serial port since the previous call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.cpu.mmu.serial.take_captured()
+    }
+
+    /// Plug a link cable into the serial port, replacing any previous one.
+    /// The link stays attached across save state loads.
+    pub fn set_serial_link(&mut self, link: Box<dyn SerialLink>) {
+        self.cpu.mmu.serial.set_link(Some(link));
+    }
+
+    pub fn unplug_serial_link(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.cpu.mmu.serial.take_link()
+    }
+
     pub fn enable_audio(&mut self, player: Box<dyn apu::AudioPlayer>, is_on: bool) {
         match self.cpu.mmu.gbmode {
             GbMode::Classic => {
@@ -589,6 +923,11 @@ impl Device {
         self.cpu.mmu.mbc.dumpram()
     }
 
+    /// True when the cartridge reads tilt, so `set_tilt` does something.
+    pub fn has_accelerometer(&self) -> bool {
+        self.cpu.mmu.mbc.has_accelerometer()
+    }
+
     pub fn ram_is_battery_backed(&self) -> bool {
         self.cpu.mmu.mbc.is_battery_backed()
     }
@@ -603,64 +942,75 @@ impl Device {
         true // placeholder
     }
 
+    /// Loads battery RAM from a `.sav` file, as written by this or another emulator (raw
+    /// RAM, followed by an RTC footer on MBC3 carts with a clock), and saves it as this
+    /// game's battery RAM. Reset afterwards so the game reads it from scratch.
+    pub fn import_battery_ram(&mut self, path: &Path) -> StrResult<()> {
+        if !self.ram_is_battery_backed() {
+            return Err("Cartridge has no battery-backed RAM");
+        }
+        let data = std::fs::read(path).map_err(|_| "Could not read save file")?;
+        self.cpu.mmu.mbc.loadram(&data)?;
+        self.cpu.mmu.mbc.save_battery_ram(true)
+    }
+
+    /// Writes the battery RAM to `path` in the `.sav` layout other emulators read.
+    pub fn export_battery_ram(&self, path: &Path) -> StrResult<()> {
+        if !self.ram_is_battery_backed() {
+            return Err("Cartridge has no battery-backed RAM");
+        }
+        save_file::write_atomic(path, &self.dumpram(), 0).map_err(|_| "Could not write save file")
+    }
+
     pub fn save_battery_ram(&self) -> StrResult<()> {
-        self.save_battery_ram_with_message(true)
+        self.save_battery_ram_with_message(true, true)
     }
 
     pub fn save_battery_ram_silent(&self) -> StrResult<()> {
-        self.save_battery_ram_with_message(false)
-    }
-
-    fn save_battery_ram_with_message(&self, show_message: bool) -> StrResult<()> {
-        if self.cpu.mmu.mbc.is_battery_backed() {
-            let ram_data = self.cpu.mmu.mbc.dumpram();
-
-            if let Some(save_path) = self.cpu.mmu.mbc.get_save_path() {
-                if show_message {
-                    println!("DEBUG: Attempting to save to path: {}", save_path);
-                    println!("DEBUG: RAM data size: {} bytes", ram_data.len());
-
-                    // Show first 16 bytes of RAM for debugging
-                    if ram_data.len() > 0 {
-                        let preview: Vec<String> = ram_data
-                            .iter()
-                            .take(16)
-                            .map(|b| format!("{:02X}", b))
-                            .collect();
-                        println!("DEBUG: First 16 bytes of RAM: {}", preview.join(" "));
-                    }
-                }
+        self.save_battery_ram_with_message(false, true)
+    }
 
-                // Make the save completely asynchronous to prevent hanging
-                std::thread::spawn(move || match std::fs::write(&save_path, &ram_data) {
-                    Ok(_) => {
-                        if show_message {
-                            println!(
-                                "Game save written to {} ({} bytes)",
-                                save_path,
-                                ram_data.len()
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to write game save to {}: {}", save_path, e);
-                    }
-                });
-                Ok(())
-            } else {
-                if show_message {
-                    eprintln!("DEBUG: No save path available from MBC");
-                }
-                Err("No save path available")
-            }
-        } else {
-            if show_message {
-                println!("DEBUG: MBC is not battery-backed, no save needed");
-            }
-            Ok(()) // No battery-backed RAM, nothing to save
+    /// The final battery save when emulation stops. Once it is written the save file is
+    /// detached, so dropping the device does not write it again.
+    pub fn save_battery_ram_on_exit(&mut self) -> StrResult<()> {
+        if self.cpu.mmu.mbc.get_save_path().is_none() {
+            return Ok(());
+        }
+        self.save_battery_ram_silent()?;
+        self.detach_save_file();
+        Ok(())
+    }
+
+    /// Writes the state to resume from next time, if one was requested, in place of
+    /// doing it on drop.
+    pub fn save_state_on_exit(&mut self) -> StrResult<()> {
+        match self.save_state.take() {
+            Some(path) => write_exit_state(&self.cpu, &path),
+            None => Ok(()),
         }
     }
 
+    /// The periodic save while running: skips syncing to disk, which would stall emulation.
+    /// Exit and explicit saves still sync.
+    pub fn save_battery_ram_periodic(&self) -> StrResult<()> {
+        self.save_battery_ram_with_message(false, false)
+    }
+
+    fn save_battery_ram_with_message(&self, show_message: bool, sync: bool) -> StrResult<()> {
+        if !self.cpu.mmu.mbc.is_battery_backed() {
+            return Ok(()); // No battery-backed RAM, nothing to save
+        }
+        let Some(save_path) = self.cpu.mmu.mbc.get_save_path() else {
+            return Err("No save path available");
+        };
+        // Written in place of the old save only once complete, so a crash cannot corrupt it.
+        self.cpu.mmu.mbc.save_battery_ram(sync)?;
+        if show_message {
+            println!("Game save written to {}", save_path);
+        }
+        Ok(())
+    }
+
     pub fn read_byte(&mut self, address: u16) -> u8 {
         self.cpu.read_byte(address)
     }
@@ -713,7 +1063,7 @@ impl Device {
 
         let save_path = self.save_state_slot_path(slot);
 
-        match std::fs::write(&save_path, &serialized_data) {
+        match save_file::write_atomic(&save_path, &serialized_data, save_file::BACKUPS) {
             Ok(_) => {
                 println!("State saved to slot {} ({})", slot, save_path.display());
                 Ok(preview)
@@ -725,6 +1075,89 @@ impl Device {
         }
     }
 
+    /// Refills WRAM with the power-on pattern generated from `seed`. Only meaningful right
+    /// after construction, which uses `DEFAULT_WRAM_SEED`.
+    pub fn reseed_wram(&mut self, seed: u32) {
+        self.cpu.mmu.reseed_wram(seed);
+    }
+
+    /// Pins the cartridge RTC to the given unix time; None follows the host clock again.
+    pub fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        self.cpu.mmu.mbc.set_rtc_clock(unix_secs);
+    }
+
+    /// Advances the cartridge RTC by the time since its save file was written. Call right
+    /// after construction; otherwise the clock only follows emulated time.
+    pub fn catch_up_rtc(&mut self) {
+        self.cpu.mmu.mbc.catch_up_rtc();
+    }
+
+    /// Tilt for accelerometer cartridges, in g from -1.0 to 1.0; positive x tilts right
+    /// and positive y tilts the bottom of the screen down. Ignored by other cartridges.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.mbc.set_tilt(x, y);
+    }
+
+    /// Scene seen by a Pocket Camera's sensor. Ignored by other cartridges.
+    pub fn set_camera_image(&mut self, image: &CameraImage) {
+        self.cpu.mmu.mbc.set_camera_image(image);
+    }
+
+    /// Scene a Pocket Camera currently sees; None for other cartridges.
+    pub fn camera_image(&self) -> Option<CameraImage> {
+        self.cpu.mmu.mbc.camera_image()
+    }
+
+    /// Battery-backed cartridge RAM in the same layout as the save file.
+    pub fn cartridge_ram(&self) -> Vec<u8> {
+        self.cpu.mmu.mbc.dumpram()
+    }
+
+    pub fn load_cartridge_ram(&mut self, data: &[u8]) -> StrResult<()> {
+        self.cpu.mmu.mbc.loadram(data)
+    }
+
+    /// Keeps the cartridge RAM from being written back to the save file.
+    pub fn detach_save_file(&mut self) {
+        self.cpu.mmu.mbc.detach_save_file();
+    }
+
+    /// FNV-1a hash of the current frame, for checking that two runs ended identically.
+    pub fn frame_hash(&self) -> u64 {
+        self.get_gpu_data()
+            .iter()
+            .fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+    }
+
+    /// Serializes the machine state for `restore_snapshot`, e.g. for rewinding.
+    pub fn snapshot(&self) -> StrResult<Vec<u8>> {
+        encode_cpu_state(&self.cpu)
+    }
+
+    pub fn restore_snapshot(&mut self, data: &[u8]) -> StrResult<()> {
+        let cpu = decode_cpu_state(data)?;
+        self.replace_cpu(cpu);
+        Ok(())
+    }
+
+    // Swaps in a deserialized CPU, keeping the host-side attachments that save states skip.
+    // The save file stays with the running session: the state's serialized save path is
+    // replaced, and the outgoing cartridge neither writes nor rotates anything.
+    fn replace_cpu(&mut self, mut cpu: CPU) {
+        self.cpu.mmu.mbc.hand_over_save_file(&mut cpu.mmu.mbc);
+        let link = self.cpu.mmu.serial.take_link();
+        let watchpoints = std::mem::take(&mut self.cpu.mmu.watchpoints);
+        let tracer = self.cpu.tracer.take();
+        let sound = self.cpu.mmu.sound.take();
+        let renderer = self.renderer();
+        self.cpu = cpu;
+        self.cpu.mmu.serial.set_link(link);
+        self.cpu.mmu.watchpoints = watchpoints;
+        self.cpu.tracer = tracer;
+        self.cpu.mmu.sound = sound;
+        self.cpu.mmu.gpu.renderer = renderer;
+    }
+
     pub fn load_state_slot(&mut self, slot: u8) -> StrResult<()> {
         println!("Loading state from slot {}...", slot);
         let save_path = self.save_state_slot_path(slot);
@@ -732,7 +1165,7 @@ impl Device {
         match std::fs::read(&save_path) {
             Ok(data) => match decode_cpu_state(&data) {
                 Ok(cpu) => {
-                    self.cpu = cpu;
+                    self.replace_cpu(cpu);
                     println!("State loaded from slot {}", slot);
                     Ok(())
                 }
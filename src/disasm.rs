@@ -0,0 +1,354 @@
+//! SM83 disassembler with I/O register names and RGBDS / no$gmb symbol files.
+use crate::StrResult;
+use std::collections::HashMap;
+use std::path::Path;
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACC_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+/// Labels loaded from a symbol file, keyed by bank and address.
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: HashMap<(usize, u16), String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Parses `BB:AAAA name` lines as written by RGBDS (`.sym`) and no$gmb.
+    /// Comments (`;`) and section headers (`[labels]`) are skipped.
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(location), Some(name)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let Some((bank, address)) = location.split_once(':') else {
+                continue;
+            };
+            if let (Ok(bank), Ok(address)) =
+                (usize::from_str_radix(bank, 16), u16::from_str_radix(address, 16))
+            {
+                table.insert(bank, address, name);
+            }
+        }
+        table
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> StrResult<SymbolTable> {
+        std::fs::read_to_string(path)
+            .map(|text| SymbolTable::parse(&text))
+            .map_err(|_| "Could not read symbol file")
+    }
+
+    pub fn insert(&mut self, bank: usize, address: u16, name: &str) {
+        self.symbols.insert((bank, address), name.to_string());
+    }
+
+    /// Label at `address` in `bank`. Outside ROM the bank is only a preference, since
+    /// symbol files disagree on how RAM banks are numbered.
+    pub fn lookup(&self, bank: usize, address: u16) -> Option<&str> {
+        if let Some(name) = self.symbols.get(&(bank, address)) {
+            return Some(name);
+        }
+        if address < 0x8000 {
+            return None;
+        }
+        self.symbols
+            .iter()
+            .filter(|((_, a), _)| *a == address)
+            .min_by_key(|((b, _), _)| *b)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// One decoded instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Label defined at `address`, if any.
+    pub label: Option<String>,
+    /// I/O register name for instructions accessing 0xFF00-0xFFFF.
+    pub comment: Option<String>,
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04X}: ", self.address)?;
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:<9} {}", bytes.join(" "), self.text)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " ; {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn io_register_name(address: u16) -> Option<&'static str> {
+    Some(match address {
+        0xFF00 => "P1",
+        0xFF01 => "SB",
+        0xFF02 => "SC",
+        0xFF04 => "DIV",
+        0xFF05 => "TIMA",
+        0xFF06 => "TMA",
+        0xFF07 => "TAC",
+        0xFF0F => "IF",
+        0xFF10 => "NR10",
+        0xFF11 => "NR11",
+        0xFF12 => "NR12",
+        0xFF13 => "NR13",
+        0xFF14 => "NR14",
+        0xFF16 => "NR21",
+        0xFF17 => "NR22",
+        0xFF18 => "NR23",
+        0xFF19 => "NR24",
+        0xFF1A => "NR30",
+        0xFF1B => "NR31",
+        0xFF1C => "NR32",
+        0xFF1D => "NR33",
+        0xFF1E => "NR34",
+        0xFF20 => "NR41",
+        0xFF21 => "NR42",
+        0xFF22 => "NR43",
+        0xFF23 => "NR44",
+        0xFF24 => "NR50",
+        0xFF25 => "NR51",
+        0xFF26 => "NR52",
+        0xFF30..=0xFF3F => "WAVE",
+        0xFF40 => "LCDC",
+        0xFF41 => "STAT",
+        0xFF42 => "SCY",
+        0xFF43 => "SCX",
+        0xFF44 => "LY",
+        0xFF45 => "LYC",
+        0xFF46 => "DMA",
+        0xFF47 => "BGP",
+        0xFF48 => "OBP0",
+        0xFF49 => "OBP1",
+        0xFF4A => "WY",
+        0xFF4B => "WX",
+        0xFF4D => "KEY1",
+        0xFF4F => "VBK",
+        0xFF50 => "BOOT",
+        0xFF51 => "HDMA1",
+        0xFF52 => "HDMA2",
+        0xFF53 => "HDMA3",
+        0xFF54 => "HDMA4",
+        0xFF55 => "HDMA5",
+        0xFF56 => "RP",
+        0xFF68 => "BCPS",
+        0xFF69 => "BCPD",
+        0xFF6A => "OCPS",
+        0xFF6B => "OCPD",
+        0xFF6C => "OPRI",
+        0xFF70 => "SVBK",
+        0xFF76 => "PCM12",
+        0xFF77 => "PCM34",
+        0xFFFF => "IE",
+        _ => return None,
+    })
+}
+
+// How an operand address should be rendered.
+enum Operand {
+    None,
+    // Jump or call target: replaced by its label when known.
+    Target,
+    // Memory address read or written: label if known, I/O register name as a comment.
+    Memory(u16),
+}
+
+/// Decodes the instruction at `address`. `read` fetches memory through the current
+/// mapping and `bank_of` gives the bank mapped at an address, for symbol lookups.
+pub fn disassemble(
+    mut read: impl FnMut(u16) -> u8,
+    bank_of: impl Fn(u16) -> usize,
+    address: u16,
+    symbols: Option<&SymbolTable>,
+) -> Instruction {
+    let op = read(address);
+    let n = read(address.wrapping_add(1));
+    let nn = n as u16 | (read(address.wrapping_add(2)) as u16) << 8;
+    let rel = address.wrapping_add(2).wrapping_add(n as i8 as u16);
+
+    let (x, y, z) = (op >> 6, ((op >> 3) & 7) as usize, (op & 7) as usize);
+    let (p, q) = (y >> 1, y & 1);
+
+    let label = |a: u16| {
+        symbols
+            .and_then(|s| s.lookup(bank_of(a), a))
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("${:04X}", a))
+    };
+
+    let (len, text, operand): (u16, String, Operand) = match (x, z) {
+        (0, 0) => match y {
+            0 => (1, "NOP".into(), Operand::None),
+            1 => (3, format!("LD ({}),SP", label(nn)), Operand::Memory(nn)),
+            2 => (2, "STOP".into(), Operand::None),
+            3 => (2, format!("JR {}", label(rel)), Operand::Target),
+            _ => (2, format!("JR {},{}", CC[y - 4], label(rel)), Operand::Target),
+        },
+        (0, 1) if q == 0 => (3, format!("LD {},${:04X}", RP[p], nn), Operand::None),
+        (0, 1) => (1, format!("ADD HL,{}", RP[p]), Operand::None),
+        (0, 2) => {
+            let mem = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            let text = if q == 0 { format!("LD {},A", mem) } else { format!("LD A,{}", mem) };
+            (1, text, Operand::None)
+        }
+        (0, 3) => (1, format!("{} {}", ["INC", "DEC"][q], RP[p]), Operand::None),
+        (0, 4) => (1, format!("INC {}", R[y]), Operand::None),
+        (0, 5) => (1, format!("DEC {}", R[y]), Operand::None),
+        (0, 6) => (2, format!("LD {},${:02X}", R[y], n), Operand::None),
+        (0, _) => (1, ACC_OPS[y].into(), Operand::None),
+        (1, 6) if y == 6 => (1, "HALT".into(), Operand::None),
+        (1, _) => (1, format!("LD {},{}", R[y], R[z]), Operand::None),
+        (2, _) => (1, format!("{}{}", ALU[y], R[z]), Operand::None),
+        (_, 0) => match y {
+            0..=3 => (1, format!("RET {}", CC[y]), Operand::None),
+            4 => {
+                let a = 0xFF00 | n as u16;
+                (2, format!("LDH ({}),A", label(a)), Operand::Memory(a))
+            }
+            5 => (2, format!("ADD SP,{}", n as i8), Operand::None),
+            6 => {
+                let a = 0xFF00 | n as u16;
+                (2, format!("LDH A,({})", label(a)), Operand::Memory(a))
+            }
+            _ => (2, format!("LD HL,SP{:+}", n as i8), Operand::None),
+        },
+        (_, 1) if q == 0 => (1, format!("POP {}", RP2[p]), Operand::None),
+        (_, 1) => (1, ["RET", "RETI", "JP HL", "LD SP,HL"][p].into(), Operand::None),
+        (_, 2) => match y {
+            0..=3 => (3, format!("JP {},{}", CC[y], label(nn)), Operand::Target),
+            4 => (1, "LD ($FF00+C),A".into(), Operand::None),
+            5 => (3, format!("LD ({}),A", label(nn)), Operand::Memory(nn)),
+            6 => (1, "LD A,($FF00+C)".into(), Operand::None),
+            _ => (3, format!("LD A,({})", label(nn)), Operand::Memory(nn)),
+        },
+        (_, 3) => match y {
+            0 => (3, format!("JP {}", label(nn)), Operand::Target),
+            1 => {
+                let (cx, cy, cz) = (n >> 6, ((n >> 3) & 7) as usize, (n & 7) as usize);
+                let text = match cx {
+                    0 => format!("{} {}", ROT[cy], R[cz]),
+                    1 => format!("BIT {},{}", cy, R[cz]),
+                    2 => format!("RES {},{}", cy, R[cz]),
+                    _ => format!("SET {},{}", cy, R[cz]),
+                };
+                (2, text, Operand::None)
+            }
+            6 => (1, "DI".into(), Operand::None),
+            7 => (1, "EI".into(), Operand::None),
+            _ => (1, format!("DB ${:02X}", op), Operand::None),
+        },
+        (_, 4) if y < 4 => (3, format!("CALL {},{}", CC[y], label(nn)), Operand::Target),
+        (_, 5) if q == 0 => (1, format!("PUSH {}", RP2[p]), Operand::None),
+        (_, 5) if p == 0 => (3, format!("CALL {}", label(nn)), Operand::Target),
+        (_, 6) => (2, format!("{}${:02X}", ALU[y], n), Operand::None),
+        (_, 7) => {
+            let target = (y * 8) as u16;
+            (1, format!("RST ${:02X}", target), Operand::Target)
+        }
+        _ => (1, format!("DB ${:02X}", op), Operand::None),
+    };
+
+    let comment = match operand {
+        Operand::Memory(a) => io_register_name(a).map(str::to_string),
+        Operand::None | Operand::Target => None,
+    };
+    Instruction {
+        address,
+        bytes: (0..len).map(|i| read(address.wrapping_add(i))).collect(),
+        text,
+        label: symbols.and_then(|s| s.lookup(bank_of(address), address)).map(str::to_string),
+        comment,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(bytes: &[u8], symbols: Option<&SymbolTable>) -> Instruction {
+        let mut mem = vec![0u8; 0x10000];
+        mem[0x4000..0x4000 + bytes.len()].copy_from_slice(bytes);
+        disassemble(|a| mem[a as usize], |a| if a < 0x4000 { 0 } else { 2 }, 0x4000, symbols)
+    }
+
+    #[test]
+    fn decodes_basic_instructions() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0x00], "NOP"),
+            (&[0x01, 0x34, 0x12], "LD BC,$1234"),
+            (&[0x22], "LD (HL+),A"),
+            (&[0x36, 0x7F], "LD (HL),$7F"),
+            (&[0x76], "HALT"),
+            (&[0x78], "LD A,B"),
+            (&[0x9E], "SBC A,(HL)"),
+            (&[0xE6, 0x0F], "AND $0F"),
+            (&[0xE8, 0xFE], "ADD SP,-2"),
+            (&[0xF8, 0x05], "LD HL,SP+5"),
+            (&[0x18, 0xFE], "JR $4000"),
+            (&[0x20, 0x02], "JR NZ,$4004"),
+            (&[0xC3, 0x50, 0x01], "JP $0150"),
+            (&[0xE9], "JP HL"),
+            (&[0xD9], "RETI"),
+            (&[0xFF], "RST $38"),
+            (&[0xCB, 0x37], "SWAP A"),
+            (&[0xCB, 0x7E], "BIT 7,(HL)"),
+            (&[0xCB, 0xC1], "SET 0,C"),
+            (&[0xD3], "DB $D3"),
+        ];
+        for (bytes, text) in cases {
+            let ins = decode(bytes, None);
+            assert_eq!(ins.text, *text);
+            assert_eq!(ins.bytes, *bytes);
+        }
+    }
+
+    #[test]
+    fn io_registers_are_annotated() {
+        let ins = decode(&[0xE0, 0x40], None);
+        assert_eq!(ins.text, "LDH ($FF40),A");
+        assert_eq!(ins.comment.as_deref(), Some("LCDC"));
+        assert_eq!(decode(&[0xFA, 0xFF, 0xFF], None).comment.as_deref(), Some("IE"));
+    }
+
+    #[test]
+    fn symbols_resolve_per_bank() {
+        let symbols = SymbolTable::parse(
+            "; generated\n[labels]\n00:0150 Main\n02:4000 BankTwoStart\n03:4000 BankThreeStart\n00:C000 wBuffer\n",
+        );
+        assert_eq!(symbols.len(), 4);
+
+        let ins = decode(&[0xCD, 0x50, 0x01], Some(&symbols));
+        assert_eq!(ins.text, "CALL Main");
+        assert_eq!(ins.label.as_deref(), Some("BankTwoStart"));
+        assert_eq!(decode(&[0xEA, 0x00, 0xC0], Some(&symbols)).text, "LD (wBuffer),A");
+        assert_eq!(decode(&[0x18, 0xFE], Some(&symbols)).text, "JR BankTwoStart");
+    }
+}
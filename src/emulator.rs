@@ -1,16 +1,39 @@
 //! High-level emulator orchestration: device construction, run loop & events.
 use std::sync::mpsc::{Receiver, Sender, SyncSender, TryRecvError, TrySendError};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use rust_gbe::bootrom::BootRom;
+use rust_gbe::debugger::{Breakpoint, CpuRegisters, StopReason, Watchpoint};
 use rust_gbe::device::{Device, SaveStatePreview};
+use rust_gbe::disasm::Instruction;
+use rust_gbe::link::TcpLink;
+use rust_gbe::movie::{Movie, MoviePlayer, MovieRecorder};
+use rust_gbe::printer::Printer;
+use rust_gbe::rewind::RewindBuffer;
+use rust_gbe::{HardwareModel, DEFAULT_WRAM_SEED};
 
-// Global setting for additional periodic auto-save functionality
-static AUTO_SAVE_ENABLED: bool = false;
+// Battery RAM is written at most this often while a game keeps changing it; the final
+// state is written when the emulator stops.
+const RAM_SAVE_INTERVAL_FRAMES: u32 = 60;
+
+// Frames between rewind snapshots; rewinding restores one snapshot per frame, so it
+// plays back at this multiple of normal speed.
+const REWIND_INTERVAL_FRAMES: u32 = 4;
+
+fn rewind_capacity(seconds: u32) -> usize {
+    (seconds * 60 / REWIND_INTERVAL_FRAMES) as usize
+}
 
 pub enum GBEvent {
     KeyUp(rust_gbe::KeypadKey),
     KeyDown(rust_gbe::KeypadKey),
+    Tilt(f32, f32), // accelerometer cartridges, in g
+    SetCameraImage(rust_gbe::camera::CameraImage),
+    // Battery RAM from or to a `.sav` file; a successful import is answered with
+    // GuiEvent::SaveImported.
+    ImportSave(std::path::PathBuf),
+    ExportSave(std::path::PathBuf),
     SpeedUp,
     SpeedDown,
     SaveState {
@@ -21,37 +44,182 @@ pub enum GBEvent {
     UpdateTurbo(crate::config::TurboSetting),
     UpdateVolume(f32), // master volume 0.0-1.0
     SetPaused(bool),
+    Rewind(bool), // true while the rewind key is held
+    UpdateRewindLength(u32), // seconds of history to keep; 0 disables rewind
+    SetRenderer(rust_gbe::PpuRenderer),
+    // Plug a printer in (writing printouts next to the given ROM path) or unplug it.
+    SetPrinter(Option<std::path::PathBuf>),
+    Debug(DebugCommand),
+    // Record a movie to the given path, from power-on (the emulator was just reset) or
+    // from the current state.
+    StartRecording { path: std::path::PathBuf, power_on: bool },
+    StopRecording,
+    // Replay a movie; power-on movies are sent right after a reset.
+    PlayMovie(Box<Movie>),
     Shutdown,
 }
 
+enum MovieSession {
+    Idle,
+    Recording(MovieRecorder, std::path::PathBuf),
+    Playing(MoviePlayer),
+}
+
+impl MovieSession {
+    fn do_cycle(&mut self, cpu: &mut Device) -> u32 {
+        match self {
+            MovieSession::Idle => cpu.do_cycle(),
+            MovieSession::Recording(recorder, _) => recorder.do_cycle(cpu),
+            MovieSession::Playing(player) => player.do_cycle(cpu),
+        }
+    }
+}
+
+pub enum DebugCommand {
+    Break,
+    Continue,
+    Step,
+    StepOver,
+    StepOut,
+    AddBreakpoint(Breakpoint),
+    RemoveBreakpoint(Breakpoint),
+    AddWatchpoint(Watchpoint),
+    RemoveWatchpoint(Watchpoint),
+    SetRegisters(CpuRegisters),
+    // Only asks for a fresh DebugState.
+    Refresh,
+}
+
+/// What the debugger window shows; sent after every debug command and when execution stops.
+pub struct DebugSnapshot {
+    pub registers: CpuRegisters,
+    pub rom_bank: usize,
+    pub stop: Option<StopReason>,
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Instructions from PC onwards.
+    pub disassembly: Vec<Instruction>,
+}
+
 pub enum GuiEvent {
     SaveStateSaved { slot: u8, preview: SaveStatePreview },
     SaveStateFailed { slot: u8 },
+    // A battery save or save state could not be written (or imported/exported).
+    // `kept_previous` is set for failed writes, which leave the old file and backups alone.
+    SaveError { message: String, kept_previous: bool },
+    // An ImportSave succeeded; the GUI resets so the game picks it up.
+    SaveImported,
+    DebugState(DebugSnapshot),
+}
+
+/// Link cable backend requested on the command line.
+#[derive(Clone)]
+pub enum LinkOption {
+    Listen(String),
+    Connect(String),
 }
 
-pub fn construct_cpu_auto(filename: &str) -> Option<(Box<Device>, bool)> {
+impl LinkOption {
+    /// Blocks until the peer is connected; returns None (cable unplugged) on failure.
+    pub fn open(&self) -> Option<Box<dyn rust_gbe::SerialLink>> {
+        let result = match self {
+            LinkOption::Listen(addr) => {
+                println!("Waiting for link cable peer on {}...", addr);
+                TcpLink::listen(addr.as_str())
+            }
+            LinkOption::Connect(addr) => {
+                println!("Connecting link cable to {}...", addr);
+                TcpLink::connect(addr.as_str(), Duration::from_secs(10))
+            }
+        };
+        match result {
+            Ok(link) => {
+                println!("Link cable connected");
+                Some(Box::new(link))
+            }
+            Err(e) => {
+                eprintln!("Link cable unavailable: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Loads the ROM on the hardware model configured for it, running the matching boot ROM
+/// first when one is configured.
+pub fn construct_cpu(filename: &str) -> Option<(Box<Device>, bool)> {
     let rom_path = std::path::Path::new(filename);
     let save_state_path = rom_path.with_extension("state");
     let save_state_str = save_state_path.to_string_lossy().to_string();
-    // Try CGB first, fallback to classic
-    match Device::new_cgb(filename, false, Some(save_state_str.clone())) {
-        Ok(cpu) => {
-            let is_color = cpu.is_cgb_mode();
-            Some((Box::new(cpu), is_color))
-        }
-        Err(_) => match Device::new(filename, false, Some(save_state_str)) {
-            Ok(cpu) => {
-                let is_color = cpu.is_cgb_mode();
-                Some((Box::new(cpu), is_color))
-            }
+    let cfg = crate::config::Config::load(&crate::config::config_path());
+    let model = cfg.hardware_model_for(rom_path);
+    let boot_rom_path = if model.is_cgb() { &cfg.cgb_boot_rom } else { &cfg.dmg_boot_rom };
+    let boot_rom = match boot_rom_path.as_ref().filter(|_| !cfg.skip_boot_rom) {
+        Some(path) => match BootRom::load(std::path::Path::new(path)) {
+            Ok(boot_rom) => Some(boot_rom),
             Err(msg) => {
-                eprintln!("{}", msg);
+                eprintln!("Not using boot ROM {}: {}", path, msg);
                 None
             }
         },
+        None => None,
+    };
+    match Device::new_with_model(filename, model, boot_rom, false, Some(save_state_str)) {
+        Ok(mut cpu) => {
+            if cfg.rtc_catch_up {
+                cpu.catch_up_rtc();
+            }
+            // The SGB colors the picture itself, so DMG palette presets don't apply.
+            let is_color = cpu.is_cgb_mode() || model == HardwareModel::Sgb;
+            Some((Box::new(cpu), is_color))
+        }
+        Err(msg) => {
+            eprintln!("Could not run on {}: {}", model.name(), msg);
+            None
+        }
+    }
+}
+
+const DISASSEMBLY_LINES: usize = 16;
+
+fn debug_snapshot(cpu: &mut Device) -> DebugSnapshot {
+    let registers = cpu.registers();
+    DebugSnapshot {
+        registers,
+        rom_bank: cpu.rom_bank(registers.pc),
+        stop: cpu.debug_stop_reason(),
+        breakpoints: cpu.breakpoints().to_vec(),
+        watchpoints: cpu.watchpoints().to_vec(),
+        disassembly: cpu.disassemble(registers.pc, DISASSEMBLY_LINES),
     }
 }
 
+// Copies the current frame into a free (uniquely owned) buffer and sends it, dropping the
+// frame if the receiver is busy. Returns false once the receiver is gone.
+fn send_frame(
+    cpu: &Device,
+    sender: &SyncSender<Arc<Vec<u8>>>,
+    frame_buffers: &mut [Arc<Vec<u8>>; 2],
+    next_fb: &mut usize,
+) -> bool {
+    for attempt in 0..frame_buffers.len() {
+        let idx = (*next_fb + attempt) % frame_buffers.len();
+        if let Some(buf_mut) = Arc::get_mut(&mut frame_buffers[idx]) {
+            // Safe to mutate this buffer: no other references. Loading a state from another
+            // hardware model can change the frame size.
+            buf_mut.clear();
+            buf_mut.extend_from_slice(cpu.display_data());
+            match sender.try_send(frame_buffers[idx].clone()) {
+                Ok(_) => *next_fb = (idx + 1) % frame_buffers.len(),
+                Err(TrySendError::Disconnected(..)) => return false,
+                Err(TrySendError::Full(_)) => { /* Drop frame if receiver busy */ }
+            }
+            break;
+        }
+    }
+    true
+}
+
 // Runs the emulation core loop. Sends video frames through a bounded channel.
 pub fn run_cpu(
     mut cpu: Box<Device>,
@@ -72,54 +240,94 @@ pub fn run_cpu(
     let mut frame_count = 0;
     let mut last_ram_save_frame = 0;
     let mut ram_needs_save = false;
+    let mut ram_save_failing = false;
 
     // Two reusable frame buffers; we only write to a buffer if it is uniquely held (strong_count==1).
-    let frame_len = cpu.get_gpu_data().len();
+    let frame_len = cpu.display_data().len();
     let mut frame_buffers = [
         Arc::new(vec![0u8; frame_len]),
         Arc::new(vec![0u8; frame_len]),
     ];
     let mut next_fb = 0usize;
 
+    let mut debug_stopped = false;
+    let mut rewinding = false;
+    let mut movie = MovieSession::Idle;
+    let mut rewind = RewindBuffer::new(0);
+    // The link cable unplugged to make room for the printer, put back when it is removed.
+    let mut printer_attached = false;
+    let mut parked_link: Option<Box<dyn rust_gbe::SerialLink>> = None;
+
     'outer: loop {
         // Always execute at least one frame worth of cycles (unless paused).
         let frame_target = base_waitticks;
-        while !paused && ticks < frame_target {
-            ticks += cpu.do_cycle();
-            if cpu.check_and_reset_gpu_updated() {
-                // Try to find a free (uniquely owned) buffer to copy into.
-                for attempt in 0..frame_buffers.len() {
-                    let idx = (next_fb + attempt) % frame_buffers.len();
-                    if let Some(buf_mut) = Arc::get_mut(&mut frame_buffers[idx]) {
-                        // Safe to mutate this buffer: no other references.
-                        let src = cpu.get_gpu_data();
-                        buf_mut.copy_from_slice(src);
-                        match sender.try_send(frame_buffers[idx].clone()) {
-                            Ok(_) => {
-                                next_fb = (idx + 1) % frame_buffers.len();
-                            }
-                            Err(TrySendError::Disconnected(..)) => {
-                                break 'outer;
-                            }
-                            Err(TrySendError::Full(_)) => { /* Drop frame if receiver busy */ }
-                        }
-                        break;
-                    }
-                }
+        while !paused && !rewinding && ticks < frame_target && cpu.debug_stop_reason().is_none() {
+            ticks += movie.do_cycle(&mut cpu);
+            if cpu.check_and_reset_gpu_updated()
+                && !send_frame(&cpu, &sender, &mut frame_buffers, &mut next_fb)
+            {
+                break 'outer;
             }
         }
-        if !paused {
+        // Once the history is exhausted, hold on the oldest state until the key is released.
+        if rewinding && let Some(state) = rewind.pop() {
+            if let Err(e) = cpu.restore_snapshot(&state) {
+                eprintln!("Failed to rewind: {}", e);
+            }
+            if !send_frame(&cpu, &sender, &mut frame_buffers, &mut next_fb) {
+                break 'outer;
+            }
+        }
+        if let MovieSession::Playing(player) = &movie
+            && player.is_finished()
+        {
+            match player.verify(&cpu) {
+                Ok(()) => println!("Movie finished; playback matched the recording"),
+                Err(e) => eprintln!("Movie finished: {}", e),
+            }
+            if let MovieSession::Playing(player) = std::mem::replace(&mut movie, MovieSession::Idle) {
+                player.finish(&mut cpu);
+            }
+        }
+
+        let stopped = cpu.debug_stop_reason().is_some();
+        if stopped && !debug_stopped {
+            cpu.sync_audio();
+            let _ = ui_sender.send(GuiEvent::DebugState(debug_snapshot(&mut cpu)));
+        }
+        debug_stopped = stopped;
+
+        if !paused && !stopped && !rewinding {
             ticks -= frame_target;
             frame_count += 1;
 
-            if cpu.check_and_reset_ram_updated() {
-                if cpu.save_battery_ram_silent().is_ok() {}
-                ram_needs_save = false;
-                last_ram_save_frame = frame_count;
+            if frame_count % REWIND_INTERVAL_FRAMES == 0 && rewind.capacity() > 0 {
+                match cpu.snapshot() {
+                    Ok(state) => rewind.push(state),
+                    Err(e) => eprintln!("Failed to record rewind snapshot: {}", e),
+                }
+            }
+
+            if cpu.check_and_reset_ram_updated() && !matches!(movie, MovieSession::Playing(_)) {
+                ram_needs_save = true;
             }
-            if AUTO_SAVE_ENABLED && ram_needs_save && (frame_count - last_ram_save_frame) > 180 {
-                if cpu.save_battery_ram_silent().is_ok() {
-                    ram_needs_save = false;
+            if ram_needs_save && frame_count - last_ram_save_frame >= RAM_SAVE_INTERVAL_FRAMES {
+                last_ram_save_frame = frame_count;
+                match cpu.save_battery_ram_periodic() {
+                    Ok(()) => {
+                        ram_needs_save = false;
+                        ram_save_failing = false;
+                    }
+                    // Retried every interval, but only reported once until it works again.
+                    Err(e) if !ram_save_failing => {
+                        ram_save_failing = true;
+                        eprintln!("Failed to write game save: {}", e);
+                        let _ = ui_sender.send(GuiEvent::SaveError {
+                            message: format!("Could not write the game save: {}", e),
+                            kept_previous: true,
+                        });
+                    }
+                    Err(_) => {}
                 }
             }
         } else {
@@ -130,8 +338,49 @@ pub fn run_cpu(
         'recv: loop {
             match receiver.try_recv() {
                 Ok(ev) => match ev {
-                    GBEvent::KeyUp(k) => cpu.keyup(k),
-                    GBEvent::KeyDown(k) => cpu.keydown(k),
+                    GBEvent::KeyUp(k) => match &mut movie {
+                        MovieSession::Idle => cpu.keyup(k),
+                        MovieSession::Recording(recorder, _) => recorder.keyup(&mut cpu, k),
+                        MovieSession::Playing(_) => {}
+                    },
+                    GBEvent::KeyDown(k) => match &mut movie {
+                        MovieSession::Idle => cpu.keydown(k),
+                        MovieSession::Recording(recorder, _) => recorder.keydown(&mut cpu, k),
+                        MovieSession::Playing(_) => {}
+                    },
+                    GBEvent::Tilt(x, y) => match &mut movie {
+                        MovieSession::Idle => cpu.set_tilt(x, y),
+                        MovieSession::Recording(recorder, _) => recorder.set_tilt(&mut cpu, x, y),
+                        MovieSession::Playing(_) => {}
+                    },
+                    // The movie header holds the image it started with; a new one would desync it.
+                    GBEvent::SetCameraImage(image) => match movie {
+                        MovieSession::Idle => cpu.set_camera_image(&image),
+                        _ => eprintln!("The camera image cannot be changed while a movie is recording or playing"),
+                    },
+                    GBEvent::ImportSave(path) => {
+                        match cpu.import_battery_ram(&path) {
+                            Ok(()) => {
+                                let _ = ui_sender.send(GuiEvent::SaveImported);
+                            }
+                            Err(e) => {
+                                eprintln!("Could not import {}: {}", path.display(), e);
+                                let _ = ui_sender.send(GuiEvent::SaveError {
+                                    message: format!("Could not import {}: {}", path.display(), e),
+                                    kept_previous: false,
+                                });
+                            }
+                        }
+                    }
+                    GBEvent::ExportSave(path) => {
+                        if let Err(e) = cpu.export_battery_ram(&path) {
+                            eprintln!("Could not export {}: {}", path.display(), e);
+                            let _ = ui_sender.send(GuiEvent::SaveError {
+                                message: format!("Could not export {}: {}", path.display(), e),
+                                kept_previous: false,
+                            });
+                        }
+                    }
                     GBEvent::SpeedUp => limit_speed = false,
                     GBEvent::SpeedDown => {
                         limit_speed = true;
@@ -147,9 +396,16 @@ pub fn run_cpu(
                             Err(e) => {
                                 eprintln!("Failed to save state to slot {}: {}", slot, e);
                                 let _ = ui_sender.send(GuiEvent::SaveStateFailed { slot });
+                                let _ = ui_sender.send(GuiEvent::SaveError {
+                                    message: format!("Could not save state to slot {}: {}", slot, e),
+                                    kept_previous: true,
+                                });
                             }
                         }
                     }
+                    GBEvent::LoadState(_) if !matches!(movie, MovieSession::Idle) => {
+                        eprintln!("Cannot load a state while a movie is recording or playing");
+                    }
                     GBEvent::LoadState(s) => {
                         println!("Attempting to load state from slot {}...", s);
                         if let Err(e) = cpu.load_state_slot(s) {
@@ -168,6 +424,90 @@ pub fn run_cpu(
                         }
                         paused = p;
                     }
+                    // Rewinding would desynchronize a movie from its inputs.
+                    GBEvent::Rewind(_) if !matches!(movie, MovieSession::Idle) => {}
+                    GBEvent::Rewind(held) => {
+                        if held && !rewinding {
+                            cpu.sync_audio();
+                        }
+                        rewinding = held;
+                    }
+                    GBEvent::UpdateRewindLength(seconds) => {
+                        rewind.set_capacity(rewind_capacity(seconds));
+                    }
+                    // Movies replay on the renderer they were recorded with.
+                    GBEvent::SetRenderer(renderer) => match movie {
+                        MovieSession::Idle => cpu.set_renderer(renderer),
+                        _ => eprintln!("The renderer cannot be changed while a movie is recording or playing"),
+                    },
+                    GBEvent::SetPrinter(Some(rom_path)) => {
+                        if !printer_attached {
+                            parked_link = cpu.unplug_serial_link();
+                            printer_attached = true;
+                        }
+                        cpu.set_serial_link(Box::new(Printer::new(rom_path)));
+                    }
+                    GBEvent::SetPrinter(None) => {
+                        if printer_attached {
+                            cpu.unplug_serial_link();
+                            if let Some(link) = parked_link.take() {
+                                cpu.set_serial_link(link);
+                            }
+                            printer_attached = false;
+                        }
+                    }
+                    GBEvent::Debug(cmd) => {
+                        match cmd {
+                            DebugCommand::Break => cpu.debug_break(),
+                            DebugCommand::Continue => cpu.debug_continue(),
+                            DebugCommand::Step => cpu.debug_step(),
+                            DebugCommand::StepOver => cpu.debug_step_over(),
+                            DebugCommand::StepOut => cpu.debug_step_out(),
+                            DebugCommand::AddBreakpoint(bp) => cpu.add_breakpoint(bp),
+                            DebugCommand::RemoveBreakpoint(bp) => cpu.remove_breakpoint(bp),
+                            DebugCommand::AddWatchpoint(wp) => cpu.add_watchpoint(wp),
+                            DebugCommand::RemoveWatchpoint(wp) => cpu.remove_watchpoint(wp),
+                            DebugCommand::SetRegisters(regs) => cpu.set_registers(&regs),
+                            DebugCommand::Refresh => {}
+                        }
+                        let _ = ui_sender.send(GuiEvent::DebugState(debug_snapshot(&mut cpu)));
+                    }
+                    GBEvent::StartRecording { path, power_on } => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs());
+                        let recorder = if power_on {
+                            Ok(MovieRecorder::from_power_on(&mut cpu, DEFAULT_WRAM_SEED, now))
+                        } else {
+                            MovieRecorder::from_state(&mut cpu, now)
+                        };
+                        match recorder {
+                            Ok(recorder) => {
+                                println!("Recording movie to {}", path.display());
+                                movie = MovieSession::Recording(recorder, path);
+                            }
+                            Err(e) => eprintln!("Failed to start recording: {}", e),
+                        }
+                    }
+                    GBEvent::StopRecording => {
+                        if let MovieSession::Recording(recorder, path) =
+                            std::mem::replace(&mut movie, MovieSession::Idle)
+                        {
+                            match recorder.finish(&mut cpu).save(&path) {
+                                Ok(()) => println!("Movie saved to {}", path.display()),
+                                Err(e) => eprintln!("Failed to save movie: {}", e),
+                            }
+                        }
+                    }
+                    GBEvent::PlayMovie(m) => {
+                        match MoviePlayer::new(*m, &mut cpu) {
+                            Ok(player) => {
+                                println!("Playing movie");
+                                movie = MovieSession::Playing(player);
+                            }
+                            Err(e) => eprintln!("Failed to play movie: {}", e),
+                        }
+                    }
                     GBEvent::Shutdown => {
                         break 'outer;
                     }
@@ -178,7 +518,7 @@ pub fn run_cpu(
         }
 
         // Timing / pacing
-        let target_frame_ms = if paused {
+        let target_frame_ms = if paused || debug_stopped || rewinding {
             16.0 // keep checking events at ~60 Hz while paused
         } else if limit_speed {
             16.0 // baseline ~60 FPS
@@ -204,4 +544,15 @@ pub fn run_cpu(
         }
         last_frame_instant = Instant::now();
     }
+
+    // Written here rather than on drop so a failure reaches the GUI, which stays open
+    // across a reset or ROM change.
+    let exit_saves = [
+        cpu.save_battery_ram_on_exit().map_err(|e| format!("Could not write the game save: {}", e)),
+        cpu.save_state_on_exit().map_err(|e| format!("Could not write the exit save state: {}", e)),
+    ];
+    for message in exit_saves.into_iter().filter_map(Result::err) {
+        eprintln!("{}", message);
+        let _ = ui_sender.send(GuiEvent::SaveError { message, kept_previous: true });
+    }
 }
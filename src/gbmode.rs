@@ -10,3 +10,46 @@ pub enum GbSpeed {
     Single = 1,
     Double = 2,
 }
+
+/// The console being emulated. The CGB and AGB run DMG games in `GbMode::ColorAsClassic`.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum HardwareModel {
+    Dmg,
+    Mgb,
+    Sgb,
+    #[default]
+    Cgb,
+    Agb,
+}
+
+impl HardwareModel {
+    pub const ALL: [HardwareModel; 5] = [
+        HardwareModel::Dmg,
+        HardwareModel::Mgb,
+        HardwareModel::Sgb,
+        HardwareModel::Cgb,
+        HardwareModel::Agb,
+    ];
+
+    /// True for the models with Game Boy Color hardware.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, HardwareModel::Cgb | HardwareModel::Agb)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HardwareModel::Dmg => "DMG",
+            HardwareModel::Mgb => "MGB",
+            HardwareModel::Sgb => "SGB",
+            HardwareModel::Cgb => "CGB",
+            HardwareModel::Agb => "AGB",
+        }
+    }
+
+    /// Parses a name as returned by `name`, ignoring case.
+    pub fn from_name(name: &str) -> Option<HardwareModel> {
+        HardwareModel::ALL
+            .into_iter()
+            .find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+}
@@ -0,0 +1,471 @@
+//! GDB remote serial protocol server, so external debuggers can drive a `Device`.
+//!
+//! Registers are exposed as six 16-bit little-endian values: AF, BC, DE, HL, SP, PC.
+//! The layout is also described to the client through `target.xml`.
+use crate::debugger::{Breakpoint, CpuRegisters, StopReason, WatchKind, Watchpoint};
+use crate::device::Device;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+// Ticks run between checks for an interrupt (Ctrl-C) from the client; one frame.
+const POLL_TICKS: u64 = 70224;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.cpu">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// How a debugging session ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// The client detached; the device keeps running.
+    Detached,
+    /// The client asked to kill the target.
+    Killed,
+}
+
+enum Command {
+    Reply(String),
+    Resume { step: bool },
+    End(SessionEnd),
+}
+
+pub struct GdbServer {
+    stream: TcpStream,
+    pending: VecDeque<u8>,
+    no_ack: bool,
+    last_reply: String,
+}
+
+impl GdbServer {
+    /// Waits for one client to connect on `addr`.
+    pub fn accept(addr: impl ToSocketAddrs) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Ok(GdbServer::from_stream(stream))
+    }
+
+    pub fn from_stream(stream: TcpStream) -> GdbServer {
+        let _ = stream.set_nodelay(true);
+        GdbServer {
+            stream,
+            pending: VecDeque::new(),
+            no_ack: false,
+            last_reply: String::new(),
+        }
+    }
+
+    /// Serves the client until it detaches or kills the target. The device is stopped
+    /// on entry and only runs while the client has it continuing or stepping.
+    pub fn run(&mut self, device: &mut Device) -> io::Result<SessionEnd> {
+        device.debug_break();
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                continue;
+            };
+            match self.handle(device, &packet) {
+                Command::Reply(reply) => {
+                    self.send(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        self.no_ack = true;
+                    }
+                }
+                Command::Resume { step } => {
+                    let reply = self.resume(device, step)?;
+                    self.send(&reply)?;
+                }
+                Command::End(end) => {
+                    if end == SessionEnd::Detached {
+                        self.send("OK")?;
+                        device.debug_continue();
+                    }
+                    return Ok(end);
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, device: &mut Device, packet: &str) -> Command {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        let reply = match cmd {
+            "?" => stop_reply(device.debug_stop_reason()),
+            "g" => registers_to_hex(&device.registers()),
+            "G" => match registers_from_hex(args, device.registers()) {
+                Some(regs) => {
+                    device.set_registers(&regs);
+                    "OK".into()
+                }
+                None => "E01".into(),
+            },
+            "p" => match parse_hex(args).map(|n| n as usize) {
+                Some(n) if n < 6 => registers_to_hex(&device.registers())[n * 4..n * 4 + 4].into(),
+                _ => "E01".into(),
+            },
+            "P" => {
+                let write = args.split_once('=').and_then(|(n, value)| {
+                    let mut hex = registers_to_hex(&device.registers());
+                    let n = parse_hex(n)? as usize;
+                    if n >= 6 || value.len() != 4 {
+                        return None;
+                    }
+                    hex.replace_range(n * 4..n * 4 + 4, value);
+                    registers_from_hex(&hex, device.registers())
+                });
+                match write {
+                    Some(regs) => {
+                        device.set_registers(&regs);
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((address, len)) => (0..len)
+                    .map(|i| format!("{:02x}", device.read_memory(address.wrapping_add(i))))
+                    .collect(),
+                None => "E01".into(),
+            },
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (address, len) = parse_range(range)?;
+                    let bytes = decode_hex(data)?;
+                    (bytes.len() == len as usize).then_some((address, bytes))
+                });
+                match write {
+                    Some((address, bytes)) => {
+                        for (i, b) in bytes.into_iter().enumerate() {
+                            device.write_memory(address.wrapping_add(i as u16), b);
+                        }
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "c" | "s" => {
+                if let Some(pc) = parse_hex(args) {
+                    let mut regs = device.registers();
+                    regs.pc = pc;
+                    device.set_registers(&regs);
+                }
+                return Command::Resume { step: cmd == "s" };
+            }
+            "Z" | "z" => set_breakpoint(device, args, cmd == "Z"),
+            "D" => return Command::End(SessionEnd::Detached),
+            "k" => return Command::End(SessionEnd::Killed),
+            "H" | "T" => "OK".into(),
+            _ => query(packet),
+        };
+        Command::Reply(reply)
+    }
+
+    fn resume(&mut self, device: &mut Device, step: bool) -> io::Result<String> {
+        if step {
+            device.debug_step();
+        } else {
+            device.debug_continue();
+        }
+        loop {
+            if let Some(reason) = device.run_until_stop(POLL_TICKS) {
+                return Ok(stop_reply(Some(reason)));
+            }
+            if self.poll_interrupt()? {
+                device.debug_break();
+                return Ok(stop_reply(device.debug_stop_reason()));
+            }
+        }
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let checksum = reply.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", reply, checksum)?;
+        self.stream.flush()?;
+        self.last_reply = reply.to_string();
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        if let Some(b) = self.pending.pop_front() {
+            return Ok(b);
+        }
+        let mut b = [0u8; 1];
+        self.stream.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    /// Reads one packet. Returns None for acknowledgements and stray bytes.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        match self.read_byte()? {
+            b'$' => {}
+            b'-' => {
+                let reply = std::mem::take(&mut self.last_reply);
+                self.send(&reply)?;
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        }
+        let mut data = Vec::new();
+        let mut sum = 0u8;
+        loop {
+            let b = self.read_byte()?;
+            if b == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(b);
+            data.push(b);
+        }
+        let checksum = [self.read_byte()?, self.read_byte()?];
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok())
+            == Some(sum);
+        if !self.no_ack {
+            self.stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if !valid {
+            return Ok(None);
+        }
+        let mut unescaped = Vec::with_capacity(data.len());
+        let mut bytes = data.into_iter();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'}' => unescaped.push(bytes.next().unwrap_or(0) ^ 0x20),
+                _ => unescaped.push(b),
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&unescaped).into_owned()))
+    }
+
+    /// Drains whatever the client sent while running; true if it contained Ctrl-C.
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 256];
+        let result = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.pending.extend(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+        match self.pending.iter().position(|&b| b == 0x03) {
+            Some(i) => {
+                self.pending.remove(i);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".into();
+    }
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let Some((offset, len)) = range.split_once(',') else {
+            return "E01".into();
+        };
+        let (Ok(offset), Ok(len)) = (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16))
+        else {
+            return "E01".into();
+        };
+        let start = offset.min(TARGET_XML.len());
+        let end = (start + len).min(TARGET_XML.len());
+        let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+        return format!("{}{}", prefix, &TARGET_XML[start..end]);
+    }
+    match packet {
+        "QStartNoAckMode" => "OK".into(),
+        "qAttached" => "1".into(),
+        "qC" => "QC1".into(),
+        "qfThreadInfo" => "m1".into(),
+        "qsThreadInfo" => "l".into(),
+        _ => String::new(),
+    }
+}
+
+fn set_breakpoint(device: &mut Device, args: &str, insert: bool) -> String {
+    let mut fields = args.split(',');
+    let (Some(kind), Some(address), Some(len)) = (
+        fields.next(),
+        fields.next().and_then(parse_hex),
+        fields.next().and_then(parse_hex),
+    ) else {
+        return "E01".into();
+    };
+    let watch = |kind| Watchpoint {
+        start: address,
+        end: address.saturating_add(len.max(1) - 1),
+        kind,
+    };
+    match (kind, insert) {
+        ("0" | "1", true) => device.add_breakpoint(Breakpoint { address, bank: None }),
+        ("0" | "1", false) => device.remove_breakpoint(Breakpoint { address, bank: None }),
+        ("2", true) => device.add_watchpoint(watch(WatchKind::Write)),
+        ("2", false) => device.remove_watchpoint(watch(WatchKind::Write)),
+        ("3", true) => device.add_watchpoint(watch(WatchKind::Read)),
+        ("3", false) => device.remove_watchpoint(watch(WatchKind::Read)),
+        ("4", true) => device.add_watchpoint(watch(WatchKind::ReadWrite)),
+        ("4", false) => device.remove_watchpoint(watch(WatchKind::ReadWrite)),
+        _ => return String::new(),
+    }
+    "OK".into()
+}
+
+fn stop_reply(reason: Option<StopReason>) -> String {
+    match reason {
+        Some(StopReason::Break) | None => "S02".into(),
+        Some(StopReason::Breakpoint { .. }) | Some(StopReason::Step) => "S05".into(),
+        Some(StopReason::Watchpoint { address, write, .. }) => {
+            format!("T05{}:{:04x};", if write { "watch" } else { "rwatch" }, address)
+        }
+    }
+}
+
+fn registers_to_hex(regs: &CpuRegisters) -> String {
+    let pairs = [
+        (regs.a, regs.f),
+        (regs.b, regs.c),
+        (regs.d, regs.e),
+        (regs.h, regs.l),
+        ((regs.sp >> 8) as u8, regs.sp as u8),
+        ((regs.pc >> 8) as u8, regs.pc as u8),
+    ];
+    pairs.iter().map(|(hi, lo)| format!("{:02x}{:02x}", lo, hi)).collect()
+}
+
+fn registers_from_hex(hex: &str, mut regs: CpuRegisters) -> Option<CpuRegisters> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != 12 {
+        return None;
+    }
+    let wide = |i: usize| (bytes[i * 2 + 1] as u16) << 8 | bytes[i * 2] as u16;
+    (regs.f, regs.a) = (bytes[0] & 0xF0, bytes[1]);
+    (regs.c, regs.b) = (bytes[2], bytes[3]);
+    (regs.e, regs.d) = (bytes[4], bytes[5]);
+    (regs.l, regs.h) = (bytes[6], bytes[7]);
+    regs.sp = wide(4);
+    regs.pc = wide(5);
+    Some(regs)
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (address, len) = s.split_once(',')?;
+    Some((parse_hex(address)?, parse_hex(len)?))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufRead;
+
+    fn test_device() -> Device {
+        let mut rom = vec![0; 0x8000];
+        let program: &[(usize, &[u8])] = &[
+            (0x100, &[0x00]),             // NOP
+            (0x101, &[0x3E, 0x42]),       // LD A,$42
+            (0x103, &[0xEA, 0x00, 0xC0]), // LD ($C000),A
+            (0x106, &[0x18, 0xFE]),       // JR -2
+        ];
+        for (addr, bytes) in program {
+            rom[*addr..*addr + bytes.len()].copy_from_slice(bytes);
+        }
+        Device::new_from_buffer(rom, true, None).unwrap()
+    }
+
+    struct Client {
+        reader: io::BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, packet: &str) -> String {
+            let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(self.writer, "${}#{:02x}", packet, checksum).unwrap();
+            let mut ack = [0u8; 1];
+            self.reader.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            let mut reply = Vec::new();
+            self.reader.read_until(b'#', &mut reply).unwrap();
+            let mut checksum = [0u8; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+            self.writer.write_all(b"+").unwrap();
+            String::from_utf8(reply[1..reply.len() - 1].to_vec()).unwrap()
+        }
+    }
+
+    #[test]
+    fn session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut device = test_device();
+            let end = GdbServer::from_stream(stream).run(&mut device).unwrap();
+            (end, device.read_memory(0xC001))
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client {
+            reader: io::BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        assert!(client.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+        assert!(client.request("qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+        assert_eq!(client.request("?"), "S02");
+        assert_eq!(&client.request("g")[20..24], "0001");
+
+        assert_eq!(client.request("Z0,103,1"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p5"), "0301");
+        assert_eq!(&client.request("p0")[2..4], "42");
+
+        assert_eq!(client.request("z0,103,1"), "OK");
+        assert_eq!(client.request("Z2,c000,1"), "OK");
+        assert_eq!(client.request("c"), "T05watch:c000;");
+        assert_eq!(client.request("mc000,1"), "42");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p5"), "0601");
+
+        assert_eq!(client.request("Mc001,1:99"), "OK");
+        assert_eq!(client.request("P0=00aa"), "OK");
+        assert_eq!(&client.request("g")[0..4], "00aa");
+        assert_eq!(client.request("D"), "OK");
+        assert_eq!(server.join().unwrap(), (SessionEnd::Detached, 0x99));
+    }
+
+    #[test]
+    fn register_hex_round_trip() {
+        let device = test_device();
+        let regs = device.registers();
+        let hex = registers_to_hex(&regs);
+        assert_eq!(hex.len(), 24);
+        assert_eq!(registers_from_hex(&hex, regs), Some(regs));
+    }
+}
@@ -13,6 +13,63 @@ enum PrioType {
     Normal,
 }
 
+/// How the PPU draws. `Scanline` renders each line at once when mode 3 ends; `Fifo` runs
+/// the pixel fetcher dot by dot, so mid-line register writes and the mode 3 length match
+/// hardware at the cost of speed.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum PpuRenderer {
+    #[default]
+    Scanline,
+    Fifo,
+}
+
+#[derive(Copy, Clone, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct BgPixel {
+    color: u8,
+    palette: u8,
+    priority: bool,
+}
+
+#[derive(Copy, Clone, Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct ObjPixel {
+    // 0 is transparent
+    color: u8,
+    // CGB palette number, or OBP0/OBP1 on DMG
+    palette: u8,
+    below_bg: bool,
+    oam_index: u8,
+}
+
+// State of the pixel FIFO renderer during mode 3.
+#[derive(Default, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct PixelFifo {
+    // Pixels are only pushed once the BG FIFO is empty, so it never holds more than a tile.
+    bg: [BgPixel; 8],
+    bg_pos: u8,
+    obj: [ObjPixel; 8],
+    // Fetcher: 2 dots each for tile number, low and high byte, then a push attempt.
+    step: u8,
+    fetch_x: u8,
+    window: bool,
+    tile_addr: u16,
+    tile_attr: u8,
+    tile_lo: u8,
+    tile_hi: u8,
+    // Dots where the fetcher idles, e.g. the discarded first fetch of the line.
+    stall: u8,
+    // Pixels shifted out without drawing: SCX fine scroll, or WX < 7.
+    discard: u8,
+    // Sprites on this line as (OAM X, OAM index) in fetch order.
+    sprites: [(u8, u8); 10],
+    sprite_count: u8,
+    next_sprite: u8,
+    // Remaining dots of an in-progress sprite fetch; 0 when none.
+    sprite_fetch: u8,
+    // BG tile column the last sprite was fetched over.
+    sprite_tile: Option<u8>,
+    lx: u8,
+}
+
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct GPU {
     mode: u8,
@@ -61,8 +118,14 @@ pub struct GPU {
     pub updated: bool,
     pub interrupt: u8,
     pub gbmode: GbMode,
+    // ColorAsClassic after a CGB boot ROM: the DMG palettes index into CGB palette RAM.
+    pub compat_palettes: bool,
     hblanking: bool,
     first_frame: bool,
+    stat_line: bool,
+    fifo: PixelFifo,
+    #[rkyv(with = rkyv::with::Skip)]
+    pub renderer: PpuRenderer,
 }
 
 impl GPU {
@@ -104,6 +167,7 @@ impl GPU {
             updated: false,
             interrupt: 0,
             gbmode: GbMode::Classic,
+            compat_palettes: false,
             cbgpal_inc: false,
             cbgpal_ind: 0,
             cbgpal: [[[0u8; 3]; 4]; 8],
@@ -113,6 +177,9 @@ impl GPU {
             vrambank: 0,
             hblanking: false,
             first_frame: false,
+            stat_line: false,
+            fifo: PixelFifo::default(),
+            renderer: PpuRenderer::Scanline,
         }
     }
 
@@ -125,6 +192,13 @@ impl GPU {
             return;
         }
         self.hblanking = false;
+        if self.renderer == PpuRenderer::Fifo {
+            for _ in 0..ticks {
+                self.fifo_dot();
+                self.update_stat_line();
+            }
+            return;
+        }
 
         let mut ticksleft = ticks;
 
@@ -137,7 +211,6 @@ impl GPU {
             if self.modeclock >= 456 {
                 self.modeclock -= 456;
                 self.line = (self.line + 1) % 154;
-                self.check_interrupt_lyc();
 
                 // This is a VBlank line
                 if self.line >= 144 && self.mode != 1 {
@@ -163,23 +236,43 @@ impl GPU {
                     }
                 }
             }
+            self.update_stat_line();
         }
     }
 
-    fn check_interrupt_lyc(&mut self) {
-        if self.lyc_inte && self.line == self.lyc {
+    // LY as the CPU sees it: line 153 reads as 0 after its first 4 dots.
+    fn ly(&self) -> u8 {
+        if self.line == 153 && self.modeclock >= 4 { 0 } else { self.line }
+    }
+
+    // The enabled STAT sources are ORed into one line; the interrupt is requested on its
+    // rising edge only, so a source becoming active while another already holds the line
+    // high is blocked. The mode 2 source also fires when line 144 enters VBlank.
+    fn stat_condition(&self) -> bool {
+        self.lcd_on
+            && ((self.lyc_inte && self.ly() == self.lyc)
+                || (self.m0_inte && self.mode == 0)
+                || (self.m1_inte && self.mode == 1)
+                || (self.m2_inte && (self.mode == 2 || (self.mode == 1 && self.line == 144))))
+    }
+
+    fn update_stat_line(&mut self) {
+        let line = self.stat_condition();
+        if line && !self.stat_line {
             self.interrupt |= 0x02;
         }
+        self.stat_line = line;
     }
 
     fn change_mode(&mut self, mode: u8) {
         self.mode = mode;
 
-        if match self.mode {
+        match self.mode {
             0 => {
-                self.renderscan();
+                if self.renderer == PpuRenderer::Scanline {
+                    self.renderscan();
+                }
                 self.hblanking = true;
-                self.m0_inte
             }
             1 => {
                 // Vertical blank
@@ -189,19 +282,17 @@ impl GPU {
                 self.front ^= 1;
                 self.updated = true;
                 self.first_frame = false;
-                self.m1_inte
             }
-            2 => self.m2_inte,
             3 => {
                 if self.win_on && self.wy_trigger == false && self.line == self.winy {
                     self.wy_trigger = true;
                     self.wy_pos = -1;
                 }
-                false
+                if self.renderer == PpuRenderer::Fifo {
+                    self.start_fifo_line();
+                }
             }
-            _ => false,
-        } {
-            self.interrupt |= 0x02;
+            _ => {}
         }
     }
 
@@ -224,12 +315,12 @@ impl GPU {
                     | (if self.m2_inte { 0x20 } else { 0 })
                     | (if self.m1_inte { 0x10 } else { 0 })
                     | (if self.m0_inte { 0x08 } else { 0 })
-                    | (if self.line == self.lyc { 0x04 } else { 0 })
+                    | (if self.ly() == self.lyc { 0x04 } else { 0 })
                     | self.mode
             }
             0xFF42 => self.scy,
             0xFF43 => self.scx,
-            0xFF44 => self.line,
+            0xFF44 => self.ly(),
             0xFF45 => self.lyc,
             0xFF46 => 0, // Write only
             0xFF47 => self.palbr,
@@ -300,6 +391,7 @@ impl GPU {
                     self.mode = 0;
                     self.wy_trigger = false;
                     self.first_frame = true;
+                    self.stat_line = false;
                     self.clear_screen();
                 }
                 if !orig_lcd_on && self.lcd_on {
@@ -308,17 +400,29 @@ impl GPU {
                 }
             }
             0xFF41 => {
+                // DMG bug: for one cycle during the write every source is enabled, so any
+                // write outside mode 3 (or while LY=LYC) requests an interrupt.
+                if self.gbmode == GbMode::Classic
+                    && self.lcd_on
+                    && (self.mode != 3 || self.ly() == self.lyc)
+                {
+                    if !self.stat_line {
+                        self.interrupt |= 0x02;
+                    }
+                    self.stat_line = true;
+                }
                 self.lyc_inte = v & 0x40 == 0x40;
                 self.m2_inte = v & 0x20 == 0x20;
                 self.m1_inte = v & 0x10 == 0x10;
                 self.m0_inte = v & 0x08 == 0x08;
+                self.update_stat_line();
             }
             0xFF42 => self.scy = v,
             0xFF43 => self.scx = v,
             0xFF44 => {} // Read-only
             0xFF45 => {
                 self.lyc = v;
-                self.check_interrupt_lyc();
+                self.update_stat_line();
             }
             0xFF46 => panic!("0xFF46 should be handled by MMU"),
             0xFF47 => {
@@ -505,12 +609,7 @@ impl GPU {
                 (0, false, false, false, false)
             };
 
-            let tileaddress = self.tilebase
-                + (if self.tilebase == 0x8000 {
-                    tilenr as u16
-                } else {
-                    (tilenr as i8 as i16 + 128) as u16
-                }) * 16;
+            let tileaddress = self.tile_address(tilenr);
 
             let a0 = match yflip {
                 false => tileaddress + (pixely * 2),
@@ -541,6 +640,9 @@ impl GPU {
                 let g = self.cbgpal[palnr][colnr][1];
                 let b = self.cbgpal[palnr][colnr][2];
                 self.setrgb(x as usize, r, g, b);
+            } else if self.compat_palettes {
+                let [r, g, b] = self.cbgpal[0][(self.palbr >> (colnr * 2)) as usize & 0x03];
+                self.setrgb(x, r, g, b);
             } else {
                 let color = self.palb[colnr];
                 self.setcolor(x, color);
@@ -634,6 +736,12 @@ impl GPU {
                     if belowbg && self.bgprio[(spritex + x) as usize] != PrioType::Color0 {
                         continue 'xloop;
                     }
+                    if self.compat_palettes {
+                        let (palnr, palr) = if usepal1 { (1, self.pal1r) } else { (0, self.pal0r) };
+                        let [r, g, b] = self.csprit[palnr][(palr >> (colnr * 2)) as usize & 0x03];
+                        self.setrgb((spritex + x) as usize, r, g, b);
+                        continue 'xloop;
+                    }
                     let color = if usepal1 {
                         self.pal1[colnr]
                     } else {
@@ -645,6 +753,318 @@ impl GPU {
         }
     }
 
+    pub fn set_renderer(&mut self, renderer: PpuRenderer) {
+        if renderer == PpuRenderer::Fifo && self.renderer != renderer && self.mode == 3 {
+            self.start_fifo_line();
+        }
+        self.renderer = renderer;
+    }
+
+    // One dot of the FIFO renderer. Mode 2 lasts 80 dots and mode 3 until the 160th pixel
+    // is shifted out; mode 0 fills the rest of the 456 dot line.
+    fn fifo_dot(&mut self) {
+        self.modeclock += 1;
+        if self.modeclock >= 456 {
+            self.modeclock -= 456;
+            self.line = (self.line + 1) % 154;
+            if self.line >= 144 && self.mode != 1 {
+                self.change_mode(1);
+            }
+        }
+        if self.line >= 144 {
+            return;
+        }
+        if (self.mode == 0 || self.mode == 1) && self.modeclock < 80 {
+            self.change_mode(2);
+        }
+        if self.mode == 2 && self.modeclock >= 80 {
+            self.change_mode(3);
+        }
+        if self.mode == 3 {
+            self.fifo_step();
+            if self.fifo.lx as usize >= SCREEN_W {
+                self.change_mode(0);
+            }
+        }
+    }
+
+    // OAM scan and fetcher reset for the line about to be drawn.
+    fn start_fifo_line(&mut self) {
+        let line = self.line as i32;
+        let sprite_size = self.sprite_size as i32;
+        let mut fifo = PixelFifo {
+            bg_pos: 8,
+            // The first tile of each line is fetched twice.
+            stall: 6,
+            discard: self.scx & 0x07,
+            ..PixelFifo::default()
+        };
+        for index in 0..40u8 {
+            let spritey = self.voam[index as usize * 4] as i32 - 16;
+            if line < spritey || line >= spritey + sprite_size {
+                continue;
+            }
+            fifo.sprites[fifo.sprite_count as usize] = (self.voam[index as usize * 4 + 1], index);
+            fifo.sprite_count += 1;
+            if fifo.sprite_count == 10 {
+                break;
+            }
+        }
+        // Stable, so sprites sharing an X stay in OAM order.
+        fifo.sprites[..fifo.sprite_count as usize].sort_by_key(|&(x, _)| x);
+        self.fifo = fifo;
+    }
+
+    fn fifo_step(&mut self) {
+        if !self.fifo.window
+            && self.win_on
+            && self.wy_trigger
+            && self.winx <= 166
+            && self.fifo.lx + 7 >= self.winx
+        {
+            // The BG pixels queued so far are dropped and the fetcher starts over.
+            self.wy_pos += 1;
+            self.fifo.window = true;
+            self.fifo.bg_pos = 8;
+            self.fifo.step = 0;
+            self.fifo.fetch_x = 0;
+            if self.fifo.lx == 0 {
+                self.fifo.discard = 7u8.saturating_sub(self.winx);
+            }
+        }
+
+        self.fetcher_tick();
+
+        if self.fifo.sprite_fetch == 0 && self.fifo.discard == 0 && self.fifo.bg_pos < 8 {
+            self.check_sprite_hit();
+        }
+        if self.fifo.sprite_fetch > 0 {
+            // Pixels stop shifting out while a sprite is fetched.
+            self.fifo.sprite_fetch -= 1;
+            if self.fifo.sprite_fetch == 0 {
+                self.fetch_sprite();
+            }
+            return;
+        }
+        self.shift_pixel();
+    }
+
+    fn check_sprite_hit(&mut self) {
+        let fifo = &mut self.fifo;
+        if !self.sprite_on || fifo.next_sprite == fifo.sprite_count {
+            return;
+        }
+        let (x, _) = fifo.sprites[fifo.next_sprite as usize];
+        if x > fifo.lx + 8 {
+            return;
+        }
+        // 6 dots for the fetch itself, plus up to 5 waiting for the BG fetcher. Only the
+        // first sprite over a BG tile waits; a sprite at X 0 always does.
+        let pos = fifo.lx as u16 + self.scx as u16;
+        let tile = (pos >> 3) as u8;
+        fifo.sprite_fetch = if x == 0 {
+            11
+        } else if fifo.sprite_tile == Some(tile) {
+            6
+        } else {
+            11 - (pos as u8 & 0x07).min(5)
+        };
+        fifo.sprite_tile = Some(tile);
+    }
+
+    fn fetcher_tick(&mut self) {
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return;
+        }
+        match self.fifo.step {
+            1 => self.fetch_tile_number(),
+            3 => self.fifo.tile_lo = self.read_tile_byte(self.fifo.tile_addr),
+            5 => self.fifo.tile_hi = self.read_tile_byte(self.fifo.tile_addr + 1),
+            6 => {
+                if self.fifo.bg_pos < 8 {
+                    return;
+                }
+                self.push_bg_tile();
+                self.fifo.step = 0;
+                self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+                return;
+            }
+            _ => {}
+        }
+        self.fifo.step += 1;
+    }
+
+    fn fetch_tile_number(&mut self) {
+        // SCX (apart from the fine scroll), SCY and the tile maps are read on every fetch.
+        let (tilemapbase, tilex, y) = if self.fifo.window {
+            (self.win_tilemap, self.fifo.fetch_x as u16 & 31, self.wy_pos as u8)
+        } else {
+            (
+                self.bg_tilemap,
+                ((self.scx >> 3) as u16 + self.fifo.fetch_x as u16) & 31,
+                self.scy.wrapping_add(self.line),
+            )
+        };
+        let mapaddr = tilemapbase + ((y as u16 >> 3) & 31) * 32 + tilex;
+        let tilenr = self.rbvram0(mapaddr);
+        let attr = if self.gbmode == GbMode::Color { self.rbvram1(mapaddr) } else { 0 };
+        let pixely = if attr & 0x40 != 0 { 7 - (y as u16 & 0x07) } else { y as u16 & 0x07 };
+        self.fifo.tile_addr = self.tile_address(tilenr) + pixely * 2;
+        self.fifo.tile_attr = attr;
+    }
+
+    fn read_tile_byte(&self, a: u16) -> u8 {
+        if self.fifo.tile_attr & 0x08 != 0 {
+            self.rbvram1(a)
+        } else {
+            self.rbvram0(a)
+        }
+    }
+
+    fn push_bg_tile(&mut self) {
+        let fifo = &mut self.fifo;
+        let attr = fifo.tile_attr;
+        for (i, pixel) in fifo.bg.iter_mut().enumerate() {
+            let bit = if attr & 0x20 != 0 { i } else { 7 - i };
+            *pixel = BgPixel {
+                color: ((fifo.tile_lo >> bit) & 1) | (((fifo.tile_hi >> bit) & 1) << 1),
+                palette: attr & 0x07,
+                priority: attr & 0x80 != 0,
+            };
+        }
+        fifo.bg_pos = 0;
+    }
+
+    // Mixes the next sprite's row into the sprite FIFO, which lines up with the next
+    // pixels to be drawn. Opaque pixels already there win on DMG, where sprites are
+    // fetched in X order; on CGB the lower OAM index wins.
+    fn fetch_sprite(&mut self) {
+        let (x, index) = self.fifo.sprites[self.fifo.next_sprite as usize];
+        self.fifo.next_sprite += 1;
+        let cgb = self.gbmode == GbMode::Color;
+        let spriteaddr = index as usize * 4;
+        let spritey = self.voam[spriteaddr] as i32 - 16;
+        let tilenum = (self.voam[spriteaddr + 2]
+            & (if self.sprite_size == 16 { 0xFE } else { 0xFF })) as u16;
+        let flags = self.voam[spriteaddr + 3];
+        let row = ((self.line as i32 - spritey) & (self.sprite_size as i32 - 1)) as u16;
+        let tiley = if flags & 0x40 != 0 { self.sprite_size as u16 - 1 - row } else { row };
+        let tileaddress = 0x8000 + tilenum * 16 + tiley * 2;
+        let (b1, b2) = if cgb && flags & 0x08 != 0 {
+            (self.rbvram1(tileaddress), self.rbvram1(tileaddress + 1))
+        } else {
+            (self.rbvram0(tileaddress), self.rbvram0(tileaddress + 1))
+        };
+        let palette = if cgb { flags & 0x07 } else { (flags >> 4) & 0x01 };
+        let lx = self.fifo.lx as i32;
+        for i in 0..8 {
+            let slot = x as i32 - 8 + i - lx;
+            if !(0..8).contains(&slot) {
+                continue;
+            }
+            let bit = if flags & 0x20 != 0 { i } else { 7 - i };
+            let color = ((b1 >> bit) & 1) | (((b2 >> bit) & 1) << 1);
+            let current = &mut self.fifo.obj[slot as usize];
+            if color != 0 && (current.color == 0 || (cgb && index < current.oam_index)) {
+                *current = ObjPixel {
+                    color,
+                    palette,
+                    below_bg: flags & 0x80 != 0,
+                    oam_index: index,
+                };
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        if self.fifo.bg_pos >= 8 {
+            return;
+        }
+        let bg = self.fifo.bg[self.fifo.bg_pos as usize];
+        self.fifo.bg_pos += 1;
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj[0];
+        self.fifo.obj.copy_within(1.., 0);
+        self.fifo.obj[7] = ObjPixel::default();
+        let x = self.fifo.lx as usize;
+        self.fifo.lx += 1;
+        if !self.first_frame {
+            self.draw_fifo_pixel(x, bg, obj);
+        }
+    }
+
+    // Palettes and the enable bits in LCDC apply as each pixel is drawn.
+    fn draw_fifo_pixel(&mut self, x: usize, bg: BgPixel, obj: ObjPixel) {
+        let cgb = self.gbmode == GbMode::Color;
+        // On DMG, LCDC bit 0 blanks both background and window.
+        let bg_on = cgb || self.lcdc0;
+        let bg_color = if bg_on { bg.color } else { 0 };
+        let obj_hidden = bg_color != 0
+            && (obj.below_bg || (cgb && bg.priority))
+            && (!cgb || self.lcdc0);
+        if obj.color != 0 && self.sprite_on && !obj_hidden {
+            let palnr = obj.palette as usize;
+            if cgb {
+                let [r, g, b] = self.csprit[palnr][obj.color as usize];
+                self.setrgb(x, r, g, b);
+            } else if self.compat_palettes {
+                let palr = if palnr == 1 { self.pal1r } else { self.pal0r };
+                let [r, g, b] = self.csprit[palnr][(palr >> (obj.color * 2)) as usize & 0x03];
+                self.setrgb(x, r, g, b);
+            } else {
+                let pal = if palnr == 1 { self.pal1 } else { self.pal0 };
+                self.setcolor(x, pal[obj.color as usize]);
+            }
+        } else if cgb {
+            let [r, g, b] = self.cbgpal[bg.palette as usize][bg.color as usize];
+            self.setrgb(x, r, g, b);
+        } else if !bg_on {
+            self.setcolor(x, 255);
+        } else if self.compat_palettes {
+            let [r, g, b] = self.cbgpal[0][(self.palbr >> (bg.color * 2)) as usize & 0x03];
+            self.setrgb(x, r, g, b);
+        } else {
+            self.setcolor(x, self.palb[bg.color as usize]);
+        }
+    }
+
+    fn tile_address(&self, tilenr: u8) -> u16 {
+        self.tilebase
+            + (if self.tilebase == 0x8000 {
+                tilenr as u16
+            } else {
+                (tilenr as i8 as i16 + 128) as u16
+            }) * 16
+    }
+
+    /// The first 256 background tiles on screen in reading order, mapped through BGP:
+    /// the 4 KiB the SGB captures for its VRAM transfer commands.
+    pub fn sgb_transfer_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(0x1000);
+        for i in 0..256u16 {
+            let tilenr = self.rbvram0(self.bg_tilemap + (i / 20) * 32 + i % 20);
+            let tileaddress = self.tile_address(tilenr);
+            for row in 0..8 {
+                let b1 = self.rbvram0(tileaddress + row * 2);
+                let b2 = self.rbvram0(tileaddress + row * 2 + 1);
+                let (mut o1, mut o2) = (0, 0);
+                for bit in 0..8 {
+                    let colnr = ((b1 >> bit) & 1) | (((b2 >> bit) & 1) << 1);
+                    let shade = (self.palbr >> (colnr * 2)) & 0x03;
+                    o1 |= (shade & 1) << bit;
+                    o2 |= (shade >> 1) << bit;
+                }
+                data.push(o1);
+                data.push(o2);
+            }
+        }
+        data
+    }
+
     pub fn may_hdma(&self) -> bool {
         return self.hblanking;
     }
@@ -669,3 +1089,153 @@ fn cgb_sprite_order(a: &(i32, i32, u8), b: &(i32, i32, u8)) -> Ordering {
     // CGB order: only prioritize based on OAM position.
     return b.2.cmp(&a.2);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fifo_gpu() -> GPU {
+        let mut gpu = GPU::new();
+        gpu.set_renderer(PpuRenderer::Fifo);
+        gpu.wb(0xFF40, 0x93);
+        gpu
+    }
+
+    // Runs to line 1 (line 0 starts 4 dots late after the LCD is enabled) and returns how
+    // long mode 3 lasted there.
+    fn mode3_length(gpu: &mut GPU) -> u32 {
+        while gpu.line != 1 || gpu.mode != 3 {
+            gpu.do_cycle(1);
+        }
+        let start = gpu.modeclock;
+        while gpu.mode == 3 {
+            gpu.do_cycle(1);
+        }
+        // Both the first dot and the one drawing the last pixel are in mode 3.
+        gpu.modeclock - start + 1
+    }
+
+    #[test]
+    fn fifo_mode3_length() {
+        assert_eq!(mode3_length(&mut fifo_gpu()), 172);
+
+        let mut gpu = fifo_gpu();
+        gpu.wb(0xFF43, 0x05);
+        assert_eq!(mode3_length(&mut gpu), 172 + 5);
+
+        let mut gpu = fifo_gpu();
+        gpu.wb(0xFF4A, 0);
+        gpu.wb(0xFF4B, 87);
+        gpu.wb(0xFF40, 0xB3);
+        assert_eq!(mode3_length(&mut gpu), 172 + 6);
+
+        // Two sprites over the same tile: the first waits for the BG fetch, the second not.
+        let mut gpu = fifo_gpu();
+        for (i, x) in [24, 26].iter().enumerate() {
+            gpu.wb(0xFE00 + i as u16 * 4, 16);
+            gpu.wb(0xFE01 + i as u16 * 4, *x);
+        }
+        assert_eq!(mode3_length(&mut gpu), 172 + 11 + 6);
+
+        let mut gpu = fifo_gpu();
+        gpu.wb(0xFE00, 16);
+        gpu.wb(0xFE01, 8 + 20);
+        assert_eq!(mode3_length(&mut gpu), 172 + 11 - 4);
+    }
+
+    // Counts STAT interrupts over one frame, starting from the top of line 0.
+    fn stat_interrupts_per_frame(gpu: &mut GPU) -> u32 {
+        while gpu.line != 0 || gpu.mode != 2 {
+            gpu.do_cycle(4);
+        }
+        gpu.interrupt = 0;
+        let mut count = 0;
+        for _ in 0..70224 / 4 {
+            gpu.do_cycle(4);
+            if gpu.interrupt & 0x02 != 0 {
+                count += 1;
+            }
+            gpu.interrupt = 0;
+        }
+        count
+    }
+
+    #[test]
+    fn stat_sources_block_each_other() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x91);
+        gpu.wb(0xFF41, 0x08);
+        assert_eq!(stat_interrupts_per_frame(&mut gpu), 144);
+        // HBlank runs straight into the next line's OAM scan, so mode 2 only adds the
+        // rise after VBlank.
+        gpu.wb(0xFF41, 0x28);
+        assert_eq!(stat_interrupts_per_frame(&mut gpu), 145);
+        // LY=LYC holds the line high from line 10's start through its HBlank, so neither
+        // it nor that HBlank raise an interrupt.
+        gpu.wb(0xFF45, 10);
+        gpu.wb(0xFF41, 0x48);
+        assert_eq!(stat_interrupts_per_frame(&mut gpu), 143);
+        gpu.wb(0xFF41, 0x40);
+        assert_eq!(stat_interrupts_per_frame(&mut gpu), 1);
+    }
+
+    #[test]
+    fn line_153_reads_as_zero() {
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x91);
+        gpu.wb(0xFF45, 0);
+        gpu.wb(0xFF41, 0x40);
+        while gpu.line != 153 {
+            gpu.do_cycle(4);
+        }
+        gpu.interrupt = 0;
+        gpu.do_cycle(4);
+        assert_eq!(gpu.rb(0xFF44), 0);
+        assert_eq!(gpu.rb(0xFF41) & 0x04, 0x04);
+        assert_eq!(gpu.interrupt & 0x02, 0x02);
+        // Still high when line 0 starts, so no second interrupt.
+        gpu.interrupt = 0;
+        while gpu.line != 0 {
+            gpu.do_cycle(4);
+        }
+        assert_eq!(gpu.interrupt & 0x02, 0);
+    }
+
+    #[test]
+    fn dmg_stat_write_bug() {
+        for (mode, expected) in [(GbMode::Classic, 0x02), (GbMode::Color, 0)] {
+            let mut gpu = GPU::new();
+            gpu.gbmode = mode;
+            gpu.wb(0xFF40, 0x91);
+            gpu.wb(0xFF45, 0xFF);
+            while gpu.mode != 0 {
+                gpu.do_cycle(4);
+            }
+            gpu.interrupt = 0;
+            gpu.wb(0xFF41, 0x00);
+            assert_eq!(gpu.interrupt & 0x02, expected);
+        }
+    }
+
+    #[test]
+    fn fifo_mid_line_palette_write() {
+        let mut gpu = fifo_gpu();
+        for a in 0x8000..0x8010 {
+            gpu.wb(a, 0xFF);
+        }
+        gpu.wb(0xFF47, 0xC0);
+        // Pixel 0 is drawn 12 dots into mode 3; stop once pixel 79 is out.
+        while gpu.line != 1 || gpu.modeclock < 80 + 12 + 79 {
+            gpu.do_cycle(1);
+        }
+        gpu.wb(0xFF47, 0x00);
+        while !gpu.updated {
+            gpu.do_cycle(1);
+        }
+        let line = &gpu.front_buffer()[SCREEN_W * 3..SCREEN_W * 6];
+        assert_eq!(line[0], 0);
+        assert_eq!(line[79 * 3], 0);
+        assert_eq!(line[80 * 3], 255);
+        assert_eq!(line[159 * 3], 255);
+    }
+}
@@ -7,7 +7,11 @@ use std::time::Instant;
 
 use cpal::Stream;
 use glium::Surface;
+use rust_gbe::bootrom::BootRom;
+use rust_gbe::camera::CameraImage;
+use rust_gbe::{HardwareModel, PpuRenderer};
 use rust_gbe::device::{read_save_state_preview, SaveStatePreview};
+use rust_gbe::movie::Movie;
 use time::{Month, OffsetDateTime, UtcOffset};
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
@@ -24,9 +28,10 @@ pub struct RenderOptions {
 }
 
 use crate::audio::init_audio;
+use crate::debugger_ui::DebuggerWindow;
 use crate::config::{binding_value, config_path, Config, DmgPalettePreset, KeyBindings, TurboSetting};
-use crate::emulator::{construct_cpu_auto, run_cpu, GBEvent, GuiEvent};
-use crate::input::is_reserved_key_name;
+use crate::emulator::{construct_cpu, run_cpu, GBEvent, GuiEvent, LinkOption};
+use crate::input::{is_reserved_key_name, TiltInput};
 use crate::palette::{apply_dmg_palette, palette_for_preset, DmgPalette};
 
 struct SaveSlotUi {
@@ -129,6 +134,9 @@ enum RootPhase {
         receiver: Receiver<Arc<Vec<u8>>>,
         ui_receiver: Receiver<GuiEvent>,
         save_slots: SaveSlotCache,
+        // Last failed battery save, save state, import or export, until dismissed, and
+        // whether the previous file was kept.
+        save_error: Option<(String, bool)>,
         latest_frame: Option<Arc<Vec<u8>>>,
         renderoptions: RenderOptions,
         running: bool,
@@ -143,9 +151,19 @@ enum RootPhase {
         turbo_toggle: bool,
         turbo_held: bool,
         turbo_setting: TurboSetting,
+        rewind_seconds: u32,
+        fifo_renderer: bool,
+        skip_boot_rom: bool,
+        rtc_catch_up: bool,
+        // Per-ROM choice (None follows the global one), applied on reset.
+        rom_model: Option<HardwareModel>,
+        default_model: HardwareModel,
         volume: u8,
         rom_path: PathBuf,
         is_color: bool,
+        printer_attached: bool,
+        movie_recording: bool,
+        debugger_window: DebuggerWindow,
         emu_thread: Option<JoinHandle<()>>,
         modifiers: ModifiersState,
         paused: bool,
@@ -153,6 +171,8 @@ enum RootPhase {
         fullscreen: bool,
         fps_overlay: bool,
         fps_meter: FpsMeter,
+        // Only for accelerometer cartridges.
+        tilt: Option<TiltInput>,
         dmg_palette_preset: DmgPalettePreset,
         dmg_palette_custom: [[u8; 3]; 4],
         // Scratch buffer reused across frames for host-side palette mapping in DMG mode.
@@ -164,6 +184,8 @@ enum RootPhase {
 /// after the borrow ends so they can call `&mut self` methods (stop_emulator, start_game_from_path).
 enum PendingAction {
     Reset,
+    // Reset, then send an event to the fresh emulator (power-on movie recording/playback).
+    ResetThen(GBEvent),
     LoadRom(PathBuf),
 }
 
@@ -173,13 +195,18 @@ pub struct RootApp {
     egui_glium: Option<egui_glium::EguiGlium>,
     phase: RootPhase,
     scale: u32,
+    // Size of the emulated picture: the Game Boy screen, or the SGB one with border.
+    screen_size: (u32, u32),
     pending_rom: Option<PathBuf>,
     pending_action: Option<PendingAction>,
+    link: Option<LinkOption>,
+    // A save failure from the emulator that was just stopped, shown by the next one.
+    carried_save_error: Option<(String, bool)>,
     pub exit_code: i32,
 }
 
 impl RootApp {
-    pub fn new(scale: u32, pending_rom: Option<PathBuf>) -> Self {
+    pub fn new(scale: u32, pending_rom: Option<PathBuf>, link: Option<LinkOption>) -> Self {
         let default_dir = std::env::current_exe()
             .ok()
             .and_then(|p| p.parent().map(|p| p.to_string_lossy().to_string()))
@@ -194,25 +221,38 @@ impl RootApp {
                 browse_requested: false,
             },
             scale,
+            screen_size: (rust_gbe::SCREEN_W as u32, rust_gbe::SCREEN_H as u32),
             pending_rom,
             pending_action: None,
+            link,
+            carried_save_error: None,
             exit_code: EXITCODE_SUCCESS,
         }
     }
 
+    fn reset(&mut self) {
+        let path = match &self.phase {
+            RootPhase::Running { rom_path, .. } => Some(rom_path.clone()),
+            _ => None,
+        };
+        if let Some(p) = path {
+            self.stop_emulator();
+            self.start_game_from_path(p);
+        }
+    }
+
     /// Drains any deferred actions set during a phase borrow. Called at the end of
     /// each window_event so reset / open-recent can call mutating methods on self.
     fn drain_pending_action(&mut self) {
         let action = self.pending_action.take();
         match action {
-            Some(PendingAction::Reset) => {
-                let path = match &self.phase {
-                    RootPhase::Running { rom_path, .. } => Some(rom_path.clone()),
-                    _ => None,
-                };
-                if let Some(p) = path {
-                    self.stop_emulator();
-                    self.start_game_from_path(p);
+            Some(PendingAction::Reset) => self.reset(),
+            Some(PendingAction::ResetThen(event)) => {
+                let recording = matches!(event, GBEvent::StartRecording { .. });
+                self.reset();
+                if let RootPhase::Running { sender, movie_recording, .. } = &mut self.phase {
+                    *movie_recording = recording;
+                    let _ = sender.send(event);
                 }
             }
             Some(PendingAction::LoadRom(p)) => {
@@ -226,9 +266,8 @@ impl RootApp {
     }
 
     fn start_game_from_path(&mut self, rom_path: PathBuf) {
-        // Always run in (CGB-capable) mode; attempt CGB first, fallback to classic if needed.
         let filename = rom_path.to_string_lossy().to_string();
-        let (mut cpu, is_color) = match construct_cpu_auto(&filename) {
+        let (mut cpu, is_color) = match construct_cpu(&filename) {
             Some(pair) => pair,
             None => {
                 self.exit_code = EXITCODE_CPULOADFAILS;
@@ -244,6 +283,14 @@ impl RootApp {
             warn("Audio disabled: no output device available");
         }
         let _ = cpu.romname();
+        let has_accelerometer = cpu.has_accelerometer();
+        let (screen_w, screen_h) = cpu.display_size();
+        self.screen_size = (screen_w as u32, screen_h as u32);
+        // Pick up labels for the debugger from an RGBDS/no$gmb symbol file next to the ROM.
+        let sym_path = rom_path.with_extension("sym");
+        if sym_path.exists() && cpu.load_symbols(&sym_path).is_err() {
+            warn("Could not read symbol file");
+        }
         let save_slots = SaveSlotCache::from_paths(
             (1..=4)
                 .map(|slot| (slot, cpu.save_state_slot_path(slot)))
@@ -253,14 +300,21 @@ impl RootApp {
         let (frame_sender, frame_receiver) = mpsc::sync_channel(1);
         let (ui_sender, ui_receiver) = mpsc::channel();
         let frame_sender_clone = frame_sender.clone();
-        let emu_thread = thread::spawn(move || run_cpu(cpu, frame_sender_clone, recv_events, ui_sender));
+        let link = self.link.clone();
+        let emu_thread = thread::spawn(move || {
+            // Connect on the emulator thread so the window stays responsive while waiting.
+            if let Some(link) = link.as_ref().and_then(LinkOption::open) {
+                cpu.set_serial_link(link);
+            }
+            run_cpu(cpu, frame_sender_clone, recv_events, ui_sender)
+        });
         if let Some(display) = &self.display {
             let texture = glium::texture::texture2d::Texture2d::empty_with_format(
                 display,
                 glium::texture::UncompressedFloatFormat::U8U8U8,
                 glium::texture::MipmapsOption::NoMipmap,
-                rust_gbe::SCREEN_W as u32,
-                rust_gbe::SCREEN_H as u32,
+                self.screen_size.0,
+                self.screen_size.1,
             )
             .unwrap();
             let cfg = Config::load(&config_path());
@@ -276,6 +330,7 @@ impl RootApp {
                 receiver: frame_receiver,
                 ui_receiver,
                 save_slots,
+                save_error: self.carried_save_error.take(),
                 latest_frame: None,
                 renderoptions: RenderOptions::default(),
                 running: true,
@@ -287,9 +342,18 @@ impl RootApp {
                 turbo_toggle: false,
                 turbo_held: false,
                 turbo_setting: cfg.turbo,
+                rewind_seconds: cfg.rewind_seconds,
+                fifo_renderer: cfg.fifo_renderer,
+                skip_boot_rom: cfg.skip_boot_rom,
+                rtc_catch_up: cfg.rtc_catch_up,
+                rom_model: cfg.rom_hardware_model(&rom_path),
+                default_model: cfg.hardware_model.as_deref().and_then(HardwareModel::from_name).unwrap_or_default(),
                 volume: cfg.volume,
                 rom_path,
                 is_color,
+                printer_attached: false,
+                movie_recording: false,
+                debugger_window: DebuggerWindow::new(),
                 emu_thread: Some(emu_thread),
                 modifiers: ModifiersState::empty(),
                 paused: false,
@@ -297,17 +361,20 @@ impl RootApp {
                 fullscreen: cfg.fullscreen,
                 fps_overlay: cfg.fps_overlay,
                 fps_meter: FpsMeter::new(),
+                tilt: has_accelerometer.then(TiltInput::default),
                 dmg_palette_preset: cfg.dmg_palette_preset,
                 dmg_palette_custom: cfg.dmg_palette_custom,
                 palette_scratch: Vec::new(),
             };
             if let RootPhase::Running { sender, .. } = &self.phase {
                 let _ = sender.send(GBEvent::UpdateTurbo(cfg.turbo));
+                let _ = sender.send(GBEvent::UpdateRewindLength(cfg.rewind_seconds));
+                let _ = sender.send(GBEvent::SetRenderer(renderer_for(cfg.fifo_renderer)));
                 let _ = sender.send(GBEvent::UpdateVolume(perceptual_to_linear(cfg.volume)));
             }
             // Now that we've transitioned to Running, resize/configure window.
             if let Some(win) = &self.window {
-                apply_window_mode(win, self.scale, cfg.fullscreen);
+                apply_window_mode(win, self.scale, cfg.fullscreen, self.screen_size);
             }
         } else {
             self.exit_code = EXITCODE_CPULOADFAILS;
@@ -328,15 +395,24 @@ impl RootApp {
         if let Some(h) = handle {
             let _ = h.join();
         }
+        // The final battery save happens on shutdown; keep any failure for the next game.
+        if let RootPhase::Running { ui_receiver, save_error, .. } = &mut self.phase {
+            for event in ui_receiver.try_iter() {
+                if let GuiEvent::SaveError { message, kept_previous } = event {
+                    *save_error = Some((message, kept_previous));
+                }
+            }
+            self.carried_save_error = save_error.take();
+        }
     }
 }
 
-fn apply_window_mode(window: &winit::window::Window, scale: u32, fullscreen: bool) {
+fn apply_window_mode(window: &winit::window::Window, scale: u32, fullscreen: bool, screen_size: (u32, u32)) {
     if fullscreen {
         window.set_fullscreen(Some(Fullscreen::Borderless(None)));
     } else {
         window.set_fullscreen(None);
-        set_window_size(window, scale);
+        set_window_size(window, scale, screen_size);
     }
 }
 
@@ -500,6 +576,18 @@ impl ApplicationHandler for RootApp {
                     self.start_game_from_path(path);
                 }
             }
+            // The mouse tilts accelerometer cartridges.
+            (
+                RootPhase::Running { sender, tilt: Some(tilt), .. },
+                WindowEvent::CursorMoved { position, .. },
+            ) => {
+                if let Some(window) = &self.window {
+                    let size = window.inner_size();
+                    let (w, h) = (size.width as f64, size.height as f64);
+                    let (x, y) = tilt.mouse_moved(position.x, position.y, w, h);
+                    let _ = sender.send(GBEvent::Tilt(x, y));
+                }
+            }
             // Track modifier state so chords like Ctrl+R work.
             (
                 RootPhase::Running { modifiers, .. },
@@ -527,6 +615,7 @@ impl ApplicationHandler for RootApp {
                     pre_mute_volume,
                     fullscreen,
                     fps_overlay,
+                    tilt,
                     ..
                 },
                 WindowEvent::KeyboardInput {
@@ -585,6 +674,9 @@ impl ApplicationHandler for RootApp {
                                 }
                             }
                         }
+                        SystemAction::RewindHold(held) => {
+                            let _ = sender.send(GBEvent::Rewind(held));
+                        }
                         SystemAction::TurboToggle => {
                             *turbo_toggle = !*turbo_toggle;
                             if *turbo_toggle {
@@ -633,7 +725,7 @@ impl ApplicationHandler for RootApp {
                     let sc = self.scale;
                     if apply_fs_now {
                         if let Some(win) = &self.window {
-                            apply_window_mode(win, sc, fs);
+                            apply_window_mode(win, sc, fs, self.screen_size);
                         }
                     }
                     if request_reset {
@@ -641,6 +733,13 @@ impl ApplicationHandler for RootApp {
                     }
                     return;
                 }
+                if let Some(tilt) = tilt
+                    && dynamic_winit_to_keypad(logical.as_ref(), keybindings).is_none()
+                    && let Some((x, y)) = tilt.key(&logical.as_ref(), state)
+                {
+                    let _ = sender.send(GBEvent::Tilt(x, y));
+                    return;
+                }
                 match (state, logical.as_ref()) {
                     // Escape: if keybindings window open, close it immediately; else require double-press to exit.
                     (Pressed, Key::Named(NamedKey::Escape)) => {
@@ -686,6 +785,7 @@ impl ApplicationHandler for RootApp {
                     receiver,
                     ui_receiver,
                     save_slots,
+                    save_error,
                     latest_frame,
                     renderoptions,
                     running,
@@ -694,6 +794,12 @@ impl ApplicationHandler for RootApp {
                     show_keybindings_window,
                     turbo_toggle,
                     turbo_setting,
+                    rewind_seconds,
+                    fifo_renderer,
+                    skip_boot_rom,
+                    rtc_catch_up,
+                    rom_model,
+                    default_model,
                     volume,
                     paused,
                     fullscreen,
@@ -704,6 +810,10 @@ impl ApplicationHandler for RootApp {
                     is_color,
                     palette_scratch,
                     pre_mute_volume,
+                    printer_attached,
+                    movie_recording,
+                    rom_path,
+                    debugger_window,
                     ..
                 },
                 WindowEvent::RedrawRequested,
@@ -711,10 +821,12 @@ impl ApplicationHandler for RootApp {
                 if !*running {
                     return;
                 }
-                drain_gui_events(ui_receiver, save_slots);
+                let save_imported = drain_gui_events(ui_receiver, save_slots, save_error, debugger_window);
                 // Deferred actions set inside the egui closure or below, applied after the borrow ends.
                 let mut quit_requested = false;
-                let mut reset_clicked = false;
+                // An imported battery save takes effect from a reset.
+                let mut reset_clicked = save_imported;
+                let mut movie_action: Option<PendingAction> = None;
                 let mut open_recent: Option<PathBuf> = None;
                 let mut new_scale: Option<u32> = None;
                 let mut apply_fullscreen_now = false;
@@ -732,6 +844,27 @@ impl ApplicationHandler for RootApp {
                                     ui.menu_button("States", |ui| {
                                         show_states_menu(ui, sender, save_slots, latest_frame);
                                     });
+                                    ui.menu_button("Battery Save", |ui| {
+                                        let dialog = || {
+                                            rfd::FileDialog::new()
+                                                .add_filter("Battery saves", &["sav"])
+                                                .set_directory(rom_path.parent().unwrap_or(Path::new(".")))
+                                        };
+                                        if ui.button("Import .sav... (resets)").clicked() {
+                                            if let Some(path) = dialog().pick_file() {
+                                                let _ = sender.send(GBEvent::ImportSave(path));
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Export .sav...").clicked() {
+                                            let name = rom_path.with_extension("sav");
+                                            let name = name.file_name().map(|n| n.to_string_lossy().into_owned());
+                                            if let Some(path) = dialog().set_file_name(name.unwrap_or_default()).save_file() {
+                                                let _ = sender.send(GBEvent::ExportSave(path));
+                                            }
+                                            ui.close();
+                                        }
+                                    });
                                     ui.add_enabled_ui(!recent_roms.is_empty(), |ui| {
                                         ui.menu_button("Open Recent", |ui| {
                                             for entry in &recent_roms {
@@ -773,6 +906,144 @@ impl ApplicationHandler for RootApp {
                                         }
                                     });
                                     ui.checkbox(turbo_toggle, "Turbo Enabled (T)");
+                                    ui.menu_button("Rewind Buffer (hold Backspace)", |ui| {
+                                        for &seconds in crate::config::REWIND_CHOICES {
+                                            let label = match seconds {
+                                                0 => "Off".to_string(),
+                                                s => format!("{} seconds", s),
+                                            };
+                                            if ui.radio(*rewind_seconds == seconds, label).clicked() {
+                                                *rewind_seconds = seconds;
+                                                crate::config::update_config(|c| c.rewind_seconds = seconds);
+                                                let _ = sender.send(GBEvent::UpdateRewindLength(seconds));
+                                            }
+                                        }
+                                    });
+                                    if ui.checkbox(fifo_renderer, "Accurate Renderer (pixel FIFO)").changed() {
+                                        let fifo = *fifo_renderer;
+                                        crate::config::update_config(|c| c.fifo_renderer = fifo);
+                                        let _ = sender.send(GBEvent::SetRenderer(renderer_for(fifo)));
+                                    }
+                                    ui.menu_button("Hardware Model (applies on reset)", |ui| {
+                                        ui.label("This ROM");
+                                        let label = format!("Default ({})", default_model.name());
+                                        let choices = std::iter::once((None, label))
+                                            .chain(HardwareModel::ALL.map(|m| (Some(m), m.name().to_string())));
+                                        for (model, label) in choices {
+                                            if ui.radio(*rom_model == model, label).clicked() {
+                                                *rom_model = model;
+                                                crate::config::update_config(|c| c.set_rom_hardware_model(rom_path, model));
+                                            }
+                                        }
+                                        ui.separator();
+                                        ui.label("All ROMs");
+                                        for model in HardwareModel::ALL {
+                                            if ui.radio(*default_model == model, model.name()).clicked() {
+                                                *default_model = model;
+                                                crate::config::update_config(|c| c.hardware_model = Some(model.name().to_string()));
+                                            }
+                                        }
+                                    });
+                                    ui.menu_button("Boot ROM (applies on reset)", |ui| {
+                                        if ui.checkbox(skip_boot_rom, "Skip Boot ROM").changed() {
+                                            let skip = *skip_boot_rom;
+                                            crate::config::update_config(|c| c.skip_boot_rom = skip);
+                                        }
+                                        if ui.button("Set DMG/MGB/SGB Boot ROM...").clicked() {
+                                            if let Some(path) = pick_boot_rom(false) {
+                                                crate::config::update_config(|c| c.dmg_boot_rom = Some(path));
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Set CGB/AGB Boot ROM...").clicked() {
+                                            if let Some(path) = pick_boot_rom(true) {
+                                                crate::config::update_config(|c| c.cgb_boot_rom = Some(path));
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Clear Boot ROMs").clicked() {
+                                            crate::config::update_config(|c| {
+                                                c.dmg_boot_rom = None;
+                                                c.cgb_boot_rom = None;
+                                            });
+                                            ui.close();
+                                        }
+                                    });
+                                    if ui.checkbox(rtc_catch_up, "Catch Up Cartridge Clock (applies on reset)").changed() {
+                                        let catch_up = *rtc_catch_up;
+                                        crate::config::update_config(|c| c.rtc_catch_up = catch_up);
+                                    }
+                                    ui.separator();
+                                    if ui.checkbox(printer_attached, "Game Boy Printer").changed() {
+                                        let path = printer_attached.then(|| rom_path.clone());
+                                        let _ = sender.send(GBEvent::SetPrinter(path));
+                                    }
+                                    ui.menu_button("Pocket Camera", |ui| {
+                                        if ui.button("Load Image...").clicked() {
+                                            let picked = rfd::FileDialog::new()
+                                                .add_filter("Images", &["png", "bmp"])
+                                                .set_directory(rom_path.parent().unwrap_or(Path::new(".")))
+                                                .pick_file();
+                                            match picked.as_deref().map(CameraImage::load) {
+                                                Some(Ok(image)) => {
+                                                    let _ = sender.send(GBEvent::SetCameraImage(image));
+                                                }
+                                                Some(Err(e)) => warn(e),
+                                                None => {}
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Test Pattern").clicked() {
+                                            let _ = sender.send(GBEvent::SetCameraImage(CameraImage::test_pattern()));
+                                            ui.close();
+                                        }
+                                    });
+                                    ui.menu_button("Movie", |ui| {
+                                        if *movie_recording {
+                                            if ui.button("Stop Recording").clicked() {
+                                                let _ = sender.send(GBEvent::StopRecording);
+                                                *movie_recording = false;
+                                                ui.close();
+                                            }
+                                            return;
+                                        }
+                                        if ui.button("Record from Power-On...").clicked() {
+                                            if let Some(path) = pick_movie_save_path(rom_path) {
+                                                let start = GBEvent::StartRecording { path, power_on: true };
+                                                movie_action = Some(PendingAction::ResetThen(start));
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Record from Current State...").clicked() {
+                                            if let Some(path) = pick_movie_save_path(rom_path) {
+                                                let _ = sender.send(GBEvent::StartRecording { path, power_on: false });
+                                                *movie_recording = true;
+                                            }
+                                            ui.close();
+                                        }
+                                        if ui.button("Play Movie...").clicked() {
+                                            let picked = rfd::FileDialog::new()
+                                                .add_filter("Movies", &["gbm"])
+                                                .set_directory(rom_path.parent().unwrap_or(Path::new(".")))
+                                                .pick_file();
+                                            match picked.map(Movie::load) {
+                                                Some(Ok(movie)) if movie.is_power_on() => {
+                                                    let play = GBEvent::PlayMovie(Box::new(movie));
+                                                    movie_action = Some(PendingAction::ResetThen(play));
+                                                }
+                                                Some(Ok(movie)) => {
+                                                    let _ = sender.send(GBEvent::PlayMovie(Box::new(movie)));
+                                                }
+                                                Some(Err(e)) => warn(e),
+                                                None => {}
+                                            }
+                                            ui.close();
+                                        }
+                                    });
+                                    if ui.button("Debugger...").clicked() {
+                                        debugger_window.open(sender);
+                                        ui.close();
+                                    }
                                 });
                                 ui.menu_button("Display", |ui| {
                                     if ui.checkbox(fullscreen, "Fullscreen (F11)").changed() {
@@ -868,6 +1139,22 @@ impl ApplicationHandler for RootApp {
                                 });
                         }
 
+                        debugger_window.show(ctx, sender);
+
+                        if let Some((message, kept_previous)) = save_error.as_ref() {
+                            let mut dismissed = false;
+                            egui::Window::new("Save Failed").collapsible(false).resizable(false).show(ctx, |ui| {
+                                ui.label(message);
+                                if *kept_previous {
+                                    ui.label("The previous save file and its backups were left untouched.");
+                                }
+                                dismissed = ui.button("OK").clicked();
+                            });
+                            if dismissed {
+                                *save_error = None;
+                            }
+                        }
+
                         if *show_keybindings_window {
                             egui::Window::new("Keybindings").open(show_keybindings_window).show(ctx, |ui| {
                                 ui.label("Click a binding, then press a key (Esc to cancel capture). Reserved keys can't be used.");
@@ -959,18 +1246,21 @@ impl ApplicationHandler for RootApp {
                     crate::config::update_config(|c| c.scale = s);
                     let fs = if let RootPhase::Running { fullscreen, .. } = &self.phase { *fullscreen } else { false };
                     if let Some(win) = &self.window {
-                        apply_window_mode(win, s, fs);
+                        apply_window_mode(win, s, fs, self.screen_size);
                     }
                 }
                 if apply_fullscreen_now {
                     let fs = if let RootPhase::Running { fullscreen, .. } = &self.phase { *fullscreen } else { false };
                     if let Some(win) = &self.window {
-                        apply_window_mode(win, self.scale, fs);
+                        apply_window_mode(win, self.scale, fs, self.screen_size);
                     }
                 }
                 if reset_clicked {
                     self.pending_action = Some(PendingAction::Reset);
                 }
+                if let Some(action) = movie_action {
+                    self.pending_action = Some(action);
+                }
                 if let Some(p) = open_recent {
                     self.pending_action = Some(PendingAction::LoadRom(p));
                 }
@@ -999,6 +1289,7 @@ impl ApplicationHandler for RootApp {
             receiver,
             ui_receiver,
             save_slots,
+            save_error,
             latest_frame,
             texture,
             running,
@@ -1007,13 +1298,16 @@ impl ApplicationHandler for RootApp {
             dmg_palette_preset,
             dmg_palette_custom,
             palette_scratch,
+            debugger_window,
             ..
         } = &mut self.phase
         {
             if !*running {
                 return;
             }
-            drain_gui_events(ui_receiver, save_slots);
+            if drain_gui_events(ui_receiver, save_slots, save_error, debugger_window) {
+                self.pending_action = Some(PendingAction::Reset);
+            }
             let palette_now = palette_for_preset(*dmg_palette_preset, dmg_palette_custom);
             let needs_palette = !*is_color;
             match receiver.try_recv() {
@@ -1032,6 +1326,7 @@ impl ApplicationHandler for RootApp {
                 }
             }
         }
+        self.drain_pending_action();
     }
 }
 
@@ -1048,7 +1343,14 @@ fn request_save_state(
     }
 }
 
-fn drain_gui_events(receiver: &Receiver<GuiEvent>, save_slots: &mut SaveSlotCache) {
+/// Returns true when a battery save was imported and the game should be reset.
+fn drain_gui_events(
+    receiver: &Receiver<GuiEvent>,
+    save_slots: &mut SaveSlotCache,
+    save_error: &mut Option<(String, bool)>,
+    debugger_window: &mut DebuggerWindow,
+) -> bool {
+    let mut save_imported = false;
     loop {
         match receiver.try_recv() {
             Ok(GuiEvent::SaveStateSaved { slot, preview }) => {
@@ -1057,10 +1359,18 @@ fn drain_gui_events(receiver: &Receiver<GuiEvent>, save_slots: &mut SaveSlotCach
             Ok(GuiEvent::SaveStateFailed { slot }) => {
                 save_slots.mark_failed(slot);
             }
+            Ok(GuiEvent::SaveError { message, kept_previous }) => {
+                *save_error = Some((message, kept_previous));
+            }
+            Ok(GuiEvent::SaveImported) => save_imported = true,
+            Ok(GuiEvent::DebugState(snapshot)) => {
+                debugger_window.update(snapshot);
+            }
             Err(TryRecvError::Empty) => break,
             Err(TryRecvError::Disconnected) => break,
         }
     }
+    save_imported
 }
 
 fn show_states_menu(
@@ -1310,18 +1620,23 @@ fn period(hour: u8) -> &'static str {
 }
 
 fn upload_screen(texture: &mut glium::texture::texture2d::Texture2d, datavec: &[u8]) {
+    let (width, height) = (texture.width(), texture.height());
+    // A state saved on another hardware model can bring a differently sized picture.
+    if datavec.len() != (width * height * 3) as usize {
+        return;
+    }
     let rawimage2d = glium::texture::RawImage2d {
         data: std::borrow::Cow::Borrowed(datavec),
-        width: rust_gbe::SCREEN_W as u32,
-        height: rust_gbe::SCREEN_H as u32,
+        width,
+        height,
         format: glium::texture::ClientFormat::U8U8U8,
     };
     texture.write(
         glium::Rect {
             left: 0,
             bottom: 0,
-            width: rust_gbe::SCREEN_W as u32,
-            height: rust_gbe::SCREEN_H as u32,
+            width,
+            height,
         },
         rawimage2d,
     );
@@ -1346,16 +1661,51 @@ fn upload_frame_with_palette(
     }
 }
 
+fn pick_movie_save_path(rom_path: &Path) -> Option<PathBuf> {
+    let stem = rom_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    rfd::FileDialog::new()
+        .add_filter("Movies", &["gbm"])
+        .set_directory(rom_path.parent().unwrap_or(Path::new(".")))
+        .set_file_name(format!("{}.gbm", stem))
+        .save_file()
+}
+
+fn renderer_for(fifo: bool) -> PpuRenderer {
+    if fifo { PpuRenderer::Fifo } else { PpuRenderer::Scanline }
+}
+
+// Asks for a boot ROM file, rejecting ones of the wrong size for the requested model.
+fn pick_boot_rom(cgb: bool) -> Option<String> {
+    let path = rfd::FileDialog::new()
+        .add_filter("Boot ROMs", &["bin", "gb", "rom"])
+        .pick_file()?;
+    match BootRom::load(&path) {
+        Ok(boot_rom) if boot_rom.is_cgb() == cgb => Some(path.to_string_lossy().into_owned()),
+        Ok(_) if cgb => {
+            warn("Not a CGB/AGB boot ROM (expected 2304 bytes)");
+            None
+        }
+        Ok(_) => {
+            warn("Not a DMG/MGB/SGB boot ROM (expected 256 bytes)");
+            None
+        }
+        Err(e) => {
+            warn(e);
+            None
+        }
+    }
+}
+
 fn warn(message: &str) {
     eprintln!("{}", message);
 }
 
-fn set_window_size(window: &winit::window::Window, scale: u32) {
+fn set_window_size(window: &winit::window::Window, scale: u32, (width, height): (u32, u32)) {
     // Add extra height for the menu bar (approximately 30 pixels at 1x scale)
     let menu_bar_height = 30;
     let _ = window.request_inner_size(winit::dpi::LogicalSize::<u32>::from((
-        rust_gbe::SCREEN_W as u32 * scale,
-        rust_gbe::SCREEN_H as u32 * scale + menu_bar_height,
+        width * scale,
+        height * scale + menu_bar_height,
     )));
 }
 
@@ -8,6 +8,7 @@ pub enum SystemAction {
     LoadState(u8),
     TurboHold(bool), // true=press, false=release
     TurboToggle,
+    RewindHold(bool), // true=press, false=release
     ToggleInterpolation,
     TogglePause,
     Reset,
@@ -41,6 +42,8 @@ pub fn system_action_for(
         (Pressed, Key::Named(NamedKey::F11)) => Some(ToggleFullscreen),
         (Pressed, Key::Named(NamedKey::Shift)) => Some(TurboHold(true)),
         (Released, Key::Named(NamedKey::Shift)) => Some(TurboHold(false)),
+        (Pressed, Key::Named(NamedKey::Backspace)) => Some(RewindHold(true)),
+        (Released, Key::Named(NamedKey::Backspace)) => Some(RewindHold(false)),
         (Pressed, Key::Character("t" | "T")) => Some(TurboToggle),
         (Pressed, Key::Character("y" | "Y")) => Some(ToggleInterpolation),
         (Pressed, Key::Character("p" | "P")) => Some(TogglePause),
@@ -54,6 +57,7 @@ pub const RESERVED_KEYS: &[&str] = &[
     "F1","F2","F3","F4","F5","F6","F7","F8", // save/load slots 1-4
     "F9","F11",                              // FPS overlay, fullscreen
     "Shift","T","Y","P","M",                 // turbo hold/toggle, interpolation, pause, mute
+    "Backspace",                             // rewind hold
 ];
 
 pub fn is_reserved_key_name(name: &str) -> bool {
@@ -61,3 +65,43 @@ pub fn is_reserved_key_name(name: &str) -> bool {
     let upper = name.to_uppercase();
     RESERVED_KEYS.iter().any(|k| k.eq_ignore_ascii_case(&upper))
 }
+
+/// Accelerometer input for tilt cartridges: the mouse position over the window, or a
+/// full tilt in the direction of the I/J/K/L keys while any of them is held. Only used
+/// while such a cartridge is loaded, and joypad bindings on those keys take precedence.
+#[derive(Default)]
+pub struct TiltInput {
+    mouse: (f32, f32),
+    // Up, left, down, right.
+    keys: [bool; 4],
+}
+
+impl TiltInput {
+    /// Records a cursor position relative to the window size; returns the new tilt.
+    pub fn mouse_moved(&mut self, x: f64, y: f64, width: f64, height: f64) -> (f32, f32) {
+        let axis = |pos: f64, size: f64| ((pos / size.max(1.0)) * 2.0 - 1.0).clamp(-1.0, 1.0) as f32;
+        self.mouse = (axis(x, width), axis(y, height));
+        self.tilt()
+    }
+
+    /// Returns the new tilt if `key` is a tilt key.
+    pub fn key(&mut self, key: &Key<&str>, state: winit::event::ElementState) -> Option<(f32, f32)> {
+        let index = match key {
+            Key::Character("i" | "I") => 0,
+            Key::Character("j" | "J") => 1,
+            Key::Character("k" | "K") => 2,
+            Key::Character("l" | "L") => 3,
+            _ => return None,
+        };
+        self.keys[index] = state.is_pressed();
+        Some(self.tilt())
+    }
+
+    fn tilt(&self) -> (f32, f32) {
+        if !self.keys.contains(&true) {
+            return self.mouse;
+        }
+        let axis = |neg: bool, pos: bool| pos as i8 as f32 - neg as i8 as f32;
+        (axis(self.keys[1], self.keys[3]), axis(self.keys[0], self.keys[2]))
+    }
+}
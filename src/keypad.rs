@@ -3,10 +3,13 @@ pub struct Keypad {
     row0: u8,
     row1: u8,
     data: u8,
+    // Controllers enabled by the SGB's MLT_REQ; only the first one has input.
+    players: u8,
+    player: u8,
     pub interrupt: u8,
 }
 
-#[derive(Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub enum KeypadKey {
     Right,
     Left,
@@ -24,19 +27,44 @@ impl Keypad {
             row0: 0x0F,
             row1: 0x0F,
             data: 0xFF,
+            players: 1,
+            player: 0,
             interrupt: 0,
         }
     }
 
     pub fn rb(&self) -> u8 {
+        if self.players > 1 && self.data & 0x30 == 0x30 {
+            // With no row selected, the low bits identify the selected controller.
+            return (self.data & 0xF0) | (0x0F - self.player);
+        }
+        if self.player != 0 {
+            return self.data | 0x0F;
+        }
         self.data
     }
 
     pub fn wb(&mut self, value: u8) {
+        // Deselecting the buttons row moves on to the next SGB controller.
+        if self.players > 1 && self.data & 0x20 == 0 && value & 0x20 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+        self.select_rows(value);
+    }
+
+    /// Like `wb`, but stays on the current SGB controller; for writes the SGB takes as
+    /// packet bits.
+    pub fn select_rows(&mut self, value: u8) {
         self.data = (self.data & 0xCF) | (value & 0x30);
         self.update();
     }
 
+    /// Sets the number of SGB controllers (1, 2 or 4) and selects the first.
+    pub fn set_players(&mut self, players: u8) {
+        self.players = players;
+        self.player = 0;
+    }
+
     fn update(&mut self) {
         let old_values = self.data & 0xF;
         let mut new_values = 0xF;
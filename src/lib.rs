@@ -1,8 +1,22 @@
-pub use crate::gpu::{SCREEN_H, SCREEN_W};
+pub use crate::gpu::{PpuRenderer, SCREEN_H, SCREEN_W};
+pub use crate::sgb::{SGB_SCREEN_H, SGB_SCREEN_W};
 pub use crate::keypad::KeypadKey;
 pub use crate::apu::AudioPlayer;
+pub use crate::serial::SerialLink;
+pub use crate::mmu::DEFAULT_WRAM_SEED;
+pub use crate::gbmode::HardwareModel;
 
+pub mod bootrom;
+pub mod camera;
+pub mod debugger;
 pub mod device;
+pub mod disasm;
+pub mod gdb;
+pub mod link;
+pub mod movie;
+pub mod printer;
+pub mod rewind;
+pub mod trace;
 
 mod cpu;
 mod gbmode;
@@ -11,7 +25,9 @@ mod keypad;
 mod mbc;
 mod mmu;
 mod register;
+mod save_file;
 mod serial;
+mod sgb;
 mod apu;
 mod timer;
 
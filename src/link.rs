@@ -0,0 +1,428 @@
+//! Link cable backends that can be plugged into a `Device` with `set_serial_link`.
+use crate::serial::SerialLink;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy)]
+struct Port {
+    data: u8,
+    control: u8,
+    incoming: Option<u8>,
+}
+
+impl Port {
+    fn new() -> Port {
+        // Until the device publishes its registers the line reads high, as if unplugged.
+        Port {
+            data: 0xFF,
+            control: 0,
+            incoming: None,
+        }
+    }
+}
+
+/// One end of a link cable joining two devices in the same process.
+pub struct LocalLink {
+    ports: Arc<Mutex<[Port; 2]>>,
+    side: usize,
+}
+
+impl LocalLink {
+    /// Creates both ends of a cable; attach one to each device.
+    pub fn pair() -> (LocalLink, LocalLink) {
+        let ports = Arc::new(Mutex::new([Port::new(), Port::new()]));
+        (
+            LocalLink {
+                ports: ports.clone(),
+                side: 0,
+            },
+            LocalLink { ports, side: 1 },
+        )
+    }
+}
+
+impl SerialLink for LocalLink {
+    fn exchange(&mut self, out: u8, _clock: u64) -> Option<u8> {
+        let mut ports = self.ports.lock().unwrap();
+        let peer = &mut ports[1 - self.side];
+        if peer.control & 0x01 == 0x01 {
+            // The peer is driving its own clock, so it is not listening to ours.
+            return Some(0xFF);
+        }
+        peer.incoming = Some(out);
+        Some(peer.data)
+    }
+
+    fn poll(&mut self, _clock: u64) -> Option<u8> {
+        let port = &mut self.ports.lock().unwrap()[self.side];
+        let incoming = port.incoming.take();
+        if port.control & 0x01 == 0x01 { None } else { incoming }
+    }
+
+    fn publish(&mut self, data: u8, control: u8) {
+        let port = &mut self.ports.lock().unwrap()[self.side];
+        port.data = data;
+        port.control = control;
+    }
+}
+
+const MSG_HELLO: u8 = 0;
+const MSG_TRANSFER: u8 = 1;
+const MSG_REPLY: u8 = 2;
+const MSG_LEN: usize = 10;
+
+// How long (in real time) the clocking side waits for the peer before treating the cable
+// as unplugged.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// A transfer stamped further ahead than this (about a second of double speed CPU ticks)
+// is applied right away instead of waiting, e.g. after one side loaded a save state.
+const MAX_SKEW: u64 = 8_388_608;
+
+enum Message {
+    // Sender's serial clock when it first used the link, used to line up both clocks.
+    Hello(u64),
+    // A byte shifted out with the sender's internal clock, stamped with the sender's clock.
+    Transfer(u8, u64),
+    // The receiver's SB, shifted back in answer to a transfer.
+    Reply(u8),
+}
+
+impl Message {
+    fn encode(&self) -> [u8; MSG_LEN] {
+        let (tag, byte, clock) = match *self {
+            Message::Hello(clock) => (MSG_HELLO, 0, clock),
+            Message::Transfer(byte, clock) => (MSG_TRANSFER, byte, clock),
+            Message::Reply(byte) => (MSG_REPLY, byte, 0),
+        };
+        let mut buf = [0; MSG_LEN];
+        buf[0] = tag;
+        buf[1] = byte;
+        buf[2..].copy_from_slice(&clock.to_be_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; MSG_LEN]) -> Option<Message> {
+        let clock = u64::from_be_bytes(buf[2..].try_into().unwrap());
+        match buf[0] {
+            MSG_HELLO => Some(Message::Hello(clock)),
+            MSG_TRANSFER => Some(Message::Transfer(buf[1], clock)),
+            MSG_REPLY => Some(Message::Reply(buf[1])),
+            _ => None,
+        }
+    }
+}
+
+/// A link cable to another emulator process over TCP.
+///
+/// Transfers are best-effort, not lockstep: neither emulator ever waits for the other.
+/// A receiver that is behind applies a byte at the point in emulated time it was sent
+/// at, but one that is already past it (or more than `MAX_SKEW` behind) applies it
+/// straight away. The side driving the clock keeps running while it waits for the
+/// answer: the transfer stays busy (and its interrupt pending) until the peer has
+/// replied, so it may complete later than on hardware. Games that time the link tightly
+/// can therefore see different results than on a real cable.
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    incoming: Receiver<Message>,
+    data: u8,
+    control: u8,
+    // Local clock minus peer clock, known once the peer's hello arrived.
+    offset: Option<i64>,
+    hello_sent: bool,
+    // Byte received from the peer and the local clock at which it should be applied.
+    pending: Option<(u8, u64)>,
+    // When the unanswered transfer we sent is given up on.
+    reply_deadline: Option<Instant>,
+}
+
+impl TcpLink {
+    /// Waits for a peer to connect to `addr`.
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        TcpLink::from_stream(stream)
+    }
+
+    /// Connects to a peer listening on `addr`, retrying for up to `timeout` so both
+    /// processes can be started at the same time.
+    pub fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> io::Result<TcpLink> {
+        let start = Instant::now();
+        loop {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => return TcpLink::from_stream(stream),
+                Err(e) if start.elapsed() >= timeout => return Err(e),
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (sender, incoming) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut buf = [0; MSG_LEN];
+            while reader.read_exact(&mut buf).is_ok() {
+                let Some(msg) = Message::decode(&buf) else { break };
+                if sender.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(TcpLink {
+            stream: Some(stream),
+            incoming,
+            data: 0xFF,
+            control: 0,
+            offset: None,
+            hello_sent: false,
+            pending: None,
+            reply_deadline: None,
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn send(&mut self, msg: Message) {
+        let ok = match self.stream.as_mut() {
+            Some(stream) => stream.write_all(&msg.encode()).is_ok(),
+            None => return,
+        };
+        if !ok {
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            eprintln!("Link cable disconnected");
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.pending = None;
+        self.reply_deadline = None;
+    }
+
+    fn greet(&mut self, clock: u64) {
+        if !self.hello_sent {
+            self.hello_sent = true;
+            self.send(Message::Hello(clock));
+        }
+    }
+
+    /// Handles a message that is not the reply to our own transfer.
+    fn receive(&mut self, msg: Message, clock: u64) {
+        match msg {
+            Message::Hello(peer) => self.offset = Some(clock as i64 - peer as i64),
+            Message::Transfer(_, _) if self.control & 0x01 == 0x01 => {
+                // Both sides drive their own clock; neither hears the other.
+                self.send(Message::Reply(0xFF));
+            }
+            Message::Transfer(byte, peer) => {
+                let due = match self.offset {
+                    Some(offset) => (peer as i64 + offset).max(0) as u64,
+                    None => clock,
+                };
+                let due = if due > clock + MAX_SKEW { clock } else { due };
+                self.pending = Some((byte, due));
+            }
+            // Late answer to a transfer we already gave up on.
+            Message::Reply(_) => {}
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn exchange(&mut self, out: u8, clock: u64) -> Option<u8> {
+        self.greet(clock);
+        if self.stream.is_none() {
+            return Some(0xFF);
+        }
+        self.send(Message::Transfer(out, clock));
+        self.reply_deadline = Some(Instant::now() + REPLY_TIMEOUT);
+        self.reply(clock)
+    }
+
+    fn reply(&mut self, clock: u64) -> Option<u8> {
+        // Nothing outstanding, e.g. a state saved mid-transfer was loaded.
+        let Some(deadline) = self.reply_deadline else {
+            return Some(0xFF);
+        };
+        loop {
+            match self.incoming.try_recv() {
+                Ok(Message::Reply(byte)) => {
+                    self.reply_deadline = None;
+                    return Some(byte);
+                }
+                Ok(msg) => self.receive(msg, clock),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnect();
+                    break;
+                }
+            }
+        }
+        if self.stream.is_none() || Instant::now() >= deadline {
+            self.disconnect();
+            return Some(0xFF);
+        }
+        None
+    }
+
+    fn poll(&mut self, clock: u64) -> Option<u8> {
+        self.greet(clock);
+        loop {
+            match self.incoming.try_recv() {
+                Ok(msg) => self.receive(msg, clock),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnect();
+                    break;
+                }
+            }
+        }
+        match self.pending {
+            Some(_) if self.control & 0x01 == 0x01 => {
+                // Switched to the internal clock before the byte was due.
+                self.pending = None;
+                self.send(Message::Reply(0xFF));
+                None
+            }
+            Some((byte, due)) if clock >= due => {
+                self.pending = None;
+                let reply = self.data;
+                self.send(Message::Reply(reply));
+                Some(byte)
+            }
+            _ => None,
+        }
+    }
+
+    fn publish(&mut self, data: u8, control: u8) {
+        self.data = data;
+        self.control = control;
+    }
+}
+
+impl Drop for TcpLink {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LocalLink, TcpLink};
+    use crate::serial::Serial;
+
+    fn run(serial: &mut Serial, ticks: u32) {
+        for _ in 0..ticks / 4 {
+            serial.do_cycle(4);
+        }
+    }
+
+    #[test]
+    fn unplugged_transfer_takes_eight_bits_and_reads_ones() {
+        let mut serial = Serial::new(None);
+        serial.wb(0xFF01, 0x42);
+        serial.wb(0xFF02, 0x81);
+
+        run(&mut serial, 8 * 512 - 4);
+        assert_eq!(serial.interrupt, 0);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0x80);
+
+        run(&mut serial, 4);
+        assert_eq!(serial.interrupt, 0x08);
+        assert_eq!(serial.rb(0xFF01), 0xFF);
+        assert_eq!(serial.rb(0xFF02) & 0x80, 0);
+    }
+
+    #[test]
+    fn fast_clock_shifts_thirty_two_times_faster() {
+        let mut serial = Serial::new(None);
+        serial.wb(0xFF02, 0x83);
+        run(&mut serial, 8 * 16);
+        assert_eq!(serial.interrupt, 0x08);
+    }
+
+    #[test]
+    fn paired_devices_exchange_bytes() {
+        let (a, b) = LocalLink::pair();
+        let mut master = Serial::new(Some(Box::new(a)));
+        let mut slave = Serial::new(Some(Box::new(b)));
+
+        slave.wb(0xFF01, 0x5A);
+        slave.wb(0xFF02, 0x80);
+        master.wb(0xFF01, 0xA5);
+        master.wb(0xFF02, 0x81);
+
+        for _ in 0..8 * 512 / 4 {
+            master.do_cycle(4);
+            slave.do_cycle(4);
+        }
+
+        assert_eq!(master.interrupt, 0x08);
+        assert_eq!(master.rb(0xFF01), 0x5A);
+        assert_eq!(slave.interrupt, 0x08);
+        assert_eq!(slave.rb(0xFF01), 0xA5);
+        assert_eq!(slave.rb(0xFF02) & 0x80, 0);
+    }
+
+    #[test]
+    fn tcp_link_exchanges_bytes() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let slave = std::thread::spawn(move || {
+            let stream = listener.accept().unwrap().0;
+            let mut slave = Serial::new(Some(Box::new(TcpLink::from_stream(stream).unwrap())));
+            slave.wb(0xFF01, 0x5A);
+            slave.wb(0xFF02, 0x80);
+            let mut ticks = 0;
+            while slave.interrupt == 0 && ticks < 64 * 512 {
+                slave.do_cycle(4);
+                ticks += 4;
+                if ticks > 8 * 512 {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+            (slave.interrupt, slave.rb(0xFF01))
+        });
+
+        let link = TcpLink::connect(addr, std::time::Duration::from_secs(5)).unwrap();
+        let mut master = Serial::new(Some(Box::new(link)));
+        master.wb(0xFF01, 0xA5);
+        master.wb(0xFF02, 0x81);
+        run(&mut master, 8 * 512);
+        // The transfer stays busy, without blocking, until the slave has answered.
+        let start = std::time::Instant::now();
+        while master.interrupt == 0 && start.elapsed() < std::time::Duration::from_secs(5) {
+            assert_eq!(master.rb(0xFF02) & 0x80, 0x80);
+            master.do_cycle(4);
+        }
+
+        assert_eq!(master.interrupt, 0x08);
+        assert_eq!(master.rb(0xFF01), 0x5A);
+        assert_eq!(slave.join().unwrap(), (0x08, 0xA5));
+    }
+
+    #[test]
+    fn slave_without_pending_transfer_gets_no_interrupt() {
+        let (a, b) = LocalLink::pair();
+        let mut master = Serial::new(Some(Box::new(a)));
+        let mut slave = Serial::new(Some(Box::new(b)));
+
+        slave.wb(0xFF01, 0x11);
+        master.wb(0xFF02, 0x81);
+        run(&mut master, 8 * 512);
+        slave.do_cycle(4);
+
+        assert_eq!(master.rb(0xFF01), 0x11);
+        assert_eq!(slave.interrupt, 0);
+    }
+}
@@ -6,6 +6,7 @@ use mimalloc::MiMalloc;
 static GLOBAL: MiMalloc = MiMalloc;
 
 mod gui;
+mod debugger_ui;
 mod audio;
 mod emulator;
 mod config;
@@ -14,6 +15,7 @@ mod palette;
 
 use std::path::PathBuf;
 
+use emulator::LinkOption;
 use gui::{RootApp, EXITCODE_CPULOADFAILS, EXITCODE_SUCCESS};
 
 fn main() {
@@ -26,11 +28,25 @@ fn main() {
 fn real_main() -> i32 {
     const DEFAULT_SCALE: u32 = 3;
     let event_loop = winit::event_loop::EventLoop::new().unwrap();
-    let pending_rom = std::env::args().nth(1).and_then(|a| {
-        let p = PathBuf::from(&a);
-        if p.is_file() { Some(p) } else { eprintln!("ROM path not found: {}", a); None }
-    });
-    let mut app = RootApp::new(DEFAULT_SCALE, pending_rom);
+    let mut pending_rom = None;
+    let mut link = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "--link-listen" | "--link-connect" => {
+                let Some(addr) = args.next() else {
+                    eprintln!("Missing address for {}", a);
+                    return EXITCODE_CPULOADFAILS;
+                };
+                link = Some(if a == "--link-listen" { LinkOption::Listen(addr) } else { LinkOption::Connect(addr) });
+            }
+            _ => {
+                let p = PathBuf::from(&a);
+                if p.is_file() { pending_rom = Some(p) } else { eprintln!("ROM path not found: {}", a) }
+            }
+        }
+    }
+    let mut app = RootApp::new(DEFAULT_SCALE, pending_rom, link);
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
     if let Err(e) = event_loop.run_app(&mut app) { eprintln!("Application error: {:?}", e); return EXITCODE_CPULOADFAILS; }
     app.exit_code
@@ -0,0 +1,115 @@
+use crate::mbc::{ram_banks, rom_banks, MBC};
+use crate::StrResult;
+
+// HuC1 banks like a simplified MBC1 and can swap the RAM area for an infrared
+// transceiver. There is nothing on the other end of the IR port, so it never sees light.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    ir_mode: bool,
+    ir_led: bool,
+    ram_updated: bool,
+    rombanks: usize,
+    rambanks: usize,
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>) -> StrResult<HuC1> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+        Ok(HuC1 {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            ir_mode: false,
+            ir_led: false,
+            ram_updated: false,
+            rombanks,
+            rambanks,
+        })
+    }
+
+    fn ram_address(&self, a: u16) -> usize {
+        (self.rambank * 0x2000) | ((a as usize) & 0x1FFF)
+    }
+}
+
+impl MBC for HuC1 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 {
+            a as usize
+        } else {
+            (self.rombank * 0x4000) | ((a as usize) & 0x3FFF)
+        };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.ir_mode {
+            // Bit 0 clear: no light received.
+            return 0xC0;
+        }
+        *self.ram.get(self.ram_address(a)).unwrap_or(&0xFF)
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.ir_mode = v & 0x0F == 0x0E,
+            0x2000..=0x3FFF => {
+                self.rombank = match v as usize & 0x3F {
+                    0 => 1,
+                    n => n,
+                } % self.rombanks
+            }
+            0x4000..=0x5FFF => {
+                if self.rambanks > 0 {
+                    self.rambank = (v as usize & 0x03) % self.rambanks;
+                }
+            }
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (HuC1)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.ir_mode {
+            self.ir_led = v & 0x01 != 0;
+            return;
+        }
+        let address = self.ram_address(a);
+        if address < self.ram.len() {
+            self.ram[address] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded RAM has incorrect length");
+        }
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::mbc::{ram_banks, rom_banks, MBC};
+use crate::StrResult;
+
+use std::convert::TryInto;
+use std::time;
+
+// System clocks per RTC second.
+const RTC_TICKS_PER_SECOND: u32 = 4_194_304;
+const MINUTES_PER_DAY: u64 = 24 * 60;
+// RTC memory, then the clock in seconds and the unix time it was saved at, little-endian.
+const RTC_TRAILER_SIZE: usize = 0x100 + 16;
+
+// HuC3 maps RAM, an RTC command port or an IR port at 0xA000 depending on the mode
+// written to 0x0000-0x1FFF. The RTC is a small MCU with 256 nibbles of memory; commands
+// copy its minute-of-day and day counters in and out of nibbles 0-6.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    mode: u8,
+    ram_updated: bool,
+    rombanks: usize,
+    rambanks: usize,
+    rtc_memory: Vec<u8>,
+    rtc_index: u8,
+    rtc_command: u8,
+    rtc_read: u8,
+    // Seconds since day 0, minute 0.
+    rtc_secs: u64,
+    // System clocks since the clock last ticked.
+    rtc_ticks: u32,
+    // Unix time the loaded save was written at, until `catch_up_rtc` consumes it.
+    rtc_saved_at: Option<u64>,
+    // Wall clock override in unix seconds; None uses the host clock.
+    rtc_clock: Option<u64>,
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>) -> StrResult<HuC3> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+        Ok(HuC3 {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            mode: 0,
+            ram_updated: false,
+            rombanks,
+            rambanks,
+            rtc_memory: vec![0; 0x100],
+            rtc_index: 0,
+            rtc_command: 0,
+            rtc_read: 0,
+            rtc_secs: 0,
+            rtc_ticks: 0,
+            rtc_saved_at: None,
+            rtc_clock: None,
+        })
+    }
+
+    fn now(&self) -> u64 {
+        match self.rtc_clock {
+            Some(t) => t,
+            None => time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    fn ram_address(&self, a: u16) -> usize {
+        (self.rambank * 0x2000) | ((a as usize) & 0x1FFF)
+    }
+
+    // The high nibble selects the command, the low nibble is its argument.
+    fn rtc_write(&mut self, v: u8) {
+        self.rtc_command = v;
+        let arg = v & 0x0F;
+        let index = self.rtc_index as usize;
+        match v >> 4 & 0x07 {
+            1 => {
+                self.rtc_read = self.rtc_memory[index];
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            }
+            2 | 3 => {
+                self.rtc_memory[index] = arg;
+                self.ram_updated = true;
+                if v >> 4 & 0x07 == 3 {
+                    self.rtc_index = self.rtc_index.wrapping_add(1);
+                }
+            }
+            4 => self.rtc_index = (self.rtc_index & 0xF0) | arg,
+            5 => self.rtc_index = (self.rtc_index & 0x0F) | arg << 4,
+            6 => match arg {
+                0 => self.latch_clock(),
+                1 => self.set_clock(),
+                // Status: the clock is running.
+                2 => self.rtc_read = 0x01,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn latch_clock(&mut self) {
+        let minutes = self.rtc_secs / 60;
+        let minute = minutes % MINUTES_PER_DAY;
+        let day = (minutes / MINUTES_PER_DAY) & 0xFFFF;
+        for i in 0..3 {
+            self.rtc_memory[i] = (minute >> (i * 4)) as u8 & 0x0F;
+        }
+        for i in 0..4 {
+            self.rtc_memory[3 + i] = (day >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn set_clock(&mut self) {
+        let nibbles = |range: std::ops::Range<usize>| {
+            range.rev().fold(0u64, |acc, i| (acc << 4) | self.rtc_memory[i] as u64)
+        };
+        let minutes = nibbles(3..7) * MINUTES_PER_DAY + nibbles(0..3);
+        self.rtc_secs = minutes * 60;
+        self.ram_updated = true;
+    }
+}
+
+impl MBC for HuC3 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 {
+            a as usize
+        } else {
+            (self.rombank * 0x4000) | ((a as usize) & 0x3FFF)
+        };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        match self.mode {
+            0x00 | 0x0A => *self.ram.get(self.ram_address(a)).unwrap_or(&0xFF),
+            0x0C => (self.rtc_command & 0xF0) | self.rtc_read,
+            // Semaphore: the RTC is always ready.
+            0x0D => 0x01,
+            // IR: no light received.
+            0x0E => 0xC0,
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.mode = v & 0x0F,
+            0x2000..=0x3FFF => self.rombank = (v as usize & 0x7F) % self.rombanks,
+            0x4000..=0x5FFF => {
+                if self.rambanks > 0 {
+                    self.rambank = (v as usize & 0x03) % self.rambanks;
+                }
+            }
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (HuC3)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        match self.mode {
+            0x0A => {
+                let address = self.ram_address(a);
+                if address < self.ram.len() {
+                    self.ram[address] = v;
+                    self.ram_updated = true;
+                }
+            }
+            0x0B => self.rtc_write(v),
+            _ => {}
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    // Saves are the RAM followed by the RTC trailer; RAM-only saves from other emulators
+    // leave the clock alone. Older saves of ours stored the clock base (8 bytes,
+    // big-endian) and the RTC memory before the RAM.
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        let len = self.ram.len();
+        match ramdata.len() {
+            n if n == len => self.ram = ramdata.to_vec(),
+            n if n == len + RTC_TRAILER_SIZE => {
+                let (ram, trailer) = ramdata.split_at(len);
+                let (memory, clock) = trailer.split_at(0x100);
+                let secs = u64::from_le_bytes(clock[0..8].try_into().unwrap());
+                let saved_at = u64::from_le_bytes(clock[8..16].try_into().unwrap());
+                self.ram = ram.to_vec();
+                self.rtc_memory = memory.to_vec();
+                self.rtc_secs = secs;
+                self.rtc_saved_at = Some(saved_at);
+            }
+            n if n == 8 + 0x100 + len => {
+                let (zero, rest) = ramdata.split_at(8);
+                let (memory, ram) = rest.split_at(0x100);
+                // The clock read zero at that unix time; catching up brings it to now.
+                self.rtc_secs = 0;
+                self.rtc_saved_at = Some(u64::from_be_bytes(zero.try_into().unwrap()));
+                self.rtc_memory = memory.to_vec();
+                self.ram = ram.to_vec();
+            }
+            _ => return Err("Loaded RAM has incorrect length"),
+        }
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.ram.clone();
+        file.extend_from_slice(&self.rtc_memory);
+        file.extend_from_slice(&self.rtc_secs.to_le_bytes());
+        file.extend_from_slice(&self.now().to_le_bytes());
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        self.rtc_clock = unix_secs;
+    }
+
+    fn catch_up_rtc(&mut self) {
+        if let Some(saved_at) = self.rtc_saved_at.take() {
+            self.rtc_secs += self.now().saturating_sub(saved_at);
+        }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.rtc_ticks += ticks;
+        while self.rtc_ticks >= RTC_TICKS_PER_SECOND {
+            self.rtc_ticks -= RTC_TICKS_PER_SECOND;
+            self.rtc_secs += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HuC3, RTC_TICKS_PER_SECOND};
+    use crate::mbc::MBC;
+
+    #[test]
+    fn rtc_set_and_latch() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFE;
+        rom[0x149] = 0x02;
+        let mut mbc = HuC3::new(rom).unwrap();
+        mbc.set_rtc_clock(Some(1_000_000));
+        mbc.writerom(0x0000, 0x0B);
+        // Index 0, then write 1 day and 59 minutes (0x03B) into nibbles 0-6.
+        mbc.writeram(0xA000, 0x40);
+        mbc.writeram(0xA000, 0x50);
+        for nibble in [0xB, 0x3, 0x0, 0x1, 0x0, 0x0, 0x0] {
+            mbc.writeram(0xA000, 0x30 | nibble);
+        }
+        mbc.writeram(0xA000, 0x61);
+
+        for _ in 0..90 {
+            mbc.do_cycle(RTC_TICKS_PER_SECOND);
+        }
+        mbc.writeram(0xA000, 0x60);
+        mbc.writeram(0xA000, 0x40);
+        let mut nibbles = vec![];
+        for _ in 0..7 {
+            mbc.writerom(0x0000, 0x0B);
+            mbc.writeram(0xA000, 0x10);
+            mbc.writerom(0x0000, 0x0C);
+            nibbles.push(mbc.readram(0xA000));
+        }
+        // One minute later: 1 day, 60 minutes.
+        assert_eq!(nibbles, [0x1C, 0x13, 0x10, 0x11, 0x10, 0x10, 0x10]);
+
+        let saved = mbc.dumpram();
+        assert_eq!(&saved[..0x2000], &mbc.ram[..]);
+        mbc.loadram(&saved).unwrap();
+        assert_eq!(mbc.dumpram(), saved);
+
+        // Catching up on loading adds the time since the save.
+        let secs = mbc.rtc_secs;
+        mbc.set_rtc_clock(Some(1_000_000 + 600));
+        mbc.catch_up_rtc();
+        assert_eq!(mbc.rtc_secs, secs + 600);
+    }
+
+    #[test]
+    fn loads_ram_only_and_old_saves() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFE;
+        rom[0x149] = 0x02;
+        let mut mbc = HuC3::new(rom).unwrap();
+        mbc.set_rtc_clock(Some(1_000_000));
+
+        let ram = vec![0x5A; 0x2000];
+        mbc.loadram(&ram).unwrap();
+        assert_eq!(mbc.ram, ram);
+
+        // Clock base, RTC memory, then the RAM.
+        let mut old = 999_940u64.to_be_bytes().to_vec();
+        old.extend_from_slice(&[0x03; 0x100]);
+        old.extend_from_slice(&[0xA5; 0x2000]);
+        mbc.loadram(&old).unwrap();
+        mbc.catch_up_rtc();
+        assert_eq!(mbc.rtc_secs, 60);
+        assert_eq!(mbc.rtc_memory, [0x03; 0x100]);
+        assert_eq!(mbc.ram, [0xA5; 0x2000]);
+
+        assert!(mbc.loadram(&[0; 0x2001]).is_err());
+    }
+}
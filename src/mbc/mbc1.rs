@@ -1,6 +1,10 @@
-use crate::mbc::{ram_banks, rom_banks, MBC};
+use crate::mbc::{ram_banks, rom_banks, MBC, NINTENDO_LOGO};
 use crate::StrResult;
 
+// BANK1 (0x2000-0x3FFF) holds the low five bits of the ROM bank, BANK2 (0x4000-0x5FFF)
+// two more bits. BANK2 always applies to 0x4000-0x7FFF; in mode 1 it also applies to
+// 0x0000-0x3FFF and selects the RAM bank. MBC1M multicarts leave bit 4 of BANK1
+// unconnected, so BANK2 lands on bits 4-5 and each game sees a 256KB slice.
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct MBC1 {
     rom: Vec<u8>,
@@ -8,8 +12,9 @@ pub struct MBC1 {
     ram_on: bool,
     ram_updated: bool,
     banking_mode: u8,
-    rombank: usize,
-    rambank: usize,
+    bank1: usize,
+    bank2: usize,
+    multicart: bool,
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
@@ -24,14 +29,16 @@ impl MBC1 {
         };
         let rombanks = rom_banks(data[0x148]);
         let ramsize = rambanks * 0x2000;
+        let multicart = is_multicart(&data);
 
         let res = MBC1 {
             rom: data,
             ram: ::std::iter::repeat(0u8).take(ramsize).collect(),
             ram_on: false,
             banking_mode: 0,
-            rombank: 1,
-            rambank: 0,
+            bank1: 1,
+            bank2: 0,
+            multicart,
             ram_updated: false,
             has_battery: has_battery,
             rombanks: rombanks,
@@ -40,18 +47,44 @@ impl MBC1 {
 
         Ok(res)
     }
+
+    fn bank_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rombank_low(&self) -> usize {
+        match self.banking_mode {
+            0 => 0,
+            _ => (self.bank2 << self.bank_shift()) % self.rombanks,
+        }
+    }
+
+    fn rombank_high(&self) -> usize {
+        let mask = (1 << self.bank_shift()) - 1;
+        ((self.bank2 << self.bank_shift()) | (self.bank1 & mask)) % self.rombanks
+    }
+
+    fn ram_address(&self, a: u16) -> usize {
+        let rambank = if self.banking_mode == 1 && self.rambanks > 1 {
+            self.bank2 % self.rambanks
+        } else {
+            0
+        };
+        (rambank * 0x2000) | ((a & 0x1FFF) as usize)
+    }
+}
+
+// MBC1M compilations are 1MB and carry a second game header, logo included, at bank 0x10.
+fn is_multicart(data: &[u8]) -> bool {
+    data.len() == 0x100000 && data[0x40104..0x40134] == NINTENDO_LOGO
 }
 
 impl MBC for MBC1 {
     fn readrom(&self, a: u16) -> u8 {
         let bank = if a < 0x4000 {
-            if self.banking_mode == 0 {
-                0
-            } else {
-                self.rombank & 0xE0
-            }
+            self.rombank_low()
         } else {
-            self.rombank
+            self.rombank_high()
         };
         let idx = bank * 0x4000 | ((a as usize) & 0x3FFF);
         *self.rom.get(idx).unwrap_or(&0xFF)
@@ -60,12 +93,7 @@ impl MBC for MBC1 {
         if !self.ram_on {
             return 0xFF;
         }
-        let rambank = if self.banking_mode == 1 {
-            self.rambank
-        } else {
-            0
-        };
-        self.ram[(rambank * 0x2000) | ((a & 0x1FFF) as usize)]
+        *self.ram.get(self.ram_address(a)).unwrap_or(&0xFF)
     }
 
     fn writerom(&mut self, a: u16, v: u8) {
@@ -74,20 +102,14 @@ impl MBC for MBC1 {
                 self.ram_on = v & 0xF == 0xA;
             }
             0x2000..=0x3FFF => {
-                let lower_bits = match (v as usize) & 0x1F {
+                // The zero check sees all five bits, even on multicarts.
+                self.bank1 = match (v as usize) & 0x1F {
                     0 => 1,
                     n => n,
                 };
-                self.rombank = ((self.rombank & 0x60) | lower_bits) % self.rombanks;
             }
             0x4000..=0x5FFF => {
-                if self.rombanks > 0x20 {
-                    let upper_bits = (v as usize & 0x03) % (self.rombanks >> 5);
-                    self.rombank = self.rombank & 0x1F | (upper_bits << 5)
-                }
-                if self.rambanks > 1 {
-                    self.rambank = (v as usize) & 0x03;
-                }
+                self.bank2 = (v as usize) & 0x03;
             }
             0x6000..=0x7FFF => {
                 self.banking_mode = v & 0x01;
@@ -100,12 +122,7 @@ impl MBC for MBC1 {
         if !self.ram_on {
             return;
         }
-        let rambank = if self.banking_mode == 1 {
-            self.rambank
-        } else {
-            0
-        };
-        let address = (rambank * 0x2000) | ((a & 0x1FFF) as usize);
+        let address = self.ram_address(a);
         if address < self.ram.len() {
             self.ram[address] = v;
             self.ram_updated = true;
@@ -135,4 +152,60 @@ impl MBC for MBC1 {
         self.ram_updated = false;
         result
     }
+
+    fn rombank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x3FFF => self.rombank_low(),
+            _ => self.rombank_high(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC1;
+    use crate::mbc::{MBC, NINTENDO_LOGO};
+
+    // 1MB ROM with each bank's number written at its start.
+    fn rom(multicart: bool) -> Vec<u8> {
+        let mut data = vec![0; 0x100000];
+        for bank in 0..64 {
+            data[bank * 0x4000] = bank as u8;
+        }
+        data[0x147] = 0x03;
+        data[0x148] = 0x05;
+        data[0x149] = 0x02;
+        data[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        if multicart {
+            data[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        }
+        data
+    }
+
+    #[test]
+    fn large_rom_mode_1_banks_low_area() {
+        let mut mbc = MBC1::new(rom(false)).unwrap();
+        mbc.writerom(0x2000, 0x03);
+        mbc.writerom(0x4000, 0x01);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x00, 0x23));
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x20, 0x23));
+        mbc.writerom(0x2000, 0x00);
+        assert_eq!(mbc.readrom(0x4000), 0x21);
+    }
+
+    #[test]
+    fn multicart_uses_four_bank_bits() {
+        assert!(!MBC1::new(rom(false)).unwrap().multicart);
+        let mut mbc = MBC1::new(rom(true)).unwrap();
+        assert!(mbc.multicart);
+        mbc.writerom(0x2000, 0x12);
+        mbc.writerom(0x4000, 0x01);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x00, 0x12));
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!((mbc.readrom(0x0000), mbc.rombank(0x0000)), (0x10, 0x10));
+        // 0x10 passes the zero check but maps to the first bank of the slice.
+        mbc.writerom(0x2000, 0x10);
+        assert_eq!(mbc.readrom(0x4000), 0x10);
+    }
 }
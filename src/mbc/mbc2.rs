@@ -94,4 +94,8 @@ impl MBC for MBC2 {
         self.ram_updated = false;
         result
     }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
 }
@@ -2,9 +2,16 @@ use crate::mbc::{ram_banks, MBC};
 use crate::StrResult;
 
 use std::convert::TryInto;
-use std::io::prelude::*;
 use std::time;
 
+// System clocks per RTC second.
+const RTC_TICKS_PER_SECOND: u32 = 4_194_304;
+// Footer appended to the RAM in saves of RTC carts, in the layout shared by most
+// emulators: the live and latched registers as little-endian u32s, then the unix time of
+// the save as a little-endian u64 (older files use a u32, for 44 bytes in total).
+const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_32: usize = 44;
+
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct MBC3 {
     rom: Vec<u8>,
@@ -12,13 +19,21 @@ pub struct MBC3 {
     rombank: usize,
     rambank: usize,
     rambanks: usize,
+    // MBC30: 8 RAM banks and 8-bit ROM bank numbers.
+    mbc30: bool,
     selectrtc: bool,
     ram_on: bool,
     ram_updated: bool,
     has_battery: bool,
+    has_rtc: bool,
     rtc_ram: [u8; 5],
     rtc_ram_latch: [u8; 5],
-    rtc_zero: Option<u64>,
+    // System clocks since the seconds register last ticked.
+    rtc_ticks: u32,
+    // Unix time the loaded save was written at, until `catch_up_rtc` consumes it.
+    rtc_saved_at: Option<u64>,
+    // Wall clock override in unix seconds; None uses the host clock.
+    rtc_clock: Option<u64>,
 }
 
 impl MBC3 {
@@ -33,10 +48,8 @@ impl MBC3 {
             _ => 0,
         };
         let ramsize = rambanks * 0x2000;
-        let rtc = match subtype {
-            0x0F | 0x10 => Some(0),
-            _ => None,
-        };
+        let mbc30 = rambanks > 4 || data.len() > 0x200000;
+        let has_rtc = matches!(subtype, 0x0F | 0x10);
 
         let res = MBC3 {
             rom: data,
@@ -44,73 +57,99 @@ impl MBC3 {
             rombank: 1,
             rambank: 0,
             rambanks: rambanks,
+            mbc30,
             selectrtc: false,
             ram_on: false,
             ram_updated: false,
             has_battery: has_battery,
+            has_rtc,
             rtc_ram: [0u8; 5],
             rtc_ram_latch: [0u8; 5],
-            rtc_zero: rtc,
+            rtc_ticks: 0,
+            rtc_saved_at: None,
+            rtc_clock: None,
         };
 
         Ok(res)
     }
 
     fn latch_rtc_reg(&mut self) {
-        self.calc_rtc_reg();
         self.rtc_ram_latch.clone_from_slice(&self.rtc_ram);
     }
 
-    fn calc_rtc_reg(&mut self) {
-        // Do not modify regs when halted
-        if self.rtc_ram[4] & 0x40 == 0x40 {
+    fn rtc_halted(&self) -> bool {
+        self.rtc_ram[4] & 0x40 == 0x40
+    }
+
+    // Each counter wraps at its register width without a carry when set out of range,
+    // like the real chip.
+    fn tick_rtc_second(&mut self) {
+        let r = &mut self.rtc_ram;
+        r[0] = (r[0] + 1) & 0x3F;
+        if r[0] != 60 {
             return;
         }
-
-        let tzero = match self.rtc_zero {
-            Some(t) => time::UNIX_EPOCH + time::Duration::from_secs(t),
-            None => return,
-        };
-
-        if self.compute_difftime() == self.rtc_zero {
-            // No time has passed. Do not alter registers
+        r[0] = 0;
+        r[1] = (r[1] + 1) & 0x3F;
+        if r[1] != 60 {
+            return;
+        }
+        r[1] = 0;
+        r[2] = (r[2] + 1) & 0x1F;
+        if r[2] != 24 {
             return;
         }
+        r[2] = 0;
+        let days = (((r[4] & 0x01) as u16) << 8 | r[3] as u16) + 1;
+        r[3] = days as u8;
+        r[4] = (r[4] & 0xFE) | ((days >> 8) & 0x01) as u8;
+        if days == 512 {
+            r[4] |= 0x80;
+        }
+    }
 
-        let difftime = match time::SystemTime::now().duration_since(tzero) {
-            Ok(n) => n.as_secs(),
-            _ => 0,
-        };
-        self.rtc_ram[0] = (difftime % 60) as u8;
-        self.rtc_ram[1] = ((difftime / 60) % 60) as u8;
-        self.rtc_ram[2] = ((difftime / 3600) % 24) as u8;
-        let days = difftime / (3600 * 24);
+    fn advance_rtc(&mut self, mut secs: u64) {
+        // Step through out of range values one second at a time, then add the rest at once.
+        while secs > 0 && (self.rtc_ram[0] >= 60 || self.rtc_ram[1] >= 60 || self.rtc_ram[2] >= 24) {
+            self.tick_rtc_second();
+            secs -= 1;
+        }
+        let days = ((self.rtc_ram[4] as u64 & 0x01) << 8) | self.rtc_ram[3] as u64;
+        let total = self.rtc_ram[0] as u64
+            + self.rtc_ram[1] as u64 * 60
+            + self.rtc_ram[2] as u64 * 3600
+            + days * 86400
+            + secs;
+        let days = total / 86400;
+        self.rtc_ram[0] = (total % 60) as u8;
+        self.rtc_ram[1] = (total / 60 % 60) as u8;
+        self.rtc_ram[2] = (total / 3600 % 24) as u8;
         self.rtc_ram[3] = days as u8;
-        self.rtc_ram[4] = (self.rtc_ram[4] & 0xFE) | (((days >> 8) & 0x01) as u8);
+        self.rtc_ram[4] = (self.rtc_ram[4] & 0xFE) | ((days >> 8) & 0x01) as u8;
         if days >= 512 {
             self.rtc_ram[4] |= 0x80;
-            self.calc_rtc_zero();
         }
     }
 
-    fn compute_difftime(&self) -> Option<u64> {
-        if self.rtc_zero.is_none() {
-            return None;
+    fn now(&self) -> u64 {
+        match self.rtc_clock {
+            Some(t) => t,
+            None => time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
         }
-        let mut difftime = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-            Ok(t) => t.as_secs(),
-            Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
-        };
-        difftime -= self.rtc_ram[0] as u64;
-        difftime -= (self.rtc_ram[1] as u64) * 60;
-        difftime -= (self.rtc_ram[2] as u64) * 3600;
-        let days = ((self.rtc_ram[4] as u64 & 0x1) << 8) | (self.rtc_ram[3] as u64);
-        difftime -= days * 3600 * 24;
-        Some(difftime)
     }
 
-    fn calc_rtc_zero(&mut self) {
-        self.rtc_zero = self.compute_difftime();
+    fn load_rtc_footer(&mut self, footer: &[u8]) {
+        let u32_at = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+        for i in 0..5 {
+            self.rtc_ram[i] = u32_at(i * 4) as u8;
+            self.rtc_ram_latch[i] = u32_at(20 + i * 4) as u8;
+        }
+        self.rtc_saved_at = Some(match footer.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            _ => u32_at(40) as u64,
+        });
     }
 }
 
@@ -129,7 +168,7 @@ impl MBC for MBC3 {
         }
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)]
-        } else if self.selectrtc && self.rambank < 5 {
+        } else if self.selectrtc && self.has_rtc && self.rambank < 5 {
             self.rtc_ram_latch[self.rambank]
         } else {
             0xFF
@@ -139,7 +178,8 @@ impl MBC for MBC3 {
         match a {
             0x0000..=0x1FFF => self.ram_on = (v & 0x0F) == 0x0A,
             0x2000..=0x3FFF => {
-                self.rombank = match v & 0x7F {
+                let mask = if self.mbc30 { 0xFF } else { 0x7F };
+                self.rombank = match v & mask {
                     0 => 1,
                     n => n as usize,
                 }
@@ -159,16 +199,18 @@ impl MBC for MBC3 {
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)] = v;
             self.ram_updated = true;
-        } else if self.selectrtc && self.rambank < 5 {
-            self.calc_rtc_reg();
+        } else if self.selectrtc && self.has_rtc && self.rambank < 5 {
             let vmask = match self.rambank {
                 0 | 1 => 0x3F,
                 2 => 0x1F,
                 4 => 0xC1,
                 _ => 0xFF,
             };
+            // Writing the seconds restarts the current second.
+            if self.rambank == 0 {
+                self.rtc_ticks = 0;
+            }
             self.rtc_ram[self.rambank] = v & vmask;
-            self.calc_rtc_zero();
             self.ram_updated = true;
         }
     }
@@ -177,37 +219,42 @@ impl MBC for MBC3 {
         self.has_battery
     }
 
+    // Saves hold the RAM, followed by the RTC footer on RTC carts. Older saves of ours
+    // stored the unix time the clock read zero, big-endian, before the RAM.
     fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
-        if ramdata.len() != 8 + self.ram.len() {
-            return Err("Loaded ram is too small");
-        }
-
-        let (int_bytes, rest) = ramdata.split_at(8);
-        let rtc = u64::from_be_bytes(int_bytes.try_into().unwrap());
-        if self.rtc_zero.is_some() {
-            self.rtc_zero = Some(rtc);
+        let len = self.ram.len();
+        match ramdata.len() {
+            n if n == len => self.ram = ramdata.to_vec(),
+            n if n == len + RTC_FOOTER_SIZE || n == len + RTC_FOOTER_SIZE_32 => {
+                let (ram, footer) = ramdata.split_at(len);
+                self.ram = ram.to_vec();
+                if self.has_rtc {
+                    self.load_rtc_footer(footer);
+                }
+            }
+            n if n == 8 + len => {
+                let (zero, ram) = ramdata.split_at(8);
+                self.ram = ram.to_vec();
+                let zero = u64::from_be_bytes(zero.try_into().unwrap());
+                // The clock read zero at that unix time; catching up brings it to now.
+                if self.has_rtc && zero != 0 {
+                    self.rtc_ram = [0; 5];
+                    self.rtc_saved_at = Some(zero);
+                }
+            }
+            _ => return Err("Loaded RAM has incorrect length"),
         }
-        self.ram = rest.to_vec();
         Ok(())
     }
 
     fn dumpram(&self) -> Vec<u8> {
-        let rtc = match self.rtc_zero {
-            Some(t) => t,
-            None => 0,
-        };
-
-        let mut file = vec![];
-
-        let mut ok = true;
-        if ok {
-            let rtc_bytes = rtc.to_be_bytes();
-            ok = file.write_all(&rtc_bytes).is_ok();
-        };
-        if ok {
-            let _ = file.write_all(&*self.ram);
-        };
-
+        let mut file = self.ram.clone();
+        if self.has_rtc {
+            for v in self.rtc_ram.iter().chain(self.rtc_ram_latch.iter()) {
+                file.extend_from_slice(&(*v as u32).to_le_bytes());
+            }
+            file.extend_from_slice(&self.now().to_le_bytes());
+        }
         file
     }
 
@@ -216,4 +263,119 @@ impl MBC for MBC3 {
         self.ram_updated = false;
         result
     }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        self.rtc_clock = unix_secs;
+    }
+
+    fn catch_up_rtc(&mut self) {
+        if let Some(saved_at) = self.rtc_saved_at.take()
+            && !self.rtc_halted()
+        {
+            self.advance_rtc(self.now().saturating_sub(saved_at));
+        }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if !self.has_rtc || self.rtc_halted() {
+            return;
+        }
+        self.rtc_ticks += ticks;
+        while self.rtc_ticks >= RTC_TICKS_PER_SECOND {
+            self.rtc_ticks -= RTC_TICKS_PER_SECOND;
+            self.tick_rtc_second();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MBC3, RTC_TICKS_PER_SECOND};
+    use crate::mbc::MBC;
+
+    fn mbc3(subtype: u8, ramsize: u8) -> MBC3 {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = subtype;
+        rom[0x149] = ramsize;
+        let mut mbc = MBC3::new(rom).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc
+    }
+
+    fn read_rtc(mbc: &mut MBC3) -> [u8; 5] {
+        mbc.writerom(0x6000, 0x00);
+        mbc.writerom(0x6000, 0x01);
+        let mut regs = [0; 5];
+        for (i, r) in regs.iter_mut().enumerate() {
+            mbc.writerom(0x4000, 0x08 + i as u8);
+            *r = mbc.readram(0xA000);
+        }
+        regs
+    }
+
+    #[test]
+    fn rtc_follows_emulated_time() {
+        let mut mbc = mbc3(0x10, 0x03);
+        // 23:59:59 on day 511.
+        for (reg, v) in [(0x08, 59), (0x09, 59), (0x0A, 23), (0x0B, 0xFF), (0x0C, 0x01)] {
+            mbc.writerom(0x4000, reg);
+            mbc.writeram(0xA000, v);
+        }
+        mbc.do_cycle(RTC_TICKS_PER_SECOND - 1);
+        assert_eq!(read_rtc(&mut mbc), [59, 59, 23, 0xFF, 0x01]);
+        mbc.do_cycle(1);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0x80]);
+
+        // Halted clocks do not advance.
+        mbc.writerom(0x4000, 0x0C);
+        mbc.writeram(0xA000, 0x40);
+        mbc.do_cycle(RTC_TICKS_PER_SECOND * 2);
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0x40]);
+    }
+
+    #[test]
+    fn rtc_footer_round_trip_and_catch_up() {
+        let mut mbc = mbc3(0x10, 0x03);
+        mbc.set_rtc_clock(Some(1_000_000));
+        mbc.writerom(0x4000, 0x0A);
+        mbc.writeram(0xA000, 5);
+        let saved = mbc.dumpram();
+        assert_eq!(saved.len(), 4 * 0x2000 + 48);
+        assert_eq!(&saved[4 * 0x2000 + 8..4 * 0x2000 + 12], &[5, 0, 0, 0]);
+
+        let mut loaded = mbc3(0x10, 0x03);
+        loaded.set_rtc_clock(Some(1_000_000 + 3 * 86400 + 61));
+        loaded.loadram(&saved).unwrap();
+        assert_eq!(read_rtc(&mut loaded), [0, 0, 5, 0, 0]);
+        loaded.catch_up_rtc();
+        assert_eq!(read_rtc(&mut loaded), [1, 1, 5, 3, 0]);
+    }
+
+    #[test]
+    fn old_saves_only_advance_when_catching_up() {
+        let mut old = (1_000_000u64 - 3661).to_be_bytes().to_vec();
+        old.extend_from_slice(&[0; 4 * 0x2000]);
+        let mut mbc = mbc3(0x10, 0x03);
+        mbc.set_rtc_clock(Some(1_000_000));
+        mbc.loadram(&old).unwrap();
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0, 0]);
+        mbc.catch_up_rtc();
+        assert_eq!(read_rtc(&mut mbc), [1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn mbc30_has_eight_ram_banks() {
+        let mut mbc = mbc3(0x10, 0x05);
+        for bank in 0..8 {
+            mbc.writerom(0x4000, bank);
+            mbc.writeram(0xA000, bank + 1);
+        }
+        mbc.writerom(0x4000, 0x07);
+        assert_eq!(mbc.readram(0xA000), 8);
+        assert_eq!(mbc.dumpram().len(), 8 * 0x2000 + 48);
+    }
 }
@@ -0,0 +1,224 @@
+use crate::mbc::MBC;
+use crate::StrResult;
+
+const RAM_SIZE: usize = 0x8000;
+const FLASH_SIZE: usize = 0x100000;
+// Macronix MX29F008 manufacturer and device IDs.
+const FLASH_ID: [u8; 2] = [0xC2, 0x81];
+
+// MBC6 splits 0x4000-0x7FFF into two 8KB windows (A and B) and 0xA000-0xBFFF into two
+// 4KB RAM windows. Each ROM window maps either ROM or a 1MB flash chip, which is saved
+// along with the RAM and programmed through the usual AA/55 unlock sequences.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct MBC6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    ram_on: bool,
+    ram_updated: bool,
+    flash_on: bool,
+    flash_write: bool,
+    // 8KB bank and flash select for windows A and B.
+    rombank: [usize; 2],
+    use_flash: [bool; 2],
+    // 4KB RAM bank for windows A and B.
+    rambank: [usize; 2],
+    // Progress through a flash command sequence; see `flash_command`.
+    flash_step: u8,
+    flash_id: bool,
+}
+
+impl MBC6 {
+    pub fn new(data: Vec<u8>) -> StrResult<MBC6> {
+        Ok(MBC6 {
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            flash: vec![0xFF; FLASH_SIZE],
+            ram_on: false,
+            ram_updated: false,
+            flash_on: false,
+            flash_write: false,
+            rombank: [2, 3],
+            use_flash: [false; 2],
+            rambank: [0, 1],
+            flash_step: 0,
+            flash_id: false,
+        })
+    }
+
+    fn window(a: u16) -> usize {
+        ((a as usize) >> 13) & 1
+    }
+
+    fn flash_address(&self, a: u16) -> usize {
+        ((self.rombank[MBC6::window(a)] * 0x2000) | ((a as usize) & 0x1FFF)) % FLASH_SIZE
+    }
+
+    fn ram_address(&self, a: u16) -> usize {
+        (self.rambank[(a as usize >> 12) & 1] * 0x1000) | ((a as usize) & 0x0FFF)
+    }
+
+    // Unlock is AA to 5555 then 55 to 2AAA; a second unlock after 80 erases, with 30 for
+    // the 128KB sector containing the address or 10 for the whole chip. A0 programs one
+    // byte (clearing bits only), 90 enters ID mode and F0 leaves it.
+    fn flash_command(&mut self, address: usize, v: u8) {
+        let cmd = address & 0x7FFF;
+        self.flash_step = match (self.flash_step, cmd, v) {
+            (6, _, _) => {
+                self.flash[address] &= v;
+                self.ram_updated = true;
+                0
+            }
+            (_, _, 0xF0) => {
+                self.flash_id = false;
+                0
+            }
+            (0 | 3, 0x5555, 0xAA) => self.flash_step + 1,
+            (1 | 4, 0x2AAA, 0x55) => self.flash_step + 1,
+            (2, 0x5555, 0x80) => 3,
+            (2, 0x5555, 0x90) => {
+                self.flash_id = true;
+                0
+            }
+            (2, 0x5555, 0xA0) => 6,
+            (5, _, 0x30) => {
+                let sector = address & !0x1FFFF;
+                self.flash[sector..sector + 0x20000].fill(0xFF);
+                self.ram_updated = true;
+                0
+            }
+            (5, 0x5555, 0x10) => {
+                self.flash.fill(0xFF);
+                self.ram_updated = true;
+                0
+            }
+            _ => 0,
+        };
+    }
+}
+
+impl MBC for MBC6 {
+    fn readrom(&self, a: u16) -> u8 {
+        if a < 0x4000 {
+            return *self.rom.get(a as usize).unwrap_or(&0xFF);
+        }
+        let window = MBC6::window(a);
+        if self.use_flash[window] {
+            let address = self.flash_address(a);
+            if self.flash_id {
+                return FLASH_ID[address & 1];
+            }
+            return self.flash[address];
+        }
+        let idx = (self.rombank[window] * 0x2000) | ((a as usize) & 0x1FFF);
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on {
+            return 0xFF;
+        }
+        self.ram[self.ram_address(a)]
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        let v = v as usize;
+        match a {
+            0x0000..=0x03FF => self.ram_on = v & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.rambank[0] = v & 0x07,
+            0x0800..=0x0BFF => self.rambank[1] = v & 0x07,
+            0x0C00..=0x0FFF => self.flash_on = v & 0x01 != 0,
+            0x1000..=0x1FFF => self.flash_write = v & 0x01 != 0,
+            0x2000..=0x27FF => self.rombank[0] = v & 0x7F,
+            0x2800..=0x2FFF => self.use_flash[0] = v & 0x08 != 0,
+            0x3000..=0x37FF => self.rombank[1] = v & 0x7F,
+            0x3800..=0x3FFF => self.use_flash[1] = v & 0x08 != 0,
+            0x4000..=0x7FFF => {
+                if self.use_flash[MBC6::window(a)] && self.flash_on && self.flash_write {
+                    self.flash_command(self.flash_address(a), v as u8);
+                }
+            }
+            _ => panic!("Could not write to {:04X} (MBC6)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on {
+            return;
+        }
+        let address = self.ram_address(a);
+        self.ram[address] = v;
+        self.ram_updated = true;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    // Saves are the RAM followed by the flash.
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != RAM_SIZE + FLASH_SIZE {
+            return Err("Loaded RAM has incorrect length");
+        }
+        let (ram, flash) = ramdata.split_at(RAM_SIZE);
+        self.ram = ram.to_vec();
+        self.flash = flash.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        [&self.ram[..], &self.flash[..]].concat()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    // Banks are 8KB: window A is 0x4000-0x5FFF and window B 0x6000-0x7FFF.
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank[MBC6::window(a)] }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC6;
+    use crate::mbc::MBC;
+
+    #[test]
+    fn flash_program_and_erase() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x20;
+        let mut mbc = MBC6::new(rom).unwrap();
+        mbc.writerom(0x0C00, 0x01);
+        mbc.writerom(0x1000, 0x01);
+        mbc.writerom(0x2800, 0x08);
+        mbc.writerom(0x3800, 0x08);
+        // Window A at flash bank 2 reaches 0x5555, window B at bank 1 reaches 0x2AAA.
+        mbc.writerom(0x2000, 0x02);
+        mbc.writerom(0x3000, 0x01);
+        let unlock = |mbc: &mut MBC6| {
+            mbc.writerom(0x5555, 0xAA);
+            mbc.writerom(0x6AAA, 0x55);
+        };
+        unlock(&mut mbc);
+        mbc.writerom(0x5555, 0xA0);
+        mbc.writerom(0x4010, 0x5A);
+        assert_eq!(mbc.readrom(0x4010), 0x5A);
+        assert!(mbc.check_and_reset_ram_updated());
+        assert_eq!(mbc.dumpram()[0x8000 + 0x4010], 0x5A);
+
+        unlock(&mut mbc);
+        mbc.writerom(0x5555, 0x90);
+        assert_eq!((mbc.readrom(0x4000), mbc.readrom(0x4001)), (0xC2, 0x81));
+        mbc.writerom(0x4000, 0xF0);
+
+        unlock(&mut mbc);
+        mbc.writerom(0x5555, 0x80);
+        unlock(&mut mbc);
+        mbc.writerom(0x4010, 0x30);
+        assert_eq!(mbc.readrom(0x4010), 0xFF);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::mbc::{rom_banks, MBC};
+use crate::StrResult;
+
+const EEPROM_SIZE: usize = 256;
+// Accelerometer reading at rest and per g of tilt.
+const TILT_CENTER: f32 = 0x81D0 as f32;
+const TILT_PER_G: f32 = 0x70 as f32;
+
+// MBC7 carts have no RAM; 0xA000-0xAFFF holds registers for a two-axis accelerometer
+// and a bit-banged 93LC56 EEPROM (128 16-bit words) that serves as the save memory.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct MBC7 {
+    rom: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    ram_on: bool,
+    ram_on2: bool,
+    // Tilt in g (-1.0 to 1.0) as last reported by the host; positive x tilts right and
+    // positive y tilts the bottom of the screen down.
+    tilt: (f32, f32),
+    latch_erased: bool,
+    latch: (u16, u16),
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(data: Vec<u8>) -> StrResult<MBC7> {
+        let rombanks = rom_banks(data[0x148]);
+        Ok(MBC7 {
+            rom: data,
+            rombank: 1,
+            rombanks,
+            ram_on: false,
+            ram_on2: false,
+            tilt: (0.0, 0.0),
+            latch_erased: false,
+            latch: (0x8000, 0x8000),
+            eeprom: Eeprom::new(),
+        })
+    }
+
+    fn registers_on(&self) -> bool {
+        self.ram_on && self.ram_on2
+    }
+}
+
+impl MBC for MBC7 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 {
+            a as usize
+        } else {
+            (self.rombank * 0x4000) | ((a as usize) & 0x3FFF)
+        };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if !self.registers_on() || a >= 0xB000 {
+            return 0xFF;
+        }
+        match (a >> 4) & 0xF {
+            2 => self.latch.0 as u8,
+            3 => (self.latch.0 >> 8) as u8,
+            4 => self.latch.1 as u8,
+            5 => (self.latch.1 >> 8) as u8,
+            6 => 0x00,
+            8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rombank = (v as usize & 0x7F) % self.rombanks,
+            0x4000..=0x5FFF => self.ram_on2 = v == 0x40,
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (MBC7)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.registers_on() || a >= 0xB000 {
+            return;
+        }
+        match (a >> 4) & 0xF {
+            0 if v == 0x55 => {
+                self.latch_erased = true;
+                self.latch = (0x8000, 0x8000);
+            }
+            1 if v == 0xAA && self.latch_erased => {
+                self.latch_erased = false;
+                let axis = |g: f32| (TILT_CENTER + TILT_PER_G * g.clamp(-1.0, 1.0)) as u16;
+                self.latch = (axis(-self.tilt.0), axis(self.tilt.1));
+            }
+            8 => self.eeprom.write(v),
+            _ => {}
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != EEPROM_SIZE {
+            return Err("Loaded RAM has incorrect length");
+        }
+        self.eeprom.data = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.eeprom.data.clone()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.eeprom.updated;
+        self.eeprom.updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+enum Transfer {
+    // Shifting in the start bit, a 2-bit opcode and an 8-bit address.
+    Command,
+    // Shifting out `addr`, MSB first; reads continue into the next word.
+    Read { addr: u8, bit: u8 },
+    // Shifting in a word for `addr`, or for every word (WRAL) when None.
+    Write { addr: Option<u8>, bit: u8 },
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct Eeprom {
+    // Words are stored little-endian.
+    data: Vec<u8>,
+    updated: bool,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    out: bool,
+    write_enabled: bool,
+    shift: u16,
+    bits: u8,
+    transfer: Transfer,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: vec![0xFF; EEPROM_SIZE],
+            updated: false,
+            cs: false,
+            clk: false,
+            di: false,
+            out: true,
+            write_enabled: false,
+            shift: 0,
+            bits: 0,
+            transfer: Transfer::Command,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.out as u8
+    }
+
+    // Bit 7 is chip select, bit 6 the clock and bit 1 data in; the chip samples on the
+    // rising clock edge.
+    fn write(&mut self, v: u8) {
+        let (cs, clk, di) = (v & 0x80 != 0, v & 0x40 != 0, v & 0x02 != 0);
+        if !cs {
+            self.transfer = Transfer::Command;
+            self.bits = 0;
+            self.out = true;
+        } else if clk && !self.clk {
+            self.clock(di);
+        }
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+    }
+
+    fn clock(&mut self, di: bool) {
+        match self.transfer {
+            Transfer::Command => {
+                if self.bits == 0 && !di {
+                    return;
+                }
+                self.shift = (self.shift << 1) | di as u16;
+                self.bits += 1;
+                if self.bits == 11 {
+                    self.command();
+                }
+            }
+            Transfer::Read { addr, bit } => {
+                self.out = (self.word(addr) >> (15 - bit)) & 1 != 0;
+                self.transfer = match bit {
+                    15 => Transfer::Read { addr: (addr + 1) & 0x7F, bit: 0 },
+                    _ => Transfer::Read { addr, bit: bit + 1 },
+                };
+            }
+            Transfer::Write { addr, bit } => {
+                self.shift = (self.shift << 1) | di as u16;
+                if bit < 15 {
+                    self.transfer = Transfer::Write { addr, bit: bit + 1 };
+                    return;
+                }
+                if self.write_enabled {
+                    match addr {
+                        Some(addr) => self.set_word(addr, self.shift),
+                        None => (0..0x80).for_each(|addr| self.set_word(addr, self.shift)),
+                    }
+                }
+                self.out = true;
+                self.transfer = Transfer::Command;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn command(&mut self) {
+        let addr = (self.shift & 0x7F) as u8;
+        self.bits = 0;
+        match (self.shift >> 8) & 0x03 {
+            // READ: a dummy zero bit precedes the data.
+            2 => {
+                self.out = false;
+                self.transfer = Transfer::Read { addr, bit: 0 };
+            }
+            1 => self.transfer = Transfer::Write { addr: Some(addr), bit: 0 },
+            // ERASE
+            3 => {
+                if self.write_enabled {
+                    self.set_word(addr, 0xFFFF);
+                }
+                self.out = true;
+            }
+            _ => match (self.shift >> 6) & 0x03 {
+                0 => self.write_enabled = false,
+                3 => self.write_enabled = true,
+                // ERAL
+                2 => {
+                    if self.write_enabled {
+                        (0..0x80).for_each(|addr| self.set_word(addr, 0xFFFF));
+                    }
+                    self.out = true;
+                }
+                _ => self.transfer = Transfer::Write { addr: None, bit: 0 },
+            },
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let i = addr as usize * 2;
+        u16::from_le_bytes([self.data[i], self.data[i + 1]])
+    }
+
+    fn set_word(&mut self, addr: u8, v: u16) {
+        let i = addr as usize * 2;
+        self.data[i..i + 2].copy_from_slice(&v.to_le_bytes());
+        self.updated = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC7;
+    use crate::mbc::MBC;
+
+    fn mbc7() -> MBC7 {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x22;
+        let mut mbc = MBC7::new(rom).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x40);
+        mbc
+    }
+
+    // Clocks `count` bits of `value` into the EEPROM, MSB first, returning the bits read
+    // back on the rising edges.
+    fn shift(mbc: &mut MBC7, value: u32, count: u32) -> u32 {
+        let mut out = 0;
+        for i in (0..count).rev() {
+            let di = ((value >> i) & 1) as u8 * 0x02;
+            mbc.writeram(0xA080, 0x80 | di);
+            mbc.writeram(0xA080, 0xC0 | di);
+            out = (out << 1) | (mbc.readram(0xA080) & 1) as u32;
+        }
+        out
+    }
+
+    // Start bit, 2-bit opcode and 8-bit address.
+    fn command(mbc: &mut MBC7, opcode: u32, addr: u32) {
+        mbc.writeram(0xA080, 0x00);
+        shift(mbc, 0x04 | opcode, 3);
+        shift(mbc, addr, 8);
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc7();
+        command(&mut mbc, 0, 0xC0); // EWEN
+        command(&mut mbc, 1, 5); // WRITE
+        shift(&mut mbc, 0xBEEF, 16);
+        mbc.writeram(0xA080, 0x00);
+        assert!(mbc.check_and_reset_ram_updated());
+        assert_eq!(&mbc.dumpram()[10..12], &[0xEF, 0xBE]);
+
+        command(&mut mbc, 2, 5); // READ
+        assert_eq!(mbc.readram(0xA080) & 1, 0);
+        assert_eq!(shift(&mut mbc, 0, 16), 0xBEEF);
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc = mbc7();
+        mbc.set_tilt(0.5, -1.0);
+        mbc.writeram(0xA010, 0xAA);
+        assert_eq!(mbc.readram(0xA030), 0x80);
+        mbc.writeram(0xA000, 0x55);
+        mbc.writeram(0xA010, 0xAA);
+        let x = u16::from_le_bytes([mbc.readram(0xA020), mbc.readram(0xA030)]);
+        let y = u16::from_le_bytes([mbc.readram(0xA040), mbc.readram(0xA050)]);
+        assert_eq!((x, y), (0x81D0 - 0x38, 0x81D0 - 0x70));
+    }
+}
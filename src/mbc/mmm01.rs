@@ -0,0 +1,226 @@
+use crate::mbc::{ram_banks, MBC, NINTENDO_LOGO};
+use crate::StrResult;
+
+// MMM01 multicarts boot unmapped, with the last 32KB of ROM (the menu) at 0x0000-0x7FFF.
+// The menu writes the selected game's base bank and size masks, then sets the map enable
+// bit, after which the cart behaves like an MBC1 confined to that game's slice.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct MMM01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_on: bool,
+    ram_updated: bool,
+    has_battery: bool,
+    mapped: bool,
+    rom_low: usize,
+    rom_mid: usize,
+    rom_high: usize,
+    // Bits of `rom_low` (1-4) and `ram_low` (0-1) frozen once mapped.
+    rom_mask: usize,
+    ram_mask: usize,
+    ram_low: usize,
+    ram_high: usize,
+    banking_mode: u8,
+    mode_locked: bool,
+    rombanks: usize,
+    rambanks: usize,
+}
+
+/// Offset of the cartridge header describing the MMM01 itself: dumps usually start with
+/// the first game, and the menu's full header (logo included) sits in the last 32KB.
+pub fn header_offset(data: &[u8]) -> Option<usize> {
+    if matches!(data[0x147], 0x0B..=0x0D) {
+        return Some(0);
+    }
+    let offset = data.len().checked_sub(0x8000).filter(|&offset| offset > 0)?;
+    let header = &data[offset..];
+    (matches!(header[0x147], 0x0B..=0x0D) && header[0x104..0x134] == NINTENDO_LOGO).then_some(offset)
+}
+
+impl MMM01 {
+    pub fn new(data: Vec<u8>) -> StrResult<MMM01> {
+        let header = header_offset(&data).ok_or("Not an MMM01 cartridge")?;
+        let (has_battery, rambanks) = match data[header + 0x147] {
+            0x0C => (false, ram_banks(data[header + 0x149])),
+            0x0D => (true, ram_banks(data[header + 0x149])),
+            _ => (false, 0),
+        };
+        let rombanks = (data.len() / 0x4000).next_power_of_two().max(2);
+        Ok(MMM01 {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            ram_on: false,
+            ram_updated: false,
+            has_battery,
+            mapped: false,
+            rom_low: 0,
+            rom_mid: 0,
+            rom_high: 0,
+            rom_mask: 0,
+            ram_mask: 0,
+            ram_low: 0,
+            ram_high: 0,
+            banking_mode: 0,
+            mode_locked: false,
+            rombanks,
+            rambanks,
+        })
+    }
+
+    fn rom_base(&self) -> usize {
+        let base = self.rom_high << 7 | self.rom_mid << 5;
+        // Unmapped, bits 1-8 of the bank read as ones: the last 32KB.
+        if self.mapped { base } else { base | 0x1FE }
+    }
+
+    fn rombank_low(&self) -> usize {
+        (self.rom_base() | (self.rom_low & self.rom_mask << 1)) % self.rombanks
+    }
+
+    fn rombank_high(&self) -> usize {
+        // As on MBC1, only the bits the game can change are checked for zero.
+        let low = match self.rom_low & !(self.rom_mask << 1) {
+            0 => self.rom_low | 1,
+            _ => self.rom_low,
+        };
+        (self.rom_base() | low) % self.rombanks
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if !self.ram_on || self.rambanks == 0 {
+            return None;
+        }
+        let low = if self.banking_mode == 1 { self.ram_low } else { 0 };
+        let bank = (self.ram_high << 2 | low) % self.rambanks;
+        Some((bank * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+
+    // Writes only touch the bits not frozen by `mask`.
+    fn masked(old: usize, new: usize, mask: usize) -> usize {
+        (old & mask) | (new & !mask)
+    }
+}
+
+impl MBC for MMM01 {
+    fn readrom(&self, a: u16) -> u8 {
+        let bank = if a < 0x4000 {
+            self.rombank_low()
+        } else {
+            self.rombank_high()
+        };
+        *self.rom.get((bank * 0x4000) | ((a as usize) & 0x3FFF)).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        match self.ram_address(a) {
+            Some(address) => self.ram[address],
+            None => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        let v = v as usize;
+        match a {
+            0x0000..=0x1FFF => {
+                self.ram_on = v & 0x0F == 0x0A;
+                if !self.mapped {
+                    self.ram_mask = v >> 4 & 0x03;
+                    self.mapped = v & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let mask = if self.mapped { self.rom_mask << 1 } else { 0 };
+                self.rom_low = MMM01::masked(self.rom_low, v & 0x1F, mask);
+                if !self.mapped {
+                    self.rom_mid = v >> 5 & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                let mask = if self.mapped { self.ram_mask } else { 0 };
+                self.ram_low = MMM01::masked(self.ram_low, v & 0x03, mask);
+                if !self.mapped {
+                    self.ram_high = v >> 2 & 0x03;
+                    self.rom_high = v >> 4 & 0x03;
+                    self.mode_locked = v & 0x40 != 0;
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.mode_locked {
+                    self.banking_mode = v as u8 & 0x01;
+                }
+                if !self.mapped {
+                    self.rom_mask = v >> 2 & 0x0F;
+                }
+            }
+            _ => panic!("Could not write to {:04X} (MMM01)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if let Some(address) = self.ram_address(a) {
+            self.ram[address] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        self.has_battery
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded RAM has incorrect length");
+        }
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x3FFF => self.rombank_low(),
+            _ => self.rombank_high(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MMM01;
+    use crate::mbc::{MBC, NINTENDO_LOGO};
+
+    #[test]
+    fn menu_then_mapped_game() {
+        // 512KB with the menu header in the last 32KB and bank numbers at each bank start.
+        let mut rom = vec![0; 0x80000];
+        for bank in 0..32 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x78104..0x78134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x78000 + 0x147] = 0x0B;
+        let mut mbc = MMM01::new(rom).unwrap();
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (30, 31));
+
+        // A 64KB game at bank 8: base bank 8 with bits 2-4 of the ROM bank frozen.
+        mbc.writerom(0x2000, 0x08);
+        mbc.writerom(0x6000, 0x07 << 3);
+        mbc.writerom(0x0000, 0x40);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (8, 9));
+        mbc.writerom(0x2000, 0x03);
+        assert_eq!(mbc.readrom(0x4000), 11);
+        mbc.writerom(0x2000, 0x1F);
+        assert_eq!(mbc.readrom(0x4000), 11);
+        // Changing the base needs a reset.
+        mbc.writerom(0x4000, 0x30);
+        assert_eq!(mbc.readrom(0x0000), 8);
+    }
+}
@@ -1,14 +1,31 @@
+use crate::camera::CameraImage;
+use crate::save_file;
 use crate::StrResult;
 use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
 use std::path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
+mod huc1;
+mod huc3;
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod pocket_camera;
+mod tama5;
+
+/// Logo bitmap every cartridge header carries at 0x0104-0x0133.
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
 
 pub trait MBC: Send {
     fn readrom(&self, a: u16) -> u8;
@@ -21,6 +38,35 @@ pub trait MBC: Send {
     fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()>;
     fn dumpram(&self) -> Vec<u8>;
 
+    /// ROM bank currently mapped at `a` (0x0000-0x7FFF).
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { 1 }
+    }
+
+    /// Pins the wall clock seen by a cartridge RTC to `unix_secs`; None follows the host
+    /// clock. Used to make movie recordings deterministic.
+    fn set_rtc_clock(&mut self, _unix_secs: Option<u64>) {}
+
+    /// Advances a cartridge RTC by the wall clock time since its save file was written, as
+    /// if it had kept running while the emulator was closed.
+    fn catch_up_rtc(&mut self) {}
+
+    /// Feeds a cartridge accelerometer (MBC7), in g from -1.0 to 1.0. Positive x tilts
+    /// right, positive y tilts the bottom of the screen down.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Advances cartridge hardware by `ticks` clocks of the 4 MiHz system clock; unlike CPU
+    /// clocks these keep real-time pace in CGB double speed.
+    fn do_cycle(&mut self, _ticks: u32) {}
+
+    /// Sets the scene in front of a camera cartridge's sensor.
+    fn set_camera_image(&mut self, _image: &CameraImage) {}
+
+    /// The scene a camera cartridge's sensor sees; None for other cartridges.
+    fn camera_image(&self) -> Option<CameraImage> {
+        None
+    }
+
     fn get_save_path(&self) -> Option<String> {
         None // Default implementation for non-file-backed MBCs
     }
@@ -54,6 +100,13 @@ pub enum MbcState {
     Mbc2(mbc2::MBC2),
     Mbc3(mbc3::MBC3),
     Mbc5(mbc5::MBC5),
+    Mbc6(mbc6::MBC6),
+    Mbc7(mbc7::MBC7),
+    Mmm01(mmm01::MMM01),
+    HuC1(huc1::HuC1),
+    HuC3(huc3::HuC3),
+    PocketCamera(pocket_camera::PocketCamera),
+    Tama5(tama5::TAMA5),
 }
 
 impl MBC for MbcState {
@@ -64,6 +117,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.readrom(a),
             MbcState::Mbc3(mbc) => mbc.readrom(a),
             MbcState::Mbc5(mbc) => mbc.readrom(a),
+            MbcState::Mbc6(mbc) => mbc.readrom(a),
+            MbcState::Mbc7(mbc) => mbc.readrom(a),
+            MbcState::Mmm01(mbc) => mbc.readrom(a),
+            MbcState::HuC1(mbc) => mbc.readrom(a),
+            MbcState::HuC3(mbc) => mbc.readrom(a),
+            MbcState::PocketCamera(mbc) => mbc.readrom(a),
+            MbcState::Tama5(mbc) => mbc.readrom(a),
         }
     }
 
@@ -74,6 +134,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.readram(a),
             MbcState::Mbc3(mbc) => mbc.readram(a),
             MbcState::Mbc5(mbc) => mbc.readram(a),
+            MbcState::Mbc6(mbc) => mbc.readram(a),
+            MbcState::Mbc7(mbc) => mbc.readram(a),
+            MbcState::Mmm01(mbc) => mbc.readram(a),
+            MbcState::HuC1(mbc) => mbc.readram(a),
+            MbcState::HuC3(mbc) => mbc.readram(a),
+            MbcState::PocketCamera(mbc) => mbc.readram(a),
+            MbcState::Tama5(mbc) => mbc.readram(a),
         }
     }
 
@@ -84,6 +151,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.writerom(a, v),
             MbcState::Mbc3(mbc) => mbc.writerom(a, v),
             MbcState::Mbc5(mbc) => mbc.writerom(a, v),
+            MbcState::Mbc6(mbc) => mbc.writerom(a, v),
+            MbcState::Mbc7(mbc) => mbc.writerom(a, v),
+            MbcState::Mmm01(mbc) => mbc.writerom(a, v),
+            MbcState::HuC1(mbc) => mbc.writerom(a, v),
+            MbcState::HuC3(mbc) => mbc.writerom(a, v),
+            MbcState::PocketCamera(mbc) => mbc.writerom(a, v),
+            MbcState::Tama5(mbc) => mbc.writerom(a, v),
         }
     }
 
@@ -94,6 +168,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.writeram(a, v),
             MbcState::Mbc3(mbc) => mbc.writeram(a, v),
             MbcState::Mbc5(mbc) => mbc.writeram(a, v),
+            MbcState::Mbc6(mbc) => mbc.writeram(a, v),
+            MbcState::Mbc7(mbc) => mbc.writeram(a, v),
+            MbcState::Mmm01(mbc) => mbc.writeram(a, v),
+            MbcState::HuC1(mbc) => mbc.writeram(a, v),
+            MbcState::HuC3(mbc) => mbc.writeram(a, v),
+            MbcState::PocketCamera(mbc) => mbc.writeram(a, v),
+            MbcState::Tama5(mbc) => mbc.writeram(a, v),
         }
     }
 
@@ -104,6 +185,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.check_and_reset_ram_updated(),
             MbcState::Mbc3(mbc) => mbc.check_and_reset_ram_updated(),
             MbcState::Mbc5(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::Mbc6(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::Mbc7(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::Mmm01(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::HuC1(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::HuC3(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::PocketCamera(mbc) => mbc.check_and_reset_ram_updated(),
+            MbcState::Tama5(mbc) => mbc.check_and_reset_ram_updated(),
         }
     }
 
@@ -114,6 +202,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.is_battery_backed(),
             MbcState::Mbc3(mbc) => mbc.is_battery_backed(),
             MbcState::Mbc5(mbc) => mbc.is_battery_backed(),
+            MbcState::Mbc6(mbc) => mbc.is_battery_backed(),
+            MbcState::Mbc7(mbc) => mbc.is_battery_backed(),
+            MbcState::Mmm01(mbc) => mbc.is_battery_backed(),
+            MbcState::HuC1(mbc) => mbc.is_battery_backed(),
+            MbcState::HuC3(mbc) => mbc.is_battery_backed(),
+            MbcState::PocketCamera(mbc) => mbc.is_battery_backed(),
+            MbcState::Tama5(mbc) => mbc.is_battery_backed(),
         }
     }
 
@@ -124,6 +219,13 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.loadram(ramdata),
             MbcState::Mbc3(mbc) => mbc.loadram(ramdata),
             MbcState::Mbc5(mbc) => mbc.loadram(ramdata),
+            MbcState::Mbc6(mbc) => mbc.loadram(ramdata),
+            MbcState::Mbc7(mbc) => mbc.loadram(ramdata),
+            MbcState::Mmm01(mbc) => mbc.loadram(ramdata),
+            MbcState::HuC1(mbc) => mbc.loadram(ramdata),
+            MbcState::HuC3(mbc) => mbc.loadram(ramdata),
+            MbcState::PocketCamera(mbc) => mbc.loadram(ramdata),
+            MbcState::Tama5(mbc) => mbc.loadram(ramdata),
         }
     }
 
@@ -134,6 +236,131 @@ impl MBC for MbcState {
             MbcState::Mbc2(mbc) => mbc.dumpram(),
             MbcState::Mbc3(mbc) => mbc.dumpram(),
             MbcState::Mbc5(mbc) => mbc.dumpram(),
+            MbcState::Mbc6(mbc) => mbc.dumpram(),
+            MbcState::Mbc7(mbc) => mbc.dumpram(),
+            MbcState::Mmm01(mbc) => mbc.dumpram(),
+            MbcState::HuC1(mbc) => mbc.dumpram(),
+            MbcState::HuC3(mbc) => mbc.dumpram(),
+            MbcState::PocketCamera(mbc) => mbc.dumpram(),
+            MbcState::Tama5(mbc) => mbc.dumpram(),
+        }
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.rombank(a),
+            MbcState::Mbc1(mbc) => mbc.rombank(a),
+            MbcState::Mbc2(mbc) => mbc.rombank(a),
+            MbcState::Mbc3(mbc) => mbc.rombank(a),
+            MbcState::Mbc5(mbc) => mbc.rombank(a),
+            MbcState::Mbc6(mbc) => mbc.rombank(a),
+            MbcState::Mbc7(mbc) => mbc.rombank(a),
+            MbcState::Mmm01(mbc) => mbc.rombank(a),
+            MbcState::HuC1(mbc) => mbc.rombank(a),
+            MbcState::HuC3(mbc) => mbc.rombank(a),
+            MbcState::PocketCamera(mbc) => mbc.rombank(a),
+            MbcState::Tama5(mbc) => mbc.rombank(a),
+        }
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc1(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc2(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc3(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc5(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc6(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mbc7(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Mmm01(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::HuC1(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::HuC3(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::PocketCamera(mbc) => mbc.set_rtc_clock(unix_secs),
+            MbcState::Tama5(mbc) => mbc.set_rtc_clock(unix_secs),
+        }
+    }
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc1(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc2(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc3(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc5(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc6(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mbc7(mbc) => mbc.set_tilt(x, y),
+            MbcState::Mmm01(mbc) => mbc.set_tilt(x, y),
+            MbcState::HuC1(mbc) => mbc.set_tilt(x, y),
+            MbcState::HuC3(mbc) => mbc.set_tilt(x, y),
+            MbcState::PocketCamera(mbc) => mbc.set_tilt(x, y),
+            MbcState::Tama5(mbc) => mbc.set_tilt(x, y),
+        }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc1(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc2(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc3(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc5(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc6(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mbc7(mbc) => mbc.do_cycle(ticks),
+            MbcState::Mmm01(mbc) => mbc.do_cycle(ticks),
+            MbcState::HuC1(mbc) => mbc.do_cycle(ticks),
+            MbcState::HuC3(mbc) => mbc.do_cycle(ticks),
+            MbcState::PocketCamera(mbc) => mbc.do_cycle(ticks),
+            MbcState::Tama5(mbc) => mbc.do_cycle(ticks),
+        }
+    }
+
+    fn set_camera_image(&mut self, image: &CameraImage) {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc1(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc2(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc3(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc5(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc6(mbc) => mbc.set_camera_image(image),
+            MbcState::Mbc7(mbc) => mbc.set_camera_image(image),
+            MbcState::Mmm01(mbc) => mbc.set_camera_image(image),
+            MbcState::HuC1(mbc) => mbc.set_camera_image(image),
+            MbcState::HuC3(mbc) => mbc.set_camera_image(image),
+            MbcState::PocketCamera(mbc) => mbc.set_camera_image(image),
+            MbcState::Tama5(mbc) => mbc.set_camera_image(image),
+        }
+    }
+
+    fn camera_image(&self) -> Option<CameraImage> {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.camera_image(),
+            MbcState::Mbc1(mbc) => mbc.camera_image(),
+            MbcState::Mbc2(mbc) => mbc.camera_image(),
+            MbcState::Mbc3(mbc) => mbc.camera_image(),
+            MbcState::Mbc5(mbc) => mbc.camera_image(),
+            MbcState::Mbc6(mbc) => mbc.camera_image(),
+            MbcState::Mbc7(mbc) => mbc.camera_image(),
+            MbcState::Mmm01(mbc) => mbc.camera_image(),
+            MbcState::HuC1(mbc) => mbc.camera_image(),
+            MbcState::HuC3(mbc) => mbc.camera_image(),
+            MbcState::PocketCamera(mbc) => mbc.camera_image(),
+            MbcState::Tama5(mbc) => mbc.camera_image(),
+        }
+    }
+
+    fn catch_up_rtc(&mut self) {
+        match self {
+            MbcState::Mbc0(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc1(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc2(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc3(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc5(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc6(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mbc7(mbc) => mbc.catch_up_rtc(),
+            MbcState::Mmm01(mbc) => mbc.catch_up_rtc(),
+            MbcState::HuC1(mbc) => mbc.catch_up_rtc(),
+            MbcState::HuC3(mbc) => mbc.catch_up_rtc(),
+            MbcState::PocketCamera(mbc) => mbc.catch_up_rtc(),
+            MbcState::Tama5(mbc) => mbc.catch_up_rtc(),
         }
     }
 }
@@ -145,12 +372,21 @@ pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<MbcState> {
     if !skip_checksum {
         check_checksum(&data)?;
     }
+    if mmm01::header_offset(&data).is_some() {
+        return mmm01::MMM01::new(data).map(MbcState::Mmm01);
+    }
     match data[0x147] {
         0x00 => mbc0::MBC0::new(data).map(MbcState::Mbc0),
         0x01..=0x03 => mbc1::MBC1::new(data).map(MbcState::Mbc1),
         0x05..=0x06 => mbc2::MBC2::new(data).map(MbcState::Mbc2),
         0x0F..=0x13 => mbc3::MBC3::new(data).map(MbcState::Mbc3),
         0x19..=0x1E => mbc5::MBC5::new(data).map(MbcState::Mbc5),
+        0x20 => mbc6::MBC6::new(data).map(MbcState::Mbc6),
+        0x22 => mbc7::MBC7::new(data).map(MbcState::Mbc7),
+        0xFC => pocket_camera::PocketCamera::new(data).map(MbcState::PocketCamera),
+        0xFD => tama5::TAMA5::new(data).map(MbcState::Tama5),
+        0xFE => huc3::HuC3::new(data).map(MbcState::HuC3),
+        0xFF => huc1::HuC1::new(data).map(MbcState::HuC1),
         _ => Err("Unsupported MBC type"),
     }
 }
@@ -159,6 +395,9 @@ pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<MbcState> {
 pub struct FileBackedMBC {
     rampath: String,
     mbc: MbcState,
+    // Whether this session already rotated the previous save into the backups.
+    #[rkyv(with = rkyv::with::Skip)]
+    backed_up: AtomicBool,
 }
 
 impl FileBackedMBC {
@@ -169,33 +408,59 @@ impl FileBackedMBC {
             .map_err(|_| "Could not read ROM")?;
         let mut mbc = get_mbc(data, skip_checksum)?;
 
-        let rampath = rompath.with_extension("gbsave");
-        // println!("DEBUG: FileBackedMBC will use save path: {}", rampath.display());
-
+        let rampath = rompath.with_extension("sav");
         if mbc.is_battery_backed() {
-            match fs::File::open(&rampath) {
-                Ok(mut file) => {
-                    let mut ramdata: Vec<u8> = vec![];
-                    match file.read_to_end(&mut ramdata) {
-                        Err(..) => return Err("Error while reading existing save file"),
-                        Ok(..) => {
-                            // println!("DEBUG: Loaded existing save file with {} bytes", ramdata.len());
-                            mbc.loadram(&ramdata)?;
+            match read_save_file(&rampath)? {
+                Some(ramdata) => mbc.loadram(&ramdata)?,
+                // Saves used to go to `.gbsave`; load one of those once and carry on as `.sav`.
+                None => {
+                    if let Some(ramdata) = read_save_file(&rompath.with_extension("gbsave"))? {
+                        mbc.loadram(&ramdata)?;
+                        // The `.gbsave` is left in place, so a failure here loses nothing.
+                        if let Err(e) = save_file::write_atomic(&rampath, &mbc.dumpram(), 0) {
+                            eprintln!("Failed to write game save to {}: {}", rampath.display(), e);
                         }
                     }
                 }
-                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-                    // println!("DEBUG: No existing save file found, starting fresh");
-                }
-                Err(_) => return Err("Error loading existing save file"),
             }
         }
 
         Ok(FileBackedMBC {
             rampath: rampath.to_string_lossy().to_string(),
             mbc,
+            backed_up: AtomicBool::new(false),
         })
     }
+
+    /// Writes the battery RAM to the save file, crash-safely; `sync` also makes it durable
+    /// against power loss. The first write of a session rotates the previous save into the
+    /// backups.
+    pub fn save(&self, sync: bool) -> StrResult<()> {
+        if !self.mbc.is_battery_backed() || self.rampath.is_empty() {
+            return Ok(());
+        }
+        // Only count the backups as rotated once a write that rotated them went through.
+        let backups = match self.backed_up.load(Ordering::Relaxed) {
+            true => 0,
+            false => save_file::BACKUPS,
+        };
+        let write = match sync {
+            true => save_file::write_atomic,
+            false => save_file::write_atomic_unsynced,
+        };
+        write(path::Path::new(&self.rampath), &self.mbc.dumpram(), backups)
+            .map_err(|_| "Could not write save file")?;
+        self.backed_up.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+fn read_save_file(path: &path::Path) -> StrResult<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(_) => Err("Error loading existing save file"),
+    }
 }
 
 // Implement MBC for FileBackedMBC such that the MMU can use this transparently
@@ -232,20 +497,43 @@ impl MBC for FileBackedMBC {
         self.mbc.check_and_reset_ram_updated()
     }
 
+    fn rombank(&self, a: u16) -> usize {
+        self.mbc.rombank(a)
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        self.mbc.set_rtc_clock(unix_secs)
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y)
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.mbc.do_cycle(ticks)
+    }
+
+    fn set_camera_image(&mut self, image: &CameraImage) {
+        self.mbc.set_camera_image(image)
+    }
+
+    fn camera_image(&self) -> Option<CameraImage> {
+        self.mbc.camera_image()
+    }
+
+    fn catch_up_rtc(&mut self) {
+        self.mbc.catch_up_rtc()
+    }
+
     fn get_save_path(&self) -> Option<String> {
-        Some(self.rampath.clone())
+        (!self.rampath.is_empty()).then(|| self.rampath.clone())
     }
 }
 
 impl Drop for FileBackedMBC {
     fn drop(&mut self) {
-        if self.mbc.is_battery_backed() {
-            // TODO: error handling
-            let mut file = match fs::File::create(&self.rampath) {
-                Ok(f) => f,
-                Err(..) => return,
-            };
-            let _ = file.write_all(&self.mbc.dumpram());
+        if let Err(e) = self.save(true) {
+            eprintln!("Failed to write game save to {}: {}", self.rampath, e);
         }
     }
 }
@@ -257,6 +545,45 @@ pub enum Cartridge {
 }
 
 impl Cartridge {
+    /// Stops writing the battery RAM back to the save file, e.g. while a movie replays
+    /// someone else's save data.
+    pub fn detach_save_file(&mut self) {
+        if let Cartridge::FileBacked(mbc) = self {
+            mbc.rampath.clear();
+        }
+    }
+
+    /// Hands this cartridge's save file, and whether it already rotated the backups this
+    /// session, over to `other`, which replaces it (e.g. from a save state). This one is
+    /// left detached, so dropping it writes nothing.
+    pub fn hand_over_save_file(&mut self, other: &mut Cartridge) {
+        let (rampath, backed_up) = match self {
+            Cartridge::FileBacked(mbc) => (std::mem::take(&mut mbc.rampath), mbc.backed_up.load(Ordering::Relaxed)),
+            Cartridge::Memory(_) => (String::new(), false),
+        };
+        if let Cartridge::FileBacked(mbc) = other {
+            mbc.rampath = rampath;
+            mbc.backed_up.store(backed_up, Ordering::Relaxed);
+        }
+    }
+
+    /// True for cartridges with an accelerometer (MBC7).
+    pub fn has_accelerometer(&self) -> bool {
+        let mbc = match self {
+            Cartridge::Memory(mbc) => mbc,
+            Cartridge::FileBacked(mbc) => &mbc.mbc,
+        };
+        matches!(mbc, MbcState::Mbc7(_))
+    }
+
+    /// Writes the battery RAM to the save file, if the cartridge has one.
+    pub fn save_battery_ram(&self, sync: bool) -> StrResult<()> {
+        match self {
+            Cartridge::Memory(_) => Ok(()),
+            Cartridge::FileBacked(mbc) => mbc.save(sync),
+        }
+    }
+
     pub fn from_file(rompath: path::PathBuf, skip_checksum: bool) -> StrResult<Cartridge> {
         FileBackedMBC::new(rompath, skip_checksum).map(Cartridge::FileBacked)
     }
@@ -323,6 +650,55 @@ impl MBC for Cartridge {
         }
     }
 
+    fn rombank(&self, a: u16) -> usize {
+        match self {
+            Cartridge::Memory(mbc) => mbc.rombank(a),
+            Cartridge::FileBacked(mbc) => mbc.rombank(a),
+        }
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        match self {
+            Cartridge::Memory(mbc) => mbc.set_rtc_clock(unix_secs),
+            Cartridge::FileBacked(mbc) => mbc.set_rtc_clock(unix_secs),
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        match self {
+            Cartridge::Memory(mbc) => mbc.set_tilt(x, y),
+            Cartridge::FileBacked(mbc) => mbc.set_tilt(x, y),
+        }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        match self {
+            Cartridge::Memory(mbc) => mbc.do_cycle(ticks),
+            Cartridge::FileBacked(mbc) => mbc.do_cycle(ticks),
+        }
+    }
+
+    fn set_camera_image(&mut self, image: &CameraImage) {
+        match self {
+            Cartridge::Memory(mbc) => mbc.set_camera_image(image),
+            Cartridge::FileBacked(mbc) => mbc.set_camera_image(image),
+        }
+    }
+
+    fn camera_image(&self) -> Option<CameraImage> {
+        match self {
+            Cartridge::Memory(mbc) => mbc.camera_image(),
+            Cartridge::FileBacked(mbc) => mbc.camera_image(),
+        }
+    }
+
+    fn catch_up_rtc(&mut self) {
+        match self {
+            Cartridge::Memory(mbc) => mbc.catch_up_rtc(),
+            Cartridge::FileBacked(mbc) => mbc.catch_up_rtc(),
+        }
+    }
+
     fn get_save_path(&self) -> Option<String> {
         match self {
             Cartridge::Memory(mbc) => mbc.get_save_path(),
@@ -0,0 +1,246 @@
+use crate::camera::{CameraImage, SENSOR_H, SENSOR_W};
+use crate::mbc::{rom_banks, MBC};
+use crate::StrResult;
+
+const RAM_SIZE: usize = 0x20000;
+// Captures land in RAM bank 0 as 16x14 tiles in the usual 2bpp layout.
+const IMAGE_OFFSET: usize = 0x100;
+// Sensor registers at 0xA000-0xA035 (mirrored every 0x80) when bit 4 of the bank
+// register is set: A000 starts a capture and reads back busy, A001 holds the N and VH
+// edge flags and the gain, A002-A003 the exposure time, A004 the edge enhancement ratio
+// and A006-A035 a 4x4 matrix of three dither thresholds per pixel.
+const REGISTERS: usize = 0x36;
+const DITHER_MATRIX: usize = 0x06;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    ram_on: bool,
+    ram_updated: bool,
+    registers_selected: bool,
+    registers: [u8; REGISTERS],
+    // M-cycles until the capture in progress completes.
+    capture_cycles: u32,
+    image: CameraImage,
+}
+
+impl PocketCamera {
+    pub fn new(data: Vec<u8>) -> StrResult<PocketCamera> {
+        let rombanks = rom_banks(data[0x148]);
+        Ok(PocketCamera {
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            ram_on: false,
+            ram_updated: false,
+            registers_selected: false,
+            registers: [0; REGISTERS],
+            capture_cycles: 0,
+            image: CameraImage::test_pattern(),
+        })
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.registers[2] as u32) << 8 | self.registers[3] as u32
+    }
+
+    fn start_capture(&mut self) {
+        let n = self.registers[1] & 0x80 != 0;
+        self.capture_cycles = 32446 + if n { 0 } else { 512 } + 16 * self.exposure();
+    }
+
+    // Sensor output before edge enhancement: gain steps are 1.5dB and an exposure of
+    // 0x0800 passes the scene through unchanged at the lowest gain.
+    fn sensor(&self, x: isize, y: isize) -> f32 {
+        let gain = 10f32.powf((self.registers[1] & 0x1F) as f32 * 1.5 / 20.0);
+        let pixel = self.image.pixel(x.max(0) as usize, y.max(0) as usize) as f32;
+        pixel * gain * self.exposure() as f32 / 0x0800 as f32
+    }
+
+    fn finish_capture(&mut self) {
+        self.registers[0] &= !0x01;
+        let edge = self.registers[1] & 0xE0 == 0xE0;
+        let ratio = EDGE_RATIOS[(self.registers[4] >> 4 & 0x07) as usize];
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let (sx, sy) = (x as isize, y as isize);
+                let mut v = self.sensor(sx, sy);
+                if edge {
+                    let neighbours = self.sensor(sx - 1, sy)
+                        + self.sensor(sx + 1, sy)
+                        + self.sensor(sx, sy - 1)
+                        + self.sensor(sx, sy + 1);
+                    v += (4.0 * v - neighbours) * ratio;
+                }
+                let thresholds = DITHER_MATRIX + ((x & 3) + (y & 3) * 4) * 3;
+                let shade = match self.registers[thresholds..thresholds + 3] {
+                    [t, _, _] if v < t as f32 => 3,
+                    [_, t, _] if v < t as f32 => 2,
+                    [_, _, t] if v < t as f32 => 1,
+                    _ => 0,
+                };
+                let tile = (y / 8) * (SENSOR_W / 8) + x / 8;
+                let line = IMAGE_OFFSET + tile * 16 + (y & 7) * 2;
+                let bit = 0x80 >> (x & 7);
+                for (plane, set) in [(0, shade & 1 != 0), (1, shade & 2 != 0)] {
+                    if set {
+                        self.ram[line + plane] |= bit;
+                    } else {
+                        self.ram[line + plane] &= !bit;
+                    }
+                }
+            }
+        }
+        self.ram_updated = true;
+    }
+}
+
+impl MBC for PocketCamera {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 {
+            a as usize
+        } else {
+            (self.rombank * 0x4000) | ((a as usize) & 0x3FFF)
+        };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.registers_selected {
+            // Only A000 reads back; the rest are write-only.
+            return match a & 0x7F {
+                0 => self.registers[0],
+                _ => 0x00,
+            };
+        }
+        if self.capture_cycles > 0 {
+            return 0x00;
+        }
+        self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)]
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000..=0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rombank = (v as usize & 0x3F) % self.rombanks,
+            0x4000..=0x5FFF => {
+                self.registers_selected = v & 0x10 != 0;
+                self.rambank = v as usize & 0x0F;
+            }
+            0x6000..=0x7FFF => {}
+            _ => panic!("Could not write to {:04X} (Pocket Camera)", a),
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.registers_selected {
+            match (a & 0x7F) as usize {
+                0 => {
+                    self.registers[0] = v & 0x07;
+                    if v & 0x01 != 0 && self.capture_cycles == 0 {
+                        self.start_capture();
+                    } else if v & 0x01 == 0 {
+                        self.capture_cycles = 0;
+                    }
+                }
+                r if r < REGISTERS => self.registers[r] = v,
+                _ => {}
+            }
+            return;
+        }
+        if !self.ram_on || self.capture_cycles > 0 {
+            return;
+        }
+        self.ram[(self.rambank * 0x2000) | ((a as usize) & 0x1FFF)] = v;
+        self.ram_updated = true;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err("Loaded RAM has incorrect length");
+        }
+        self.ram = ramdata.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+        self.capture_cycles = self.capture_cycles.saturating_sub(ticks / 4);
+        if self.capture_cycles == 0 {
+            self.finish_capture();
+        }
+    }
+
+    fn set_camera_image(&mut self, image: &CameraImage) {
+        self.image = image.clone();
+    }
+
+    fn camera_image(&self) -> Option<CameraImage> {
+        Some(self.image.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PocketCamera;
+    use crate::camera::CameraImage;
+    use crate::mbc::MBC;
+
+    #[test]
+    fn capture_dithers_into_ram() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0xFC;
+        let mut camera = PocketCamera::new(rom).unwrap();
+        // Left half black, right half mid grey.
+        let luma: Vec<u8> = (0..128 * 112).map(|i| if i % 128 < 64 { 0 } else { 0x80 }).collect();
+        camera.set_camera_image(&CameraImage::from_luma(128, 112, &luma));
+
+        camera.writerom(0x4000, 0x10);
+        camera.writeram(0xA001, 0x80);
+        camera.writeram(0xA002, 0x08);
+        camera.writeram(0xA003, 0x00);
+        for r in 0..16 {
+            for (i, t) in [0x40, 0x70, 0xA0].into_iter().enumerate() {
+                camera.writeram(0xA006 + r * 3 + i as u16, t);
+            }
+        }
+        camera.writeram(0xA000, 0x01);
+        camera.do_cycle(4 * (32446 + 16 * 0x800 - 1));
+        assert_eq!(camera.readram(0xA000), 0x01);
+        camera.do_cycle(4);
+        assert_eq!(camera.readram(0xA000), 0x00);
+        assert!(camera.check_and_reset_ram_updated());
+
+        camera.writerom(0x4000, 0x00);
+        // Tile 0 is black (shade 3), tile 8 is mid grey (shade 1).
+        assert_eq!((camera.readram(0xA100), camera.readram(0xA101)), (0xFF, 0xFF));
+        assert_eq!((camera.readram(0xA180), camera.readram(0xA181)), (0xFF, 0x00));
+    }
+}
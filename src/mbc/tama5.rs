@@ -0,0 +1,487 @@
+use crate::mbc::{rom_banks, MBC};
+use crate::StrResult;
+
+use std::convert::TryInto;
+use std::time;
+
+// System clocks per RTC second.
+const RTC_TICKS_PER_SECOND: u32 = 4_194_304;
+const RAM_SIZE: usize = 0x20;
+const FOOTER_SIZE: usize = 20;
+// Register indices, selected by writing to 0xA001 and accessed 4 bits at a time at 0xA000.
+const BANK_LO: usize = 0x0;
+const BANK_HI: usize = 0x1;
+const DATA_LO: usize = 0x4;
+const DATA_HI: usize = 0x5;
+const ADDR_HI: usize = 0x6;
+const ADDR_LO: usize = 0x7;
+const ACTIVE: usize = 0xA;
+const READ_LO: usize = 0xC;
+const READ_HI: usize = 0xD;
+// Nibbles of the TAMA6 calendar page: BCD seconds, minutes, hours, weekday, day, month
+// and year (2000-2099).
+const PAGE_SIZE: usize = 13;
+// Unix time of 2000-01-01 00:00 UTC.
+const UNIX_2000: u64 = 946_684_800;
+const DAYS_PER_MONTH: [u64; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+// TAMA5 carts have no memory-mapped RAM: everything goes through a register file at
+// 0xA000-0xA001. Writing the address low nibble runs the operation selected by bits 1-3
+// of the address high register: 0 writes a byte of the 32-byte RAM, 1 reads one, 2 runs a
+// command on the TAMA6 clock MCU and 4 accesses its calendar page. Results come back
+// through READ_LO/READ_HI.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct TAMA5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    ram_updated: bool,
+    reg: usize,
+    registers: [u8; 16],
+    result: u8,
+    // Clock in seconds since 2000-01-01 00:00.
+    rtc_secs: u64,
+    // System clocks since the clock last ticked.
+    rtc_ticks: u32,
+    // Unix time the loaded save was written at, until `catch_up_rtc` consumes it.
+    rtc_saved_at: Option<u64>,
+    rtc_running: bool,
+    alarm_on: bool,
+    alarm_fired: bool,
+    // BCD alarm time.
+    alarm_minute: u8,
+    alarm_hour: u8,
+    // Clock reading at the last alarm check.
+    alarm_checked: u64,
+    // Wall clock override in unix seconds; None uses the host clock.
+    rtc_clock: Option<u64>,
+}
+
+impl TAMA5 {
+    pub fn new(data: Vec<u8>) -> StrResult<TAMA5> {
+        let rombanks = rom_banks(data[0x148]);
+        // A fresh cart starts out on the host's UTC date and time.
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(TAMA5 {
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            rombank: 1,
+            rombanks,
+            ram_updated: false,
+            reg: 0,
+            registers: [0; 16],
+            result: 0,
+            rtc_secs: now.saturating_sub(UNIX_2000),
+            rtc_ticks: 0,
+            rtc_saved_at: None,
+            rtc_running: true,
+            alarm_on: false,
+            alarm_fired: false,
+            alarm_minute: 0,
+            alarm_hour: 0,
+            alarm_checked: now.saturating_sub(UNIX_2000),
+            rtc_clock: None,
+        })
+    }
+
+    fn now(&self) -> u64 {
+        match self.rtc_clock {
+            Some(t) => t,
+            None => time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    fn clock(&self) -> u64 {
+        self.rtc_secs
+    }
+
+    fn set_clock(&mut self, secs: u64) {
+        self.rtc_secs = secs;
+        // Setting the clock never sets off the alarm by itself.
+        self.alarm_checked = secs;
+        self.ram_updated = true;
+    }
+
+    fn set_running(&mut self, running: bool) {
+        self.rtc_running = running;
+        self.ram_updated = true;
+    }
+
+    // The alarm is checked whenever the game talks to the clock, and goes off if the
+    // alarm time came round since the previous check.
+    fn check_alarm(&mut self) {
+        let now = self.clock();
+        let last = std::mem::replace(&mut self.alarm_checked, now);
+        if !self.alarm_on || now <= last {
+            return;
+        }
+        let bcd = |v: u8| (v >> 4) as u64 * 10 + (v & 0x0F) as u64;
+        let alarm = (bcd(self.alarm_hour) * 60 + bcd(self.alarm_minute)) * 60;
+        let mut next = last - last % 86400 + alarm;
+        if next <= last {
+            next += 86400;
+        }
+        if next <= now {
+            self.alarm_fired = true;
+        }
+    }
+
+    fn write_register(&mut self, v: u8) {
+        if self.reg >= 0x08 {
+            return;
+        }
+        self.registers[self.reg] = v & 0x0F;
+        match self.reg {
+            BANK_LO | BANK_HI => {
+                let bank = (self.registers[BANK_HI] as usize & 0x01) << 4 | self.registers[BANK_LO] as usize;
+                self.rombank = bank % self.rombanks;
+            }
+            ADDR_LO => self.execute(),
+            _ => {}
+        }
+    }
+
+    fn execute(&mut self) {
+        let addr = (self.registers[ADDR_HI] as usize & 0x01) << 4 | self.registers[ADDR_LO] as usize;
+        let data = self.registers[DATA_HI] << 4 | self.registers[DATA_LO];
+        match self.registers[ADDR_HI] >> 1 {
+            0 => {
+                self.ram[addr] = data;
+                self.ram_updated = true;
+            }
+            1 => self.result = self.ram[addr],
+            2 => self.command(addr as u8, data),
+            4 => self.page_access(),
+            _ => {}
+        }
+    }
+
+    // TAMA6 commands; times are BCD.
+    fn command(&mut self, command: u8, data: u8) {
+        self.check_alarm();
+        let mut page = calendar(self.clock());
+        match command {
+            0x00 => self.set_running(false),
+            0x01 => self.set_running(true),
+            0x04 | 0x05 => {
+                let field = if command == 0x04 { 2 } else { 4 };
+                page[field] = data & 0x0F;
+                page[field + 1] = data >> 4;
+                // Setting the time restarts the current minute.
+                page[0] = 0;
+                page[1] = 0;
+                self.set_clock(from_calendar(&page));
+            }
+            0x06 => self.result = page[3] << 4 | page[2],
+            0x07 => self.result = page[5] << 4 | page[4],
+            0x10 => self.alarm_on = false,
+            0x11 => {
+                self.alarm_on = true;
+                self.alarm_fired = false;
+            }
+            // Reading the alarm status clears it.
+            0x12 => self.result = std::mem::take(&mut self.alarm_fired) as u8,
+            0x14 => self.alarm_minute = data,
+            0x15 => self.alarm_hour = data,
+            0x16 => self.result = self.alarm_minute,
+            0x17 => self.result = self.alarm_hour,
+            _ => {}
+        }
+        if matches!(command, 0x10 | 0x11 | 0x14 | 0x15) {
+            self.ram_updated = true;
+        }
+    }
+
+    // The data low nibble selects a calendar page nibble; even ADDR_LO values write the
+    // data high nibble to it, odd ones read it back.
+    fn page_access(&mut self) {
+        let index = self.registers[DATA_LO] as usize;
+        if index >= PAGE_SIZE {
+            return;
+        }
+        let mut page = calendar(self.clock());
+        if self.registers[ADDR_LO] & 0x01 == 0 {
+            page[index] = self.registers[DATA_HI];
+            self.set_clock(from_calendar(&page));
+        } else {
+            self.result = page[index];
+        }
+    }
+}
+
+fn is_leap(year: u64) -> bool {
+    year.is_multiple_of(4)
+}
+
+fn days_in_month(year: u64, month: usize) -> u64 {
+    DAYS_PER_MONTH[month] + (month == 1 && is_leap(year)) as u64
+}
+
+/// Splits seconds since 2000-01-01 00:00 into the BCD nibbles of the calendar page.
+fn calendar(secs: u64) -> [u8; PAGE_SIZE] {
+    let mut days = secs / 86400;
+    // 2000-01-01 was a Saturday.
+    let weekday = (days + 6) % 7;
+    let mut year = 0;
+    while days >= 365 + is_leap(year) as u64 {
+        days -= 365 + is_leap(year) as u64;
+        year = (year + 1) % 100;
+    }
+    let mut month = 0;
+    while days >= days_in_month(year, month) {
+        days -= days_in_month(year, month);
+        month += 1;
+    }
+    let fields = [secs % 60, secs / 60 % 60, secs / 3600 % 24];
+    let mut page = [0; PAGE_SIZE];
+    for (i, v) in fields.into_iter().enumerate() {
+        page[i * 2] = (v % 10) as u8;
+        page[i * 2 + 1] = (v / 10) as u8;
+    }
+    page[6] = weekday as u8;
+    for (i, v) in [days + 1, month as u64 + 1, year].into_iter().enumerate() {
+        page[7 + i * 2] = (v % 10) as u8;
+        page[8 + i * 2] = (v / 10) as u8;
+    }
+    page
+}
+
+/// Inverse of `calendar`; out of range fields are clamped and the weekday is ignored.
+fn from_calendar(page: &[u8; PAGE_SIZE]) -> u64 {
+    let bcd = |i: usize| page[i + 1].min(9) as u64 * 10 + page[i].min(9) as u64;
+    let year = bcd(11);
+    let month = (bcd(9).clamp(1, 12) - 1) as usize;
+    let day = bcd(7).clamp(1, days_in_month(year, month));
+    let days = (0..year).map(|y| 365 + is_leap(y) as u64).sum::<u64>()
+        + (0..month).map(|m| days_in_month(year, m)).sum::<u64>()
+        + day
+        - 1;
+    days * 86400 + bcd(4).min(23) * 3600 + bcd(2).min(59) * 60 + bcd(0).min(59)
+}
+
+impl MBC for TAMA5 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 {
+            a as usize
+        } else {
+            (self.rombank * 0x4000) | ((a as usize) & 0x3FFF)
+        };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if a & 0x1FFF > 1 || a & 1 == 1 {
+            return 0xFF;
+        }
+        match self.reg {
+            ACTIVE => 0xF1,
+            READ_LO => 0xF0 | (self.result & 0x0F),
+            READ_HI => 0xF0 | (self.result >> 4),
+            _ => 0xFF,
+        }
+    }
+
+    // Nothing is mapped at 0x0000-0x7FFF; banking goes through the register file.
+    fn writerom(&mut self, _a: u16, _v: u8) {}
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        match a & 0x1FFF {
+            0 => self.write_register(v),
+            1 => self.reg = v as usize & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    // Saves are the RAM followed by the clock (seconds since 2000 and the unix time of the
+    // save, both big-endian), then the running, alarm enable, alarm minute and
+    // alarm hour bytes. RAM-only saves from other emulators leave the clock alone.
+    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+        match ramdata.len() {
+            RAM_SIZE => {
+                self.ram = ramdata.to_vec();
+                return Ok(());
+            }
+            n if n == RAM_SIZE + FOOTER_SIZE => {}
+            _ => return Err("Loaded RAM has incorrect length"),
+        }
+        let (ram, footer) = ramdata.split_at(RAM_SIZE);
+        self.ram = ram.to_vec();
+        self.rtc_secs = u64::from_be_bytes(footer[0..8].try_into().unwrap());
+        self.rtc_saved_at = Some(u64::from_be_bytes(footer[8..16].try_into().unwrap()));
+        self.rtc_running = footer[16] != 0;
+        self.alarm_on = footer[17] != 0;
+        self.alarm_minute = footer[18];
+        self.alarm_hour = footer[19];
+        self.alarm_checked = self.clock();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.ram.clone();
+        file.extend_from_slice(&self.rtc_secs.to_be_bytes());
+        file.extend_from_slice(&self.now().to_be_bytes());
+        file.extend_from_slice(&[self.rtc_running as u8, self.alarm_on as u8]);
+        file.extend_from_slice(&[self.alarm_minute, self.alarm_hour]);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn rombank(&self, a: u16) -> usize {
+        if a < 0x4000 { 0 } else { self.rombank }
+    }
+
+    fn set_rtc_clock(&mut self, unix_secs: Option<u64>) {
+        self.rtc_clock = unix_secs;
+    }
+
+    // The alarm goes off at the next check if its time passed while catching up.
+    fn catch_up_rtc(&mut self) {
+        if let Some(saved_at) = self.rtc_saved_at.take()
+            && self.rtc_running
+        {
+            self.rtc_secs += self.now().saturating_sub(saved_at);
+        }
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if !self.rtc_running {
+            return;
+        }
+        self.rtc_ticks += ticks;
+        while self.rtc_ticks >= RTC_TICKS_PER_SECOND {
+            self.rtc_ticks -= RTC_TICKS_PER_SECOND;
+            self.rtc_secs += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{calendar, from_calendar, RTC_TICKS_PER_SECOND, TAMA5};
+    use crate::mbc::MBC;
+
+    fn tama5() -> TAMA5 {
+        let mut rom = vec![0; 0x40000];
+        rom[0x147] = 0xFD;
+        rom[0x148] = 0x03;
+        let mut mbc = TAMA5::new(rom).unwrap();
+        mbc.set_rtc_clock(Some(1_000_000));
+        mbc
+    }
+
+    fn write(mbc: &mut TAMA5, reg: u8, v: u8) {
+        mbc.writeram(0xA001, reg);
+        mbc.writeram(0xA000, v);
+    }
+
+    fn run(mbc: &mut TAMA5, secs: u64) {
+        for _ in 0..secs {
+            mbc.do_cycle(RTC_TICKS_PER_SECOND);
+        }
+    }
+
+    fn result(mbc: &mut TAMA5) -> u8 {
+        mbc.writeram(0xA001, 0x0C);
+        let lo = mbc.readram(0xA000) & 0x0F;
+        mbc.writeram(0xA001, 0x0D);
+        let hi = mbc.readram(0xA000) & 0x0F;
+        hi << 4 | lo
+    }
+
+    #[test]
+    fn banking_and_ram_protocol() {
+        let mut mbc = tama5();
+        mbc.writeram(0xA001, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0xF1);
+        write(&mut mbc, 0x0, 0x3);
+        write(&mut mbc, 0x1, 0x1);
+        assert_eq!(mbc.rombank(0x4000), 0x13 % 16);
+
+        // Write 0xA5 to RAM address 0x12, then read it back.
+        write(&mut mbc, 0x4, 0x5);
+        write(&mut mbc, 0x5, 0xA);
+        write(&mut mbc, 0x6, 0x1);
+        write(&mut mbc, 0x7, 0x2);
+        write(&mut mbc, 0x6, 0x3);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(result(&mut mbc), 0xA5);
+        assert!(mbc.check_and_reset_ram_updated());
+        assert_eq!(mbc.dumpram()[0x12], 0xA5);
+    }
+
+    #[test]
+    fn rtc_time_and_alarm() {
+        let mut mbc = tama5();
+        // Set 23:59 and an alarm for 00:00.
+        for (command, value) in [(0x05, 0x23), (0x04, 0x59), (0x14, 0x00), (0x15, 0x00), (0x11, 0)] {
+            write(&mut mbc, 0x4, value & 0x0F);
+            write(&mut mbc, 0x5, value >> 4);
+            write(&mut mbc, 0x6, 0x4 | command >> 4);
+            write(&mut mbc, 0x7, command & 0x0F);
+        }
+        run(&mut mbc, 61);
+        write(&mut mbc, 0x6, 0x4);
+        write(&mut mbc, 0x7, 0x7);
+        assert_eq!(result(&mut mbc), 0x00);
+        write(&mut mbc, 0x6, 0x5);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(result(&mut mbc), 0x01);
+
+        // Still goes off when the game only checks well after the alarm minute is over,
+        // but not again until the alarm time comes round once more.
+        run(&mut mbc, 86400 + 600);
+        write(&mut mbc, 0x6, 0x5);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(result(&mut mbc), 0x01);
+        write(&mut mbc, 0x6, 0x5);
+        write(&mut mbc, 0x7, 0x2);
+        assert_eq!(result(&mut mbc), 0x00);
+
+        // Loading a save catches up on the time since it was written, unless stopped.
+        let saved = mbc.dumpram();
+        let mut other = tama5();
+        other.loadram(&saved).unwrap();
+        other.set_rtc_clock(Some(1_000_000 + 100));
+        other.catch_up_rtc();
+        assert_eq!(other.clock(), mbc.clock() + 100);
+        other.set_rtc_clock(Some(1_000_000));
+        write(&mut other, 0x6, 0x4);
+        write(&mut other, 0x7, 0x0);
+        let saved = other.dumpram();
+        other.loadram(&saved).unwrap();
+        other.set_rtc_clock(Some(1_000_000 + 100));
+        other.catch_up_rtc();
+        run(&mut other, 5);
+        assert_eq!(other.clock(), mbc.clock() + 100);
+        other.set_rtc_clock(Some(1_000_000));
+
+        // A RAM-only save keeps the clock running from where it was.
+        let clock = other.clock();
+        other.loadram(&saved[..0x20]).unwrap();
+        assert_eq!(other.clock(), clock);
+        assert_eq!(other.dumpram(), saved);
+        assert!(other.loadram(&saved[..0x21]).is_err());
+    }
+
+    #[test]
+    fn calendar_round_trip() {
+        // 2024-02-29 12:34:56, a Thursday.
+        let secs = from_calendar(&[6, 5, 4, 3, 2, 1, 0, 9, 2, 2, 0, 4, 2]);
+        let page = calendar(secs);
+        assert_eq!(page, [6, 5, 4, 3, 2, 1, 4, 9, 2, 2, 0, 4, 2]);
+    }
+}
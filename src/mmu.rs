@@ -1,9 +1,12 @@
-use crate::gbmode::{GbMode, GbSpeed};
+use crate::gbmode::{GbMode, GbSpeed, HardwareModel};
 use crate::gpu::GPU;
 use crate::keypad::Keypad;
 use crate::mbc::{self, MBC};
-use crate::serial::Serial;
+use crate::serial::{Serial, SerialLink};
+use crate::sgb::Sgb;
 use crate::apu::Sound;
+use crate::bootrom::BootRom;
+use crate::debugger::{StopReason, Watchpoint};
 use crate::timer::Timer;
 use crate::StrResult;
 
@@ -17,6 +20,15 @@ enum DMAType {
     HDMA,
 }
 
+#[derive(Clone, Copy, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct OamDma {
+    src: u16,
+    // Next byte to copy, 0..0xA0
+    pos: u16,
+    // M-cycles until the first byte is copied
+    delay: u8,
+}
+
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct MMU {
     wram: [u8; WRAM_SIZE],
@@ -28,20 +40,41 @@ pub struct MMU {
     pub timer: Timer,
     pub keypad: Keypad,
     pub gpu: GPU,
+    pub sgb: Option<Sgb>,
     #[rkyv(with = rkyv::with::Skip)]
     pub sound: Option<Sound>,
     hdma_status: DMAType,
     hdma_src: u16,
     hdma_dst: u16,
     hdma_len: u8,
+    // Running OAM DMA, and a restarted one still in its startup delay. The old transfer
+    // keeps going until the new one takes over.
+    oam_dma: Option<OamDma>,
+    oam_dma_next: Option<OamDma>,
+    // Last value written to 0xFF46, and the byte on the bus DMA is reading from.
+    oam_dma_reg: u8,
+    oam_dma_byte: u8,
     wrambank: usize,
     pub mbc: mbc::Cartridge,
     pub gbmode: GbMode,
+    pub model: HardwareModel,
     gbspeed: GbSpeed,
     speed_switch_req: bool,
     undocumented_cgb_regs: [u8; 3], // 0xFF72, 0xFF73, 0xFF75
+    #[rkyv(with = rkyv::with::Skip)]
+    pub watchpoints: Vec<Watchpoint>,
+    // First watchpoint hit since the debugger last checked.
+    #[rkyv(with = rkyv::with::Skip)]
+    pub watch_hit: Option<StopReason>,
+    // Mapped over the cartridge until 0xFF50 is written; empty once unmapped.
+    boot_rom: Vec<u8>,
+    // KEY0, written by the CGB boot ROM to select DMG compatibility mode.
+    key0: u8,
 }
 
+/// Seed for the power-on WRAM contents, which real hardware leaves random.
+pub const DEFAULT_WRAM_SEED: u32 = 42;
+
 fn fill_random(slice: &mut [u8], start: u32) {
     // Simple LCG to generate (non-cryptographic) random values
     // Each distinct invocation should use a different start value
@@ -58,9 +91,9 @@ fn fill_random(slice: &mut [u8], start: u32) {
 impl MMU {
     pub fn new(
         cart: mbc::Cartridge,
-        _serial_callback: Option<Box<()>>,
+        serial_link: Option<Box<dyn SerialLink>>,
     ) -> StrResult<MMU> {
-        let serial = Serial::new();
+        let serial = Serial::new(serial_link);
         let mut res = MMU {
             wram: [0; WRAM_SIZE],
             zram: [0; ZRAM_SIZE],
@@ -72,18 +105,28 @@ impl MMU {
             timer: Timer::new(),
             keypad: Keypad::new(),
             gpu: GPU::new(),
+            sgb: None,
             sound: None,
             mbc: cart,
             gbmode: GbMode::Classic,
+            model: HardwareModel::Dmg,
             gbspeed: GbSpeed::Single,
             speed_switch_req: false,
             hdma_src: 0,
             hdma_dst: 0,
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
+            oam_dma: None,
+            oam_dma_next: None,
+            oam_dma_reg: 0xFF,
+            oam_dma_byte: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            boot_rom: Vec::new(),
+            key0: 0,
         };
-        fill_random(&mut res.wram, 42);
+        fill_random(&mut res.wram, DEFAULT_WRAM_SEED);
         if res.rb(0x0143) == 0xC0 {
             return Err("This game does not work in Classic mode");
         }
@@ -93,9 +136,9 @@ impl MMU {
 
     pub fn new_cgb(
         cart: mbc::Cartridge,
-        _serial_callback: Option<Box<()>>,
+        serial_link: Option<Box<dyn SerialLink>>,
     ) -> StrResult<MMU> {
-        let serial = Serial::new();
+        let serial = Serial::new(serial_link);
         let mut res = MMU {
             wram: [0; WRAM_SIZE],
             zram: [0; ZRAM_SIZE],
@@ -107,23 +150,79 @@ impl MMU {
             timer: Timer::new(),
             keypad: Keypad::new(),
             gpu: GPU::new_cgb(),
+            sgb: None,
             sound: None,
             mbc: cart,
             gbmode: GbMode::Color,
+            model: HardwareModel::Cgb,
             gbspeed: GbSpeed::Single,
             speed_switch_req: false,
             hdma_src: 0,
             hdma_dst: 0,
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
+            oam_dma: None,
+            oam_dma_next: None,
+            oam_dma_reg: 0xFF,
+            oam_dma_byte: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            boot_rom: Vec::new(),
+            key0: 0,
         };
-        fill_random(&mut res.wram, 42);
+        fill_random(&mut res.wram, DEFAULT_WRAM_SEED);
         res.determine_mode();
         res.set_initial();
         Ok(res)
     }
 
+    /// Emulates `model`. With a boot ROM, powers on with it mapped and leaves hardware
+    /// setup to it; otherwise starts from the state the boot ROM would leave.
+    pub fn new_with_model(
+        cart: mbc::Cartridge,
+        serial_link: Option<Box<dyn SerialLink>>,
+        model: HardwareModel,
+        boot_rom: Option<BootRom>,
+    ) -> StrResult<MMU> {
+        if boot_rom.as_ref().is_some_and(|b| b.is_cgb() != model.is_cgb()) {
+            return Err("Boot ROM does not match the hardware model");
+        }
+        let mut res = if model.is_cgb() {
+            MMU::new_cgb(cart, serial_link)?
+        } else {
+            MMU::new(cart, serial_link)?
+        };
+        res.model = model;
+        if model == HardwareModel::Sgb {
+            res.sgb = Some(Sgb::new());
+        }
+        if let Some(boot_rom) = boot_rom {
+            // set_initial only wrote zeroed timer registers and GPU registers, so a fresh
+            // GPU restores the power-on state. The CGB boot ROM picks the final mode via KEY0.
+            let mode = if model.is_cgb() { GbMode::Color } else { GbMode::Classic };
+            res.gpu = GPU::new();
+            res.gbmode = mode;
+            res.gpu.gbmode = mode;
+            res.boot_rom = boot_rom.into_data();
+        }
+        Ok(res)
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        !self.boot_rom.is_empty()
+    }
+
+    fn unmap_boot_rom(&mut self) {
+        self.boot_rom = Vec::new();
+        if self.gbmode == GbMode::Color && self.key0 & 0x0C == 0x04 {
+            // DMG game: keep the compatibility palettes the boot ROM left in palette RAM.
+            self.gbmode = GbMode::ColorAsClassic;
+            self.gpu.gbmode = GbMode::ColorAsClassic;
+            self.gpu.compat_palettes = true;
+        }
+    }
+
     fn set_initial(&mut self) {
         self.wb(0xFF05, 0);
         self.wb(0xFF06, 0);
@@ -173,14 +272,24 @@ impl MMU {
         let gputicks = ticks / cpudivider + vramticks;
         let cputicks = ticks + vramticks * cpudivider;
 
+        for _ in 0..cputicks / 4 {
+            self.oam_dma_cycle();
+        }
+
         self.timer.do_cycle(cputicks);
         self.intf |= self.timer.interrupt;
         self.timer.interrupt = 0;
 
+        self.serial.do_cycle(cputicks);
+        self.mbc.do_cycle(gputicks);
+
         self.intf |= self.keypad.interrupt;
         self.keypad.interrupt = 0;
 
         self.gpu.do_cycle(gputicks);
+        if self.gpu.interrupt & 0x01 != 0 && let Some(sgb) = self.sgb.as_mut() {
+            sgb.vblank(&self.gpu);
+        }
         self.intf |= self.gpu.interrupt;
         self.gpu.interrupt = 0;
 
@@ -192,8 +301,33 @@ impl MMU {
         return gputicks;
     }
 
+    /// Refills WRAM as at power-on, from a different seed.
+    pub fn reseed_wram(&mut self, seed: u32) {
+        fill_random(&mut self.wram, seed);
+    }
+
     pub fn rb(&mut self, address: u16) -> u8 {
+        let value = match self.oam_dma_conflict(address) {
+            Some(value) => value,
+            None => self.peek(address),
+        };
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, value, false);
+        }
+        value
+    }
+
+    fn check_watchpoints(&mut self, address: u16, value: u8, write: bool) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.matches(address, write)) {
+            self.watch_hit = Some(StopReason::Watchpoint { address, value, write });
+        }
+    }
+
+    /// Reads without triggering watchpoints, for DMA and debugger access.
+    pub fn peek(&mut self, address: u16) -> u8 {
         match address {
+            0x0000..=0x00FF if self.boot_rom_mapped() => self.boot_rom[address as usize],
+            0x0200..=0x08FF if self.boot_rom.len() > 0x100 => self.boot_rom[address as usize],
             0x0000..=0x7FFF => self.mbc.readrom(address),
             0x8000..=0x9FFF => self.gpu.rb(address),
             0xA000..=0xBFFF => self.mbc.readram(address),
@@ -203,6 +337,8 @@ impl MMU {
             }
             0xFE00..=0xFE9F => self.gpu.rb(address),
             0xFF00 => self.keypad.rb(),
+            // Only the CGB has the fast clock select in SC bit 1.
+            0xFF02 if self.gbmode != GbMode::Color => self.serial.rb(address) | 0x02,
             0xFF01..=0xFF02 => self.serial.rb(address),
             0xFF04..=0xFF07 => self.timer.rb(address),
             0xFF0F => self.intf | 0b11100000,
@@ -220,6 +356,7 @@ impl MMU {
                     })
                     | (if self.speed_switch_req { 1 } else { 0 })
             }
+            0xFF46 => self.oam_dma_reg,
             0xFF40..=0xFF4F => self.gpu.rb(address),
             0xFF51..=0xFF55 => self.hdma_read(address),
             0xFF68..=0xFF6B => self.gpu.rb(address),
@@ -238,6 +375,17 @@ impl MMU {
     }
 
     pub fn wb(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, value, true);
+        }
+        if self.oam_dma_conflict(address).is_some() {
+            return;
+        }
+        self.poke(address, value);
+    }
+
+    /// Writes without triggering watchpoints, for DMA and debugger access.
+    pub fn poke(&mut self, address: u16, value: u8) {
         match address {
             0x0000..=0x7FFF => self.mbc.writerom(address, value),
             0x8000..=0x9FFF => self.gpu.wb(address, value),
@@ -247,11 +395,26 @@ impl MMU {
                 self.wram[(self.wrambank * 0x1000) | (address as usize & 0x0FFF)] = value
             }
             0xFE00..=0xFE9F => self.gpu.wb(address, value),
-            0xFF00 => self.keypad.wb(value),
+            0xFF00 => match self.sgb.as_mut() {
+                // The SGB sees the write first; pulses that are part of a packet do not move
+                // on to the next MLT_REQ controller.
+                Some(sgb) => {
+                    sgb.write_joypad(value, &mut self.keypad);
+                    if sgb.receiving_packet() {
+                        self.keypad.select_rows(value);
+                    } else {
+                        self.keypad.wb(value);
+                    }
+                }
+                None => self.keypad.wb(value),
+            },
+            0xFF02 if self.gbmode != GbMode::Color => self.serial.wb(address, value & !0x02),
             0xFF01..=0xFF02 => self.serial.wb(address, value),
             0xFF04..=0xFF07 => self.timer.wb(address, value),
             0xFF10..=0xFF3F => self.sound.as_mut().map_or((), |s| s.wb(address, value)),
             0xFF46 => self.oamdma(value),
+            0xFF4C if self.boot_rom_mapped() && self.gbmode == GbMode::Color => self.key0 = value,
+            0xFF50 if self.boot_rom_mapped() && value != 0 => self.unmap_boot_rom(),
             0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF6C | 0xFF70 | 0xFF76..=0xFF77
                 if self.gbmode != GbMode::Color => {}
             0xFF72..=0xFF73 | 0xFF75..=0xFF77 if self.gbmode == GbMode::Classic => {}
@@ -294,11 +457,57 @@ impl MMU {
         self.speed_switch_req = false;
     }
 
+    // Starts (or restarts) OAM DMA. It copies one byte per M-cycle after a 1 M-cycle delay;
+    // sources from 0xE000 up read the WRAM below them.
     fn oamdma(&mut self, value: u8) {
-        let base = (value as u16) << 8;
-        for i in 0..0xA0 {
-            let b = self.rb(base + i);
-            self.wb(0xFE00 + i, b);
+        self.oam_dma_reg = value;
+        let src = match value {
+            0xE0..=0xFF => (value as u16 - 0x20) << 8,
+            _ => (value as u16) << 8,
+        };
+        self.oam_dma_next = Some(OamDma { src, pos: 0, delay: 1 });
+    }
+
+    fn oam_dma_cycle(&mut self) {
+        if let Some(next) = self.oam_dma_next.as_mut() {
+            if next.delay == 0 {
+                self.oam_dma = self.oam_dma_next.take();
+            } else {
+                next.delay -= 1;
+            }
+        }
+        let Some(mut dma) = self.oam_dma else {
+            return;
+        };
+        let b = self.peek(dma.src + dma.pos);
+        self.gpu.wb(0xFE00 + dma.pos, b);
+        self.oam_dma_byte = b;
+        dma.pos += 1;
+        self.oam_dma = if dma.pos < 0xA0 { Some(dma) } else { None };
+    }
+
+    // Memory bus an address is on: 0 external (cartridge and, on DMG, WRAM), 1 VRAM,
+    // 2 WRAM on CGB. OAM, I/O and HRAM are on none.
+    fn bus(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => Some(0),
+            0x8000..=0x9FFF => Some(1),
+            0xC000..=0xFDFF if self.gbmode == GbMode::Color => Some(2),
+            0xC000..=0xFDFF => Some(0),
+            _ => None,
+        }
+    }
+
+    // While OAM DMA runs, OAM reads 0xFF and the bus it reads from returns the byte being
+    // copied. Writes to either are lost. Returns None if the CPU can access the address.
+    fn oam_dma_conflict(&self, address: u16) -> Option<u8> {
+        let dma = self.oam_dma.as_ref()?;
+        if (0xFE00..=0xFE9F).contains(&address) {
+            return Some(0xFF);
+        }
+        match self.bus(address) {
+            Some(bus) if Some(bus) == self.bus(dma.src) => Some(self.oam_dma_byte),
+            _ => None,
         }
     }
 
@@ -384,7 +593,7 @@ impl MMU {
     fn perform_vramdma_row(&mut self) {
         let mmu_src = self.hdma_src;
         for j in 0..0x10 {
-            let b: u8 = self.rb(mmu_src + j);
+            let b: u8 = self.peek(mmu_src + j);
             self.gpu.wb(self.hdma_dst + j, b);
         }
         self.hdma_src += 0x10;
@@ -397,3 +606,86 @@ impl MMU {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::MMU;
+    use crate::gbmode::HardwareModel;
+    use crate::mbc::Cartridge;
+
+    fn mmu() -> MMU {
+        let cart = Cartridge::from_buffer(vec![0; 0x8000], true).unwrap();
+        let mut mmu = MMU::new(cart, None).unwrap();
+        for i in 0..0x200 {
+            mmu.wb(0xC100 + i, i as u8 ^ 0x5A);
+        }
+        mmu.wb(0xFF80, 0x42);
+        mmu
+    }
+
+    fn m_cycles(mmu: &mut MMU, n: u32) {
+        for _ in 0..n {
+            mmu.do_cycle(4);
+        }
+    }
+
+    #[test]
+    fn oam_dma_takes_160_cycles() {
+        let mut mmu = mmu();
+        mmu.wb(0xFF46, 0xC1);
+        assert_eq!(mmu.rb(0xFF46), 0xC1);
+        m_cycles(&mut mmu, 1);
+        assert_eq!(mmu.rb(0xFE00), 0x00);
+        m_cycles(&mut mmu, 1);
+        // OAM is blocked and WRAM shares the DMG's external bus with the source.
+        assert_eq!(mmu.rb(0xFE00), 0xFF);
+        assert_eq!(mmu.rb(0xD000), 0x5A);
+        assert_eq!(mmu.rb(0xFF80), 0x42);
+        mmu.wb(0xC100, 0);
+        m_cycles(&mut mmu, 158);
+        assert_eq!(mmu.rb(0xFE00), 0xFF);
+        m_cycles(&mut mmu, 1);
+        assert_eq!(mmu.rb(0xFE00), 0x5A);
+        assert_eq!(mmu.rb(0xFE9F), 0x9F ^ 0x5A);
+        assert_eq!(mmu.rb(0xC100), 0x5A);
+    }
+
+    #[test]
+    fn oam_dma_restart() {
+        let mut mmu = mmu();
+        mmu.wb(0xC200, 0x77);
+        mmu.wb(0xFF46, 0xC1);
+        m_cycles(&mut mmu, 11);
+        mmu.wb(0xFF46, 0xC2);
+        // The first transfer goes on during the new one's startup delay.
+        m_cycles(&mut mmu, 1);
+        assert_eq!(mmu.gpu.rb(0xFE0A), 0x0A ^ 0x5A);
+        m_cycles(&mut mmu, 159);
+        assert_eq!(mmu.rb(0xFE00), 0xFF);
+        m_cycles(&mut mmu, 1);
+        assert_eq!(mmu.rb(0xFE00), 0x77);
+        assert_eq!(mmu.rb(0xFE9F), 0x9F ^ 0x5A);
+    }
+
+    #[test]
+    fn sgb_packet_pulses_keep_the_controller() {
+        let cart = Cartridge::from_buffer(vec![0; 0x8000], true).unwrap();
+        let mut mmu = MMU::new_with_model(cart, None, HardwareModel::Sgb, None).unwrap();
+        let pulse = |mmu: &mut MMU, lines: u8| {
+            mmu.wb(0xFF00, lines);
+            mmu.wb(0xFF00, 0x30);
+        };
+        // MLT_REQ for two players, then a second (ignored) packet of all ones.
+        for packet in [[0x89u8, 0x01], [0xFF, 0xFF]] {
+            pulse(&mut mmu, 0x00);
+            for bit in 0..128 {
+                let byte = packet.get(bit / 8).copied().unwrap_or(0);
+                pulse(&mut mmu, if byte & (1 << (bit % 8)) != 0 { 0x10 } else { 0x20 });
+            }
+            pulse(&mut mmu, 0x20);
+        }
+        assert_eq!(mmu.rb(0xFF00) & 0x0F, 0x0F);
+        pulse(&mut mmu, 0x10);
+        assert_eq!(mmu.rb(0xFF00) & 0x0F, 0x0E);
+    }
+}
@@ -0,0 +1,501 @@
+//! Input movies: joypad and tilt events recorded against emulated time, replayed
+//! deterministically.
+//!
+//! Events are stamped with the tick count (at single speed) they were applied at, so
+//! playback presses and releases keys at exactly the same instruction boundary. The
+//! cartridge RTC runs on a clock pinned to the recording's start time plus emulated
+//! time, and power-on movies carry the WRAM seed and battery RAM they started with.
+use crate::camera::{CameraImage, SENSOR_H, SENSOR_W};
+use crate::device::Device;
+use crate::gbmode::HardwareModel;
+use crate::gpu::PpuRenderer;
+use crate::keypad::KeypadKey;
+use crate::StrResult;
+use std::path::Path;
+
+const MOVIE_MAGIC: &[u8; 8] = b"RGBEMV02";
+const TICKS_PER_SECOND: u64 = 4_194_304;
+
+const KEYS: [KeypadKey; 8] = [
+    KeypadKey::Right,
+    KeypadKey::Left,
+    KeypadKey::Up,
+    KeypadKey::Down,
+    KeypadKey::A,
+    KeypadKey::B,
+    KeypadKey::Select,
+    KeypadKey::Start,
+];
+// Event code for a tilt reading; key events use the index into `KEYS`, plus 0x80 when
+// pressed.
+const CODE_TILT: u8 = 0x40;
+
+#[derive(Clone, Copy)]
+pub enum MovieInput {
+    Key { key: KeypadKey, down: bool },
+    /// Accelerometer reading for tilt cartridges, in g.
+    Tilt { x: f32, y: f32 },
+}
+
+#[derive(Clone, Copy)]
+pub struct MovieEvent {
+    pub tick: u64,
+    pub input: MovieInput,
+}
+
+/// Where playback starts from.
+#[derive(Clone)]
+pub enum MovieStart {
+    /// A freshly constructed device, with this battery RAM loaded.
+    PowerOn { wram_seed: u32, cartridge_ram: Vec<u8> },
+    /// An embedded save state.
+    State(Vec<u8>),
+}
+
+#[derive(Clone)]
+pub struct Movie {
+    pub rom_title: String,
+    /// Hardware the movie was recorded on; it decides the startup registers.
+    pub model: HardwareModel,
+    /// Renderer the movie was recorded with; the two differ in mode 3 timing.
+    pub renderer: PpuRenderer,
+    /// Whether a boot ROM was mapped when the movie started. Power-on movies only replay
+    /// with the same setting; state movies restore the boot ROM with the state.
+    pub boot_rom: bool,
+    /// What a Pocket Camera's sensor sees when the movie starts.
+    pub camera_image: Option<CameraImage>,
+    /// Unix time the cartridge RTC reads when the movie starts.
+    pub rtc_start: u64,
+    pub start: MovieStart,
+    pub events: Vec<MovieEvent>,
+    /// Length in ticks.
+    pub length: u64,
+    /// `Device::frame_hash` when the recording stopped.
+    pub final_hash: u64,
+}
+
+impl Movie {
+    pub fn is_power_on(&self) -> bool {
+        matches!(self.start, MovieStart::PowerOn { .. })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MOVIE_MAGIC);
+        data.push(self.rom_title.len() as u8);
+        data.extend_from_slice(self.rom_title.as_bytes());
+        data.push(HardwareModel::ALL.iter().position(|m| *m == self.model).unwrap_or(0) as u8);
+        data.push((self.renderer == PpuRenderer::Fifo) as u8);
+        data.push(self.boot_rom as u8);
+        match &self.camera_image {
+            Some(image) => {
+                data.push(1);
+                data.extend_from_slice(image.luma());
+            }
+            None => data.push(0),
+        }
+        data.extend_from_slice(&self.rtc_start.to_le_bytes());
+        data.extend_from_slice(&self.length.to_le_bytes());
+        data.extend_from_slice(&self.final_hash.to_le_bytes());
+        let (kind, seed, blob) = match &self.start {
+            MovieStart::PowerOn { wram_seed, cartridge_ram } => (0u8, *wram_seed, cartridge_ram),
+            MovieStart::State(state) => (1u8, 0, state),
+        };
+        data.push(kind);
+        data.extend_from_slice(&seed.to_le_bytes());
+        data.extend_from_slice(&(blob.len() as u32).to_le_bytes());
+        data.extend_from_slice(blob);
+        data.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            data.extend_from_slice(&event.tick.to_le_bytes());
+            match event.input {
+                MovieInput::Key { key, down } => {
+                    let key = KEYS.iter().position(|k| *k == key).unwrap_or(0) as u8;
+                    data.push(key | if down { 0x80 } else { 0 });
+                }
+                MovieInput::Tilt { x, y } => {
+                    data.push(CODE_TILT);
+                    data.extend_from_slice(&x.to_le_bytes());
+                    data.extend_from_slice(&y.to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> StrResult<Movie> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.take(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return Err("Not a movie file");
+        }
+        let title_len = reader.u8()? as usize;
+        let rom_title = String::from_utf8_lossy(reader.take(title_len)?).into_owned();
+        let model = *HardwareModel::ALL.get(reader.u8()? as usize).ok_or("Unknown hardware model in movie")?;
+        let renderer = match reader.u8()? {
+            0 => PpuRenderer::Scanline,
+            1 => PpuRenderer::Fifo,
+            _ => return Err("Unknown renderer in movie"),
+        };
+        let boot_rom = reader.u8()? != 0;
+        let camera_image = match reader.u8()? {
+            0 => None,
+            _ => Some(CameraImage::from_luma(SENSOR_W, SENSOR_H, reader.take(SENSOR_W * SENSOR_H)?)),
+        };
+        let rtc_start = reader.u64()?;
+        let length = reader.u64()?;
+        let final_hash = reader.u64()?;
+        let kind = reader.u8()?;
+        let wram_seed = reader.u32()?;
+        let blob_len = reader.u32()? as usize;
+        let blob = reader.take(blob_len)?.to_vec();
+        let start = match kind {
+            0 => MovieStart::PowerOn { wram_seed, cartridge_ram: blob },
+            1 => MovieStart::State(blob),
+            _ => return Err("Unknown movie start type"),
+        };
+        let count = reader.u32()? as usize;
+        let mut events = Vec::with_capacity(count.min(data.len() / 9));
+        for _ in 0..count {
+            let tick = reader.u64()?;
+            let code = reader.u8()?;
+            let input = match code {
+                CODE_TILT => MovieInput::Tilt { x: reader.f32()?, y: reader.f32()? },
+                _ => {
+                    let key = *KEYS.get((code & 0x7F) as usize).ok_or("Invalid key in movie")?;
+                    MovieInput::Key { key, down: code & 0x80 != 0 }
+                }
+            };
+            events.push(MovieEvent { tick, input });
+        }
+        Ok(Movie { rom_title, model, renderer, boot_rom, camera_image, rtc_start, start, events, length, final_hash })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> StrResult<()> {
+        std::fs::write(path, self.encode()).map_err(|_| "Could not write movie file")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> StrResult<Movie> {
+        let data = std::fs::read(path).map_err(|_| "Could not read movie file")?;
+        Movie::decode(&data)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> StrResult<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.data.len());
+        let slice = end.map(|end| &self.data[self.pos..end]).ok_or("Movie file is truncated")?;
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> StrResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> StrResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> StrResult<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> StrResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+// Runs one step, keeping the pinned RTC in line with emulated time.
+fn step(device: &mut Device, ticks: &mut u64, rtc_start: u64) -> u32 {
+    let n = device.do_cycle();
+    let before = *ticks / TICKS_PER_SECOND;
+    *ticks += n as u64;
+    if *ticks / TICKS_PER_SECOND != before {
+        device.set_rtc_clock(Some(rtc_start + *ticks / TICKS_PER_SECOND));
+    }
+    n
+}
+
+pub struct MovieRecorder {
+    movie: Movie,
+    ticks: u64,
+}
+
+impl MovieRecorder {
+    /// Starts recording from power-on. `device` must be freshly constructed; its WRAM is
+    /// refilled from `wram_seed` and its RTC pinned to `rtc_start`.
+    pub fn from_power_on(device: &mut Device, wram_seed: u32, rtc_start: u64) -> MovieRecorder {
+        device.reseed_wram(wram_seed);
+        let cartridge_ram = device.cartridge_ram();
+        MovieRecorder::start(device, rtc_start, MovieStart::PowerOn { wram_seed, cartridge_ram })
+    }
+
+    /// Starts recording from the device's current state, which is embedded in the movie.
+    pub fn from_state(device: &mut Device, rtc_start: u64) -> StrResult<MovieRecorder> {
+        device.set_rtc_clock(Some(rtc_start));
+        let state = device.snapshot()?;
+        Ok(MovieRecorder::start(device, rtc_start, MovieStart::State(state)))
+    }
+
+    fn start(device: &mut Device, rtc_start: u64, start: MovieStart) -> MovieRecorder {
+        device.set_rtc_clock(Some(rtc_start));
+        MovieRecorder {
+            movie: Movie {
+                rom_title: device.romname(),
+                model: device.model(),
+                renderer: device.renderer(),
+                boot_rom: device.boot_rom_mapped(),
+                camera_image: device.camera_image(),
+                rtc_start,
+                start,
+                events: Vec::new(),
+                length: 0,
+                final_hash: 0,
+            },
+            ticks: 0,
+        }
+    }
+
+    /// Use in place of `Device::do_cycle` while recording.
+    pub fn do_cycle(&mut self, device: &mut Device) -> u32 {
+        step(device, &mut self.ticks, self.movie.rtc_start)
+    }
+
+    pub fn keydown(&mut self, device: &mut Device, key: KeypadKey) {
+        self.record(MovieInput::Key { key, down: true });
+        device.keydown(key);
+    }
+
+    pub fn keyup(&mut self, device: &mut Device, key: KeypadKey) {
+        self.record(MovieInput::Key { key, down: false });
+        device.keyup(key);
+    }
+
+    pub fn set_tilt(&mut self, device: &mut Device, x: f32, y: f32) {
+        self.record(MovieInput::Tilt { x, y });
+        device.set_tilt(x, y);
+    }
+
+    fn record(&mut self, input: MovieInput) {
+        self.movie.events.push(MovieEvent { tick: self.ticks, input });
+    }
+
+    /// Ends the recording and hands the RTC back to the host clock.
+    pub fn finish(mut self, device: &mut Device) -> Movie {
+        self.movie.length = self.ticks;
+        self.movie.final_hash = device.frame_hash();
+        device.set_rtc_clock(None);
+        self.movie
+    }
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    ticks: u64,
+    next_event: usize,
+}
+
+impl MoviePlayer {
+    /// Checks that `movie` can be played on `device` without changing anything.
+    pub fn check(movie: &Movie, device: &Device) -> StrResult<()> {
+        if device.romname() != movie.rom_title {
+            return Err("Movie was recorded with a different ROM");
+        }
+        if device.model() != movie.model {
+            return Err("Movie was recorded on a different hardware model");
+        }
+        if device.renderer() != movie.renderer {
+            return Err("Movie was recorded with a different renderer");
+        }
+        if movie.is_power_on() && device.boot_rom_mapped() != movie.boot_rom {
+            return Err(match movie.boot_rom {
+                true => "Movie was recorded with a boot ROM",
+                false => "Movie was recorded without a boot ROM",
+            });
+        }
+        Ok(())
+    }
+
+    /// Prepares `device` for playback. Power-on movies need a freshly constructed device.
+    /// Its battery RAM is replaced, so the device is detached from its save file once the
+    /// movie has passed `check`.
+    pub fn new(movie: Movie, device: &mut Device) -> StrResult<MoviePlayer> {
+        MoviePlayer::check(&movie, device)?;
+        // Replaying someone else's battery RAM must not overwrite the save file.
+        device.detach_save_file();
+        match &movie.start {
+            MovieStart::PowerOn { wram_seed, cartridge_ram } => {
+                device.reseed_wram(*wram_seed);
+                if !cartridge_ram.is_empty() {
+                    device.load_cartridge_ram(cartridge_ram)?;
+                }
+            }
+            MovieStart::State(state) => device.restore_snapshot(state)?,
+        }
+        if let Some(image) = &movie.camera_image {
+            device.set_camera_image(image);
+        }
+        device.set_rtc_clock(Some(movie.rtc_start));
+        Ok(MoviePlayer { movie, ticks: 0, next_event: 0 })
+    }
+
+    /// Use in place of `Device::do_cycle` while playing. Does nothing once finished.
+    pub fn do_cycle(&mut self, device: &mut Device) -> u32 {
+        if self.is_finished() {
+            return 0;
+        }
+        while let Some(event) = self.movie.events.get(self.next_event) {
+            if event.tick > self.ticks {
+                break;
+            }
+            match event.input {
+                MovieInput::Key { key, down: true } => device.keydown(key),
+                MovieInput::Key { key, down: false } => device.keyup(key),
+                MovieInput::Tilt { x, y } => device.set_tilt(x, y),
+            }
+            self.next_event += 1;
+        }
+        step(device, &mut self.ticks, self.movie.rtc_start)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ticks >= self.movie.length
+    }
+
+    /// Checks that playback ended on the same frame as the recording.
+    pub fn verify(&self, device: &Device) -> StrResult<()> {
+        if !self.is_finished() {
+            return Err("Movie has not finished playing");
+        }
+        if device.frame_hash() != self.movie.final_hash {
+            return Err("Playback diverged from the recording");
+        }
+        Ok(())
+    }
+
+    /// Ends playback and hands the RTC back to the host clock.
+    pub fn finish(self, device: &mut Device) {
+        device.set_rtc_clock(None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Writes the joypad state into the background tile map so it shows up in the frame.
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10E].copy_from_slice(&[
+            0x3E, 0x20, // LD A,$20
+            0xE0, 0x00, // LDH ($00),A
+            0xF0, 0x00, // LDH A,($00)
+            0xEA, 0x00, 0x80, // LD ($8000),A
+            0xEA, 0x10, 0x80, // LD ($8010),A
+            0x18, 0xF2, // JR $0100
+        ]);
+        rom
+    }
+
+    fn test_device() -> Device {
+        Device::new_from_buffer(test_rom(), true, None).unwrap()
+    }
+
+    fn record(device: &mut Device) -> Movie {
+        let mut recorder = MovieRecorder::from_power_on(device, 7, 1_700_000_000);
+        for frame in 0..20u64 {
+            match frame {
+                3 => recorder.keydown(device, KeypadKey::Down),
+                9 => recorder.keyup(device, KeypadKey::Down),
+                12 => recorder.keydown(device, KeypadKey::Left),
+                15 => recorder.set_tilt(device, 0.25, -0.5),
+                _ => {}
+            }
+            let mut ticks = 0;
+            while ticks < 70224 {
+                ticks += recorder.do_cycle(device);
+            }
+        }
+        recorder.finish(device)
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let movie = Movie::decode(&record(&mut test_device()).encode()).unwrap();
+        assert_eq!(movie.events.len(), 4);
+        assert!(matches!(movie.events[3].input, MovieInput::Tilt { x: 0.25, y: -0.5 }));
+        assert!(movie.is_power_on());
+
+        let mut device = test_device();
+        let mut player = MoviePlayer::new(movie, &mut device).unwrap();
+        while !player.is_finished() {
+            player.do_cycle(&mut device);
+        }
+        assert_eq!(player.verify(&device), Ok(()));
+    }
+
+    #[test]
+    fn divergence_is_detected() {
+        let mut movie = record(&mut test_device());
+        movie.events.truncate(2);
+
+        let mut device = test_device();
+        let mut player = MoviePlayer::new(movie, &mut device).unwrap();
+        while !player.is_finished() {
+            player.do_cycle(&mut device);
+        }
+        assert!(player.verify(&device).is_err());
+    }
+
+    #[test]
+    fn records_hardware_model() {
+        let movie = Movie::decode(&record(&mut test_device()).encode()).unwrap();
+        assert_eq!(movie.model, HardwareModel::Dmg);
+
+        let mut sgb =
+            Device::new_from_buffer_with_model(test_rom(), HardwareModel::Sgb, None, true, None).unwrap();
+        assert!(MoviePlayer::new(movie, &mut sgb).is_err());
+    }
+
+    #[test]
+    fn records_renderer_and_boot_rom() {
+        let movie = Movie::decode(&record(&mut test_device()).encode()).unwrap();
+        assert!(movie.renderer == PpuRenderer::Scanline && !movie.boot_rom);
+
+        let mut fifo = test_device();
+        fifo.set_renderer(PpuRenderer::Fifo);
+        assert!(MoviePlayer::check(&movie, &fifo).is_err());
+        let boot_rom = crate::bootrom::BootRom::new(vec![0; 0x100]).unwrap();
+        let booting =
+            Device::new_from_buffer_with_model(test_rom(), HardwareModel::Dmg, Some(boot_rom), true, None).unwrap();
+        assert!(MoviePlayer::check(&movie, &booting).is_err());
+        assert!(MoviePlayer::check(&movie, &test_device()).is_ok());
+    }
+
+    #[test]
+    fn camera_image_is_part_of_the_movie() {
+        let mut rom = test_rom();
+        rom[0x147] = 0xFC;
+        let image = CameraImage::from_luma(SENSOR_W, SENSOR_H, &[0x42; SENSOR_W * SENSOR_H]);
+        let mut device = Device::new_from_buffer(rom.clone(), true, None).unwrap();
+        device.set_camera_image(&image);
+        let movie = Movie::decode(&record(&mut device).encode()).unwrap();
+        assert_eq!(movie.camera_image.as_ref(), Some(&image));
+
+        let mut device = Device::new_from_buffer(rom, true, None).unwrap();
+        MoviePlayer::new(movie, &mut device).unwrap();
+        assert_eq!(device.camera_image(), Some(image));
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let data = record(&mut test_device()).encode();
+        assert!(Movie::decode(&data[..data.len() - 3]).is_err());
+        assert!(Movie::decode(b"RGBEST01").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Game Boy Printer, attached to the serial port as the externally clocked side.
+use crate::serial::SerialLink;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+const WIDTH: usize = 160;
+const BYTES_PER_TILE_ROW: usize = 20 * 16;
+// One print takes at most 9 bands of 2 tile rows, 160x144 pixels (0x1680 bytes).
+const BUFFER_SIZE: usize = 9 * 2 * BYTES_PER_TILE_ROW;
+// Pixel rows fed per unit of the top/bottom margin nibbles.
+const MARGIN_ROWS: usize = 8;
+// Status requests answered with "printing" before a print job reports completion.
+const PRINT_BUSY_POLLS: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    output: PathBuf,
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_polls: u8,
+    buffer: Vec<u8>,
+    // Pixel rows (shades 0-3) of the sheet being printed; long images span several
+    // print commands that end without a bottom margin.
+    sheet: Vec<u8>,
+    printed: Vec<PathBuf>,
+    // PNG encoding and writing happen off the emulation thread.
+    writers: Vec<JoinHandle<()>>,
+}
+
+impl Printer {
+    /// Printouts are written as `<rom name>_print_<n>.png` next to `rom_path`.
+    pub fn new(rom_path: impl AsRef<Path>) -> Printer {
+        Printer {
+            output: rom_path.as_ref().to_path_buf(),
+            stage: Stage::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            sheet: Vec::new(),
+            printed: Vec::new(),
+            writers: Vec::new(),
+        }
+    }
+
+    /// Printouts so far, oldest first. They are written in the background; call
+    /// `finish_writing` to be sure the files are complete.
+    pub fn printed(&self) -> &[PathBuf] {
+        &self.printed
+    }
+
+    /// Waits until every printout has been written.
+    pub fn finish_writing(&mut self) {
+        for writer in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+    }
+
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.stage = match self.stage {
+            Stage::Magic1 if byte == 0x88 => Stage::Magic2,
+            Stage::Magic1 => Stage::Magic1,
+            Stage::Magic2 if byte == 0x33 => Stage::Command,
+            Stage::Magic2 => Stage::Magic1,
+            Stage::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = byte & 0x01 == 0x01;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthLow
+            }
+            Stage::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthHigh
+            }
+            Stage::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 { Stage::ChecksumLow } else { Stage::Data }
+            }
+            Stage::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length { Stage::ChecksumLow } else { Stage::Data }
+            }
+            Stage::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                Stage::ChecksumHigh
+            }
+            Stage::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                Stage::Alive
+            }
+            Stage::Alive => {
+                reply = 0x81;
+                Stage::Status
+            }
+            Stage::Status => {
+                self.process_packet();
+                reply = self.status;
+                Stage::Magic1
+            }
+        };
+        reply
+    }
+
+    fn process_packet(&mut self) {
+        if self.received_checksum != self.checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            CMD_DATA => {
+                let packet = std::mem::take(&mut self.packet);
+                if self.compressed {
+                    self.decompress(&packet);
+                } else {
+                    self.buffer.extend_from_slice(&packet);
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+                self.packet = packet;
+                if !self.buffer.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            CMD_PRINT if self.packet.len() >= 4 => {
+                let sheets = self.packet[0];
+                let margins = self.packet[1];
+                let palette = match self.packet[2] {
+                    // Some games leave the palette unset and expect the normal one.
+                    0x00 => 0xE4,
+                    p => p,
+                };
+                if sheets > 0 {
+                    self.print(margins >> 4, margins & 0x0F, palette);
+                }
+                self.buffer.clear();
+                self.status = (self.status & !STATUS_UNPROCESSED) | STATUS_PRINTING;
+                self.busy_polls = PRINT_BUSY_POLLS;
+            }
+            CMD_STATUS if self.busy_polls > 0 => {
+                self.busy_polls -= 1;
+                if self.busy_polls == 0 {
+                    self.status &= !(STATUS_PRINTING | STATUS_IMAGE_FULL);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn decompress(&mut self, packet: &[u8]) {
+        let mut i = 0;
+        while i < packet.len() {
+            let control = packet[i];
+            i += 1;
+            if control & 0x80 == 0x80 {
+                let count = (control & 0x7F) as usize + 2;
+                if let Some(&v) = packet.get(i) {
+                    self.buffer.extend(std::iter::repeat_n(v, count));
+                }
+                i += 1;
+            } else {
+                let count = control as usize + 1;
+                let end = (i + count).min(packet.len());
+                self.buffer.extend_from_slice(&packet[i..end]);
+                i = end;
+            }
+        }
+    }
+
+    fn print(&mut self, top_margin: u8, bottom_margin: u8, palette: u8) {
+        self.sheet.resize(self.sheet.len() + top_margin as usize * MARGIN_ROWS * WIDTH, 0);
+        self.sheet.extend(decode_tiles(&self.buffer, palette));
+        // Without a bottom margin the paper is not fed, so the next print continues the sheet.
+        if bottom_margin == 0 {
+            return;
+        }
+        self.sheet.resize(self.sheet.len() + bottom_margin as usize * MARGIN_ROWS * WIDTH, 0);
+        let sheet = std::mem::take(&mut self.sheet);
+        let path = self.next_output_path();
+        self.writers.retain(|writer| !writer.is_finished());
+        let target = path.clone();
+        self.writers.push(std::thread::spawn(move || {
+            if let Err(e) = write_png(&target, &sheet) {
+                eprintln!("Failed to write printout {}: {}", target.display(), e);
+            }
+        }));
+        self.printed.push(path);
+    }
+
+    fn next_output_path(&self) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "printer".to_string());
+        (1..)
+            .map(|n| self.output.with_file_name(format!("{}_print_{:03}.png", stem, n)))
+            .find(|p| !p.exists() && !self.printed.contains(p))
+            .unwrap()
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.finish_writing();
+    }
+}
+
+impl SerialLink for Printer {
+    fn exchange(&mut self, out: u8, _clock: u64) -> Option<u8> {
+        Some(self.receive(out))
+    }
+}
+
+/// Converts 2bpp tile data, 20 tiles per row, into one shade (0 = white, 3 = black) per pixel.
+fn decode_tiles(data: &[u8], palette: u8) -> Vec<u8> {
+    let tile_rows = data.len() / BYTES_PER_TILE_ROW;
+    let mut pixels = vec![0; tile_rows * 8 * WIDTH];
+    for (i, tile) in data[..tile_rows * BYTES_PER_TILE_ROW].chunks_exact(16).enumerate() {
+        let (tx, ty) = (i % 20, i / 20);
+        for line in 0..8 {
+            let (lo, hi) = (tile[line * 2], tile[line * 2 + 1]);
+            for x in 0..8 {
+                let bit = 7 - x;
+                let color = ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1);
+                let shade = (palette >> (color * 2)) & 0x03;
+                pixels[(ty * 8 + line) * WIDTH + tx * 8 + x] = shade;
+            }
+        }
+    }
+    pixels
+}
+
+fn write_png(path: &Path, sheet: &[u8]) -> std::io::Result<()> {
+    const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, WIDTH as u32, (sheet.len() / WIDTH) as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = sheet.iter().map(|&s| SHADES[s as usize]).collect();
+    encoder
+        .write_header()
+        .and_then(|mut w| w.write_image_data(&pixels))
+        .map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+        let len = data.len() as u16;
+        let mut bytes = vec![0x88, 0x33, command, compression, len as u8, (len >> 8) as u8];
+        bytes.extend_from_slice(data);
+        let checksum = bytes[2..].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        bytes.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8]);
+        for b in bytes {
+            assert_eq!(printer.receive(b), 0x00);
+        }
+        (printer.receive(0), printer.receive(0))
+    }
+
+    fn scratch_rom_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-gbe-printer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("game.gb")
+    }
+
+    #[test]
+    fn packets_report_alive_and_status() {
+        let mut printer = Printer::new(scratch_rom_path("status"));
+        assert_eq!(send_packet(&mut printer, CMD_INIT, 0, &[]), (0x81, 0x00));
+        assert_eq!(send_packet(&mut printer, CMD_DATA, 0, &[0; 640]), (0x81, STATUS_UNPROCESSED));
+        assert_eq!(send_packet(&mut printer, CMD_STATUS, 0, &[]), (0x81, STATUS_UNPROCESSED));
+    }
+
+    #[test]
+    fn bad_checksum_sets_error_bit() {
+        let mut printer = Printer::new(scratch_rom_path("checksum"));
+        for b in [0x88, 0x33, CMD_INIT, 0, 0, 0, 0x55, 0x00] {
+            printer.receive(b);
+        }
+        assert_eq!(printer.receive(0), 0x81);
+        assert_eq!(printer.receive(0), STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn print_writes_png_with_margins_and_palette() {
+        let rom = scratch_rom_path("print");
+        let mut printer = Printer::new(&rom);
+        send_packet(&mut printer, CMD_INIT, 0, &[]);
+        // Two tile rows: color 3 in the first half of each tile row, color 0 after.
+        let mut band = vec![0xFF; 320];
+        band.extend_from_slice(&[0x00; 320]);
+        send_packet(&mut printer, CMD_DATA, 0, &band);
+        send_packet(&mut printer, CMD_DATA, 0, &[]);
+        let (_, status) = send_packet(&mut printer, CMD_PRINT, 0, &[1, 0x01, 0xE4, 0x40]);
+        assert_eq!(status & STATUS_PRINTING, STATUS_PRINTING);
+
+        assert_eq!(printer.printed(), &[rom.with_file_name("game_print_001.png")]);
+        printer.finish_writing();
+        let decoder = png::Decoder::new(std::fs::File::open(&printer.printed()[0]).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (160, 16 + MARGIN_ROWS as u32));
+        assert_eq!(pixels[0], 0x00);
+        assert_eq!(pixels[8 * 160], 0xFF);
+
+        for _ in 0..PRINT_BUSY_POLLS {
+            send_packet(&mut printer, CMD_STATUS, 0, &[]);
+        }
+        assert_eq!(send_packet(&mut printer, CMD_STATUS, 0, &[]).1, 0x00);
+    }
+
+    #[test]
+    fn compressed_data_is_expanded() {
+        let mut printer = Printer::new(scratch_rom_path("rle"));
+        // A run of 0x7F + 2 = 129 bytes of 0xAA, then 3 literal bytes.
+        send_packet(&mut printer, CMD_DATA, 1, &[0xFF, 0xAA, 0x02, 1, 2, 3]);
+        assert_eq!(printer.buffer.len(), 132);
+        assert_eq!(&printer.buffer[127..], &[0xAA, 0xAA, 1, 2, 3]);
+    }
+}
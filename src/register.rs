@@ -1,4 +1,4 @@
-use crate::gbmode::GbMode;
+use crate::gbmode::{GbMode, HardwareModel};
 
 #[derive(Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct Registers {
@@ -65,6 +65,44 @@ impl Registers {
         }
     }
 
+    /// State left by `model`'s boot ROM, which differs from the DMG and CGB values in `new`.
+    pub fn for_model(model: HardwareModel, mode: GbMode) -> Registers {
+        let mut reg = Registers::new(mode);
+        match model {
+            HardwareModel::Dmg | HardwareModel::Cgb => {}
+            HardwareModel::Mgb => reg.a = 0xFF,
+            HardwareModel::Sgb => {
+                reg.f = 0;
+                reg.c = 0x14;
+                reg.e = 0x00;
+                reg.h = 0xC0;
+                reg.l = 0x60;
+            }
+            HardwareModel::Agb => {
+                // The AGB boot ROM ends with INC B, which games use to detect it.
+                reg.b = 0x01;
+                reg.f = 0;
+            }
+        }
+        reg
+    }
+
+    /// Power-on state, before a boot ROM has run.
+    pub fn power_on() -> Registers {
+        Registers {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            pc: 0x0000,
+            sp: 0x0000,
+        }
+    }
+
     pub fn af(&self) -> u16 {
         ((self.a as u16) << 8) | ((self.f & 0xF0) as u16)
     }
@@ -129,7 +167,17 @@ impl Registers {
 mod test {
     use super::CpuFlag::{C, H, N, Z};
     use super::Registers;
-    use crate::gbmode::GbMode;
+    use crate::gbmode::{GbMode, HardwareModel};
+
+    #[test]
+    fn model_registers() {
+        let mgb = Registers::for_model(HardwareModel::Mgb, GbMode::Classic);
+        assert_eq!((mgb.af(), mgb.bc()), (0xFFB0, 0x0013));
+        let sgb = Registers::for_model(HardwareModel::Sgb, GbMode::Classic);
+        assert_eq!((sgb.af(), sgb.bc(), sgb.de(), sgb.hl()), (0x0100, 0x0014, 0x0000, 0xC060));
+        let agb = Registers::for_model(HardwareModel::Agb, GbMode::Color);
+        assert_eq!((agb.af(), agb.bc(), agb.de()), (0x1100, 0x0100, 0xFF56));
+    }
 
     #[test]
     fn wide_registers() {
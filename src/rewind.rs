@@ -0,0 +1,212 @@
+//! Bounded history of save states for rewinding.
+//!
+//! Only the newest state is kept whole. Each older state is stored as the XOR against
+//! its successor, run-length encoded: consecutive states differ in few bytes, so most
+//! of every delta collapses into zero runs.
+use std::collections::VecDeque;
+
+struct Delta {
+    // Length of the older state; states can differ in size.
+    len: usize,
+    rle: Vec<u8>,
+}
+
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // Oldest first; applying the last delta to `latest` yields the state before it.
+    deltas: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    /// Keeps at most `capacity` states; 0 disables recording.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, dropping the oldest states if it shrinks.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        if capacity == 0 {
+            self.clear();
+        }
+        while self.deltas.len() + 1 > capacity.max(1) {
+            self.deltas.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Bytes used by the stored states.
+    pub fn memory_usage(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(|d| d.rle.len()).sum::<usize>()
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(Delta {
+                len: previous.len(),
+                rle: encode_delta(&state, &previous),
+            });
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Removes and returns the newest state.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self
+            .deltas
+            .pop_back()
+            .map(|delta| apply_delta(&latest, &delta));
+        Some(latest)
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut v = 0;
+    let mut shift = 0;
+    while let Some(&b) = data.get(*pos) {
+        *pos += 1;
+        v |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    v
+}
+
+// Encodes `a XOR b` (shorter side zero-padded) as (zero run, literal count, literals) records.
+fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let len = a.len().max(b.len());
+    let xor = |i: usize| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        if i == len {
+            break;
+        }
+        let literal_start = i;
+        // Short zero runs stay inside the literal; a record costs more than they save.
+        while i < len && (xor(i) != 0 || (1..=2).any(|k| i + k < len && xor(i + k) != 0)) {
+            i += 1;
+        }
+        write_varint(&mut out, literal_start - zeros_start);
+        write_varint(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(xor));
+    }
+    out
+}
+
+fn apply_delta(state: &[u8], delta: &Delta) -> Vec<u8> {
+    let mut out = state.to_vec();
+    out.resize(out.len().max(delta.len), 0);
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < delta.rle.len() {
+        i += read_varint(&delta.rle, &mut pos);
+        let count = read_varint(&delta.rle, &mut pos);
+        for b in &delta.rle[pos..pos + count] {
+            out[i] ^= b;
+            i += 1;
+        }
+        pos += count;
+    }
+    out.truncate(delta.len);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+
+    #[test]
+    fn pops_states_newest_first() {
+        let mut buffer = RewindBuffer::new(3);
+        let states: Vec<Vec<u8>> = vec![
+            vec![1; 300],
+            [vec![1; 100], vec![7; 5], vec![1; 200]].concat(),
+            vec![1, 2, 3],
+            vec![0; 400],
+        ];
+        for s in &states {
+            buffer.push(s.clone());
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.pop().as_ref(), Some(&states[3]));
+        assert_eq!(buffer.pop().as_ref(), Some(&states[2]));
+        assert_eq!(buffer.pop().as_ref(), Some(&states[1]));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let mut a = vec![0x55u8; 100_000];
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(a.clone());
+        a[5000] = 0;
+        a[5002] = 0;
+        a[90_000] = 1;
+        buffer.push(a.clone());
+        assert!(buffer.memory_usage() < a.len() + 32);
+    }
+
+    #[test]
+    fn rewinds_device_state() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0x04, 0x18, 0xFD]); // INC B; JR -3
+        let mut device = Device::new_from_buffer(rom, true, None).unwrap();
+        let mut buffer = RewindBuffer::new(4);
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            buffer.push(device.snapshot().unwrap());
+            seen.push(device.registers().b);
+            device.do_cycle();
+            device.do_cycle();
+        }
+        for expected in seen.iter().rev().take(4) {
+            device.restore_snapshot(&buffer.pop().unwrap()).unwrap();
+            assert_eq!(device.registers().b, *expected);
+        }
+        assert!(buffer.is_empty());
+    }
+}
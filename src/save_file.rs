@@ -0,0 +1,123 @@
+//! Crash-safe writes for battery saves and save states.
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Previous versions kept next to each save, as `<file>.1` (newest) to `<file>.N`.
+pub const BACKUPS: usize = 3;
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Replaces `path` with `data` such that a crash at any point leaves either the old or the
+/// new contents in place: the data goes to `<path>.tmp` and is synced first, then renamed
+/// over the original. When `backups` is non-zero the previous contents are rotated into
+/// `<path>.1` .. `<path>.<backups>` beforehand.
+pub fn write_atomic(path: &Path, data: &[u8], backups: usize) -> io::Result<()> {
+    write(path, data, backups, true)
+}
+
+/// Like `write_atomic`, but skips the syncs. Still safe against the emulator crashing, but
+/// the new contents may be lost on a power failure; meant for frequent background saves.
+pub fn write_atomic_unsynced(path: &Path, data: &[u8], backups: usize) -> io::Result<()> {
+    write(path, data, backups, false)
+}
+
+fn write(path: &Path, data: &[u8], backups: usize, sync: bool) -> io::Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let result = write_file(&tmp, data, sync)
+        .and_then(|()| rotate_backups(path, backups))
+        .and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // Make the rename itself durable; not every platform can open a directory.
+    #[cfg(unix)]
+    if sync && let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        let _ = fs::File::open(dir).and_then(|d| d.sync_all());
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, data: &[u8], sync: bool) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(data)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    if backups == 0 || !path.exists() {
+        return Ok(());
+    }
+    // Copied rather than moved so the save itself never goes missing, and copied before
+    // anything is rotated so a failed copy leaves the existing backups where they were.
+    let newest = with_suffix(path, ".1.tmp");
+    if let Err(e) = fs::copy(path, &newest) {
+        let _ = fs::remove_file(&newest);
+        return Err(e);
+    }
+    for i in (1..backups).rev() {
+        let from = with_suffix(path, &format!(".{}", i));
+        if from.exists() {
+            fs::rename(&from, with_suffix(path, &format!(".{}", i + 1)))?;
+        }
+    }
+    fs::rename(newest, with_suffix(path, ".1"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_atomic, write_atomic_unsynced};
+    use std::fs;
+
+    #[test]
+    fn rotates_backups() {
+        let dir = std::env::temp_dir().join(format!("rust_gbe_save_file_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        for v in 0..5u8 {
+            write_atomic(&path, &[v], 3).unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), [4]);
+        assert_eq!(fs::read(dir.join("game.sav.1")).unwrap(), [3]);
+        assert_eq!(fs::read(dir.join("game.sav.3")).unwrap(), [1]);
+        assert!(!dir.join("game.sav.4").exists());
+        assert!(!dir.join("game.sav.tmp").exists());
+
+        write_atomic(&path, &[5], 0).unwrap();
+        assert_eq!(fs::read(dir.join("game.sav.1")).unwrap(), [3]);
+        write_atomic_unsynced(&path, &[6], 3).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [6]);
+        assert_eq!(fs::read(dir.join("game.sav.1")).unwrap(), [5]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn failed_copy_leaves_backups_alone() {
+        let dir = std::env::temp_dir().join(format!("rust_gbe_save_file_copy_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.sav");
+        for v in 0..3u8 {
+            write_atomic(&path, &[v], 3).unwrap();
+        }
+        // A directory in the way of the copy makes it fail.
+        fs::create_dir(dir.join("game.sav.1.tmp")).unwrap();
+        for _ in 0..2 {
+            assert!(write_atomic(&path, &[9], 3).is_err());
+        }
+        assert_eq!(fs::read(&path).unwrap(), [2]);
+        assert_eq!(fs::read(dir.join("game.sav.1")).unwrap(), [1]);
+        assert_eq!(fs::read(dir.join("game.sav.2")).unwrap(), [0]);
+        assert!(!dir.join("game.sav.3").exists());
+        assert!(!dir.join("game.sav.tmp").exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
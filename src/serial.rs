@@ -1,8 +1,49 @@
+/// The far end of the link cable plugged into the serial port.
+///
+/// A transfer is always driven by the side using its internal clock (SC bit 0 set).
+/// That side calls `exchange` once all eight bits have been shifted; the other side
+/// learns about the transfer through `poll`.
+pub trait SerialLink: Send {
+    /// Shift `out` to the peer and return the byte shifted back in. `clock` is the local
+    /// serial clock (in CPU ticks) at the moment the transfer completes. `None` means the
+    /// answer is not in yet: the transfer stays busy and `reply` is asked every step.
+    fn exchange(&mut self, out: u8, clock: u64) -> Option<u8>;
+
+    /// The answer to an `exchange` that returned `None`, once it has arrived.
+    fn reply(&mut self, _clock: u64) -> Option<u8> {
+        Some(0xFF)
+    }
+
+    /// Called whenever no internal-clock transfer is running. Returns a byte clocked in by
+    /// the peer; only expected while the published SC selects the external clock.
+    fn poll(&mut self, _clock: u64) -> Option<u8> {
+        None
+    }
+
+    /// Called whenever SB or SC change so the peer can see the byte it would receive.
+    fn publish(&mut self, _data: u8, _control: u8) {}
+}
+
+// Ticks per bit at 8192 Hz, and at 262144 Hz with the CGB fast clock (SC bit 1).
+const BIT_TICKS_NORMAL: u32 = 512;
+const BIT_TICKS_FAST: u32 = 16;
+
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct Serial {
     data: u8,
     control: u8,
+    // Ticks until the current internal-clock transfer completes; 0 when idle.
+    transfer_ticks: u32,
+    // All bits were shifted, but the link has not answered yet.
+    awaiting_reply: bool,
+    clock: u64,
     pub interrupt: u8,
+    #[rkyv(with = rkyv::with::Skip)]
+    link: Option<Box<dyn SerialLink>>,
+    // Bytes sent with the internal clock, collected only when capture is enabled.
+    // Host-side diagnostics, not part of the machine state.
+    #[rkyv(with = rkyv::with::Skip)]
+    capture: Option<Vec<u8>>,
 }
 
 impl Serial {
@@ -10,29 +51,119 @@ impl Serial {
         match a {
             0xFF01 => self.data = v,
             0xFF02 => {
-                self.control = v;
-                if v & 0x81 == 0x81 {
-                    // No link/printer; emulate instant transfer complete.
-                    self.interrupt = 0x8;
-                }
+                self.control = v & 0x83;
+                self.awaiting_reply = false;
+                self.transfer_ticks = if v & 0x81 == 0x81 {
+                    if let Some(capture) = self.capture.as_mut() {
+                        capture.push(self.data);
+                    }
+                    8 * if v & 0x02 == 0x02 {
+                        BIT_TICKS_FAST
+                    } else {
+                        BIT_TICKS_NORMAL
+                    }
+                } else {
+                    0
+                };
             }
             _ => panic!("Serial does not handle address {:4X} (write)", a),
         };
+        self.publish();
     }
 
     pub fn rb(&self, a: u16) -> u8 {
         match a {
             0xFF01 => self.data,
-            0xFF02 => self.control | 0b01111110,
+            0xFF02 => self.control | 0b01111100,
             _ => panic!("Serial does not handle address {:4X} (read)", a),
         }
     }
 
-    pub fn new() -> Serial {
-        Serial {
+    pub fn new(link: Option<Box<dyn SerialLink>>) -> Serial {
+        let mut res = Serial {
             data: 0,
             control: 0,
+            transfer_ticks: 0,
+            awaiting_reply: false,
+            clock: 0,
             interrupt: 0,
+            link: None,
+            capture: None,
+        };
+        res.set_link(link);
+        res
+    }
+
+    /// Advance the serial clock by `ticks` CPU ticks (the shift clock follows CPU speed).
+    pub fn do_cycle(&mut self, ticks: u32) {
+        self.clock += ticks as u64;
+
+        if self.awaiting_reply {
+            let incoming = match self.link.as_mut() {
+                Some(link) => link.reply(self.clock),
+                None => Some(0xFF),
+            };
+            if let Some(incoming) = incoming {
+                self.awaiting_reply = false;
+                self.complete_transfer(incoming);
+            }
+        } else if self.transfer_ticks > 0 {
+            if ticks < self.transfer_ticks {
+                self.transfer_ticks -= ticks;
+                return;
+            }
+            self.transfer_ticks = 0;
+            // With nothing attached the input line floats high.
+            let incoming = match self.link.as_mut() {
+                Some(link) => link.exchange(self.data, self.clock),
+                None => Some(0xFF),
+            };
+            match incoming {
+                Some(incoming) => self.complete_transfer(incoming),
+                None => self.awaiting_reply = true,
+            }
+        } else if let Some(incoming) = self.link.as_mut().and_then(|l| l.poll(self.clock)) {
+            // The shift register follows the external clock even when no transfer
+            // was requested, but only a requested transfer raises the interrupt.
+            if self.control & 0x80 == 0x80 {
+                self.complete_transfer(incoming);
+            } else {
+                self.data = incoming;
+                self.publish();
+            }
+        }
+    }
+
+    fn complete_transfer(&mut self, incoming: u8) {
+        self.data = incoming;
+        self.control &= 0x7F;
+        self.interrupt |= 0x08;
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        if let Some(link) = self.link.as_mut() {
+            link.publish(self.data, self.control);
         }
     }
+
+    pub fn set_link(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.link = link;
+        self.publish();
+    }
+
+    pub fn take_link(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    pub fn enable_capture(&mut self) {
+        if self.capture.is_none() {
+            self.capture = Some(Vec::new());
+        }
+    }
+
+    /// Returns the bytes sent since the last call and clears the capture buffer.
+    pub fn take_captured(&mut self) -> Vec<u8> {
+        self.capture.as_mut().map(std::mem::take).unwrap_or_default()
+    }
 }
@@ -0,0 +1,450 @@
+//! Super Game Boy: command packets sent through the joypad register, palettes and the
+//! 256x224 picture with border that the SNES puts on screen.
+use crate::gpu::{GPU, SCREEN_H, SCREEN_W};
+use crate::keypad::Keypad;
+
+pub const SGB_SCREEN_W: usize = 256;
+pub const SGB_SCREEN_H: usize = 224;
+
+// Top-left corner of the Game Boy picture inside the border.
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+const PACKET_BITS: usize = 16 * 8;
+const ATTR_W: usize = SCREEN_W / 8;
+const ATTR_H: usize = SCREEN_H / 8;
+const ATTR_FILE_SIZE: usize = ATTR_W * ATTR_H / 4;
+const ATTR_FILES: usize = 45;
+// Palette "1-A", which the SGB uses until a game sets its own.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Clone, Copy, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+enum Transfer {
+    Palettes,
+    BorderTiles { high: bool },
+    Border,
+    AttributeFiles,
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Sgb {
+    joyp: u8,
+    // Index of the next packet bit, or None while waiting for a reset pulse.
+    bit: Option<usize>,
+    packet: [u8; 16],
+    command: Vec<u8>,
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    attributes: [u8; ATTR_W * ATTR_H],
+    attribute_files: Vec<u8>,
+    // 256 SNES 4bpp tiles, the 32x32 tile map and palettes 4-7.
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: [[u16; 16]; 4],
+    mask: u8,
+    transfer: Option<Transfer>,
+    screen: Vec<u8>,
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        let mut sgb = Sgb {
+            joyp: 0x30,
+            bit: None,
+            packet: [0; 16],
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; 512 * 4],
+            attributes: [0; ATTR_W * ATTR_H],
+            attribute_files: vec![0; ATTR_FILES * ATTR_FILE_SIZE],
+            border_tiles: vec![0; 256 * 32],
+            border_map: vec![0; 32 * 32 * 2],
+            border_palettes: [[0; 16]; 4],
+            mask: 0,
+            transfer: None,
+            screen: vec![0; SGB_SCREEN_W * SGB_SCREEN_H * 3],
+        };
+        sgb.draw_border();
+        sgb
+    }
+
+    /// The picture with border, as interleaved RGB.
+    pub fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    /// True between a packet's reset pulse and its stop bit.
+    pub fn receiving_packet(&self) -> bool {
+        self.bit.is_some()
+    }
+
+    /// Receives packet bits from writes to 0xFF00: P14 and P15 both low reset the
+    /// packet, P14 low sends a 0, P15 low a 1, and both high end each pulse.
+    pub fn write_joypad(&mut self, value: u8, keypad: &mut Keypad) {
+        let lines = value & 0x30;
+        let previous = self.joyp;
+        self.joyp = lines;
+        if lines == previous || previous != 0x30 {
+            return;
+        }
+        match (lines, self.bit) {
+            (0x00, _) => {
+                self.bit = Some(0);
+                self.packet = [0; 16];
+            }
+            // The stop bit after the last data bit completes the packet.
+            (0x20, Some(PACKET_BITS)) => {
+                self.bit = None;
+                self.receive_packet(keypad);
+            }
+            (0x10, Some(PACKET_BITS)) => self.bit = None,
+            (0x10 | 0x20, Some(bit)) => {
+                if lines == 0x10 {
+                    self.packet[bit / 8] |= 1 << (bit % 8);
+                }
+                self.bit = Some(bit + 1);
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_packet(&mut self, keypad: &mut Keypad) {
+        if self.command.is_empty() && self.packet[0] & 0x07 == 0 {
+            return;
+        }
+        self.command.extend_from_slice(&self.packet);
+        if self.command.len() / 16 >= (self.command[0] & 0x07) as usize {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command, keypad);
+        }
+    }
+
+    fn execute(&mut self, c: &[u8], keypad: &mut Keypad) {
+        match c[0] >> 3 {
+            0x00 => self.set_palette_pair(c, 0, 1),
+            0x01 => self.set_palette_pair(c, 2, 3),
+            0x02 => self.set_palette_pair(c, 0, 3),
+            0x03 => self.set_palette_pair(c, 1, 2),
+            0x04 => self.attr_blk(c),
+            0x05 => self.attr_lin(c),
+            0x06 => self.attr_div(c),
+            0x07 => self.attr_chr(c),
+            0x0A => self.pal_set(c),
+            0x0B => self.transfer = Some(Transfer::Palettes),
+            0x11 => keypad.set_players(match c[1] & 0x03 {
+                1 => 2,
+                3 => 4,
+                _ => 1,
+            }),
+            0x13 => self.transfer = Some(Transfer::BorderTiles { high: c[1] & 0x01 != 0 }),
+            0x14 => self.transfer = Some(Transfer::Border),
+            0x15 => self.transfer = Some(Transfer::AttributeFiles),
+            0x16 => self.attr_set(c[1]),
+            0x17 => self.mask = c[1] & 0x03,
+            // Sound, SNES code upload and the rest have no visible effect here.
+            _ => {}
+        }
+    }
+
+    fn set_palette_pair(&mut self, c: &[u8], a: usize, b: usize) {
+        let color = |i: usize| u16::from_le_bytes([c[1 + i * 2], c[2 + i * 2]]);
+        for p in 0..4 {
+            self.palettes[p][0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[a][i] = color(i);
+            self.palettes[b][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, c: &[u8]) {
+        let count = (c[1] as usize).min((c.len() - 2) / 6);
+        for set in c[2..2 + count * 6].chunks_exact(6) {
+            let control = set[0] & 0x07;
+            let (inside, line, outside) = (set[1] & 0x03, (set[1] >> 2) & 0x03, (set[1] >> 4) & 0x03);
+            // With only the inside or only the outside selected, the border line follows it.
+            let line = match control {
+                1 => Some(inside),
+                4 => Some(outside),
+                c if c & 0x02 != 0 => Some(line),
+                _ => None,
+            };
+            let (x1, y1, x2, y2) = (set[2] as usize, set[3] as usize, set[4] as usize, set[5] as usize);
+            for y in 0..ATTR_H {
+                for x in 0..ATTR_W {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let palette = if within && x > x1 && x < x2 && y > y1 && y < y2 {
+                        (control & 0x01 != 0).then_some(inside)
+                    } else if within {
+                        line
+                    } else {
+                        (control & 0x04 != 0).then_some(outside)
+                    };
+                    if let Some(p) = palette {
+                        self.attributes[y * ATTR_W + x] = p;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, c: &[u8]) {
+        let count = (c[1] as usize).min(c.len() - 2);
+        for &entry in &c[2..2 + count] {
+            let (n, palette) = ((entry & 0x1F) as usize, (entry >> 5) & 0x03);
+            if entry & 0x80 != 0 && n < ATTR_H {
+                self.attributes[n * ATTR_W..(n + 1) * ATTR_W].fill(palette);
+            } else if entry & 0x80 == 0 && n < ATTR_W {
+                for y in 0..ATTR_H {
+                    self.attributes[y * ATTR_W + n] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, c: &[u8]) {
+        let (after, before, line) = (c[1] & 0x03, (c[1] >> 2) & 0x03, (c[1] >> 4) & 0x03);
+        let horizontal = c[1] & 0x40 != 0;
+        let at = c[2] as usize;
+        for y in 0..ATTR_H {
+            for x in 0..ATTR_W {
+                let pos = if horizontal { y } else { x };
+                self.attributes[y * ATTR_W + x] = match pos.cmp(&at) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, c: &[u8]) {
+        let (mut x, mut y) = (c[1] as usize, c[2] as usize);
+        let count = u16::from_le_bytes([c[3], c[4]]) as usize;
+        let vertical = c[5] & 0x01 != 0;
+        for i in 0..count.min((c.len() - 6) * 4) {
+            if x >= ATTR_W || y >= ATTR_H {
+                break;
+            }
+            self.attributes[y * ATTR_W + x] = (c[6 + i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+            if vertical {
+                y += 1;
+                if y == ATTR_H {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_W {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, c: &[u8]) {
+        for p in 0..4 {
+            let n = u16::from_le_bytes([c[1 + p * 2], c[2 + p * 2]]) as usize & 0x1FF;
+            self.palettes[p].copy_from_slice(&self.system_palettes[n * 4..n * 4 + 4]);
+        }
+        for p in 1..4 {
+            self.palettes[p][0] = self.palettes[0][0];
+        }
+        if c[9] & 0x80 != 0 {
+            self.attr_set(c[9] & 0x3F);
+        }
+        if c[9] & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    fn attr_set(&mut self, value: u8) {
+        let n = (value & 0x3F) as usize;
+        if n < ATTR_FILES {
+            let file = &self.attribute_files[n * ATTR_FILE_SIZE..(n + 1) * ATTR_FILE_SIZE];
+            for (i, a) in self.attributes.iter_mut().enumerate() {
+                *a = (file[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+            }
+        }
+        if value & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    /// Called at the start of VBlank: finishes a pending VRAM transfer, which takes the
+    /// frame that was just displayed, and redraws the picture.
+    pub fn vblank(&mut self, gpu: &GPU) {
+        if let Some(transfer) = self.transfer.take() {
+            let data = gpu.sgb_transfer_data();
+            match transfer {
+                Transfer::Palettes => {
+                    for (i, p) in self.system_palettes.iter_mut().enumerate() {
+                        *p = u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+                    }
+                }
+                Transfer::BorderTiles { high } => {
+                    let start = if high { 0x1000 } else { 0 };
+                    self.border_tiles[start..start + 0x1000].copy_from_slice(&data);
+                }
+                Transfer::Border => {
+                    self.border_map.copy_from_slice(&data[..0x800]);
+                    for (i, color) in data[0x800..0x880].chunks_exact(2).enumerate() {
+                        self.border_palettes[i / 16][i % 16] = u16::from_le_bytes([color[0], color[1]]);
+                    }
+                }
+                Transfer::AttributeFiles => {
+                    let len = self.attribute_files.len();
+                    self.attribute_files.copy_from_slice(&data[..len]);
+                }
+            }
+            self.draw_border();
+        }
+        self.draw_game(gpu.front_buffer());
+    }
+
+    fn draw_game(&mut self, frame: &[u8]) {
+        let backdrop = self.palettes[0][0];
+        for y in 0..SCREEN_H {
+            for x in 0..SCREEN_W {
+                let color = match self.mask {
+                    1 => continue,
+                    2 => 0x0000,
+                    3 => backdrop,
+                    _ => {
+                        let shade = match frame[(y * SCREEN_W + x) * 3] {
+                            255 => 0,
+                            192 => 1,
+                            96 => 2,
+                            _ => 3,
+                        };
+                        self.palettes[self.attributes[(y / 8) * ATTR_W + x / 8] as usize][shade]
+                    }
+                };
+                set_pixel(&mut self.screen, GAME_X + x, GAME_Y + y, color);
+            }
+        }
+    }
+
+    fn draw_border(&mut self) {
+        let backdrop = self.palettes[0][0];
+        for ty in 0..SGB_SCREEN_H / 8 {
+            for tx in 0..SGB_SCREEN_W / 8 {
+                let i = (ty * 32 + tx) * 2;
+                let entry = u16::from_le_bytes([self.border_map[i], self.border_map[i + 1]]);
+                let tile = &self.border_tiles[(entry as usize & 0xFF) * 32..][..32];
+                let palette = ((entry >> 10) & 0x03) as usize;
+                for py in 0..8 {
+                    let row = if entry & 0x8000 != 0 { 7 - py } else { py };
+                    for px in 0..8 {
+                        let (x, y) = (tx * 8 + px, ty * 8 + py);
+                        let bit = if entry & 0x4000 != 0 { px } else { 7 - px };
+                        let planes = [tile[row * 2], tile[row * 2 + 1], tile[16 + row * 2], tile[17 + row * 2]];
+                        let index = planes
+                            .iter()
+                            .enumerate()
+                            .fold(0, |acc, (p, b)| acc | (((b >> bit) & 1) as usize) << p);
+                        let color = match index {
+                            0 => backdrop,
+                            n => self.border_palettes[palette][n],
+                        };
+                        set_pixel(&mut self.screen, x, y, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Writes a BGR555 color as RGB.
+fn set_pixel(screen: &mut [u8], x: usize, y: usize, color: u16) {
+    let i = (y * SGB_SCREEN_W + x) * 3;
+    for (c, shift) in [0, 5, 10].into_iter().enumerate() {
+        let v = ((color >> shift) & 0x1F) as u8;
+        screen[i + c] = (v << 3) | (v >> 2);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn send(sgb: &mut Sgb, keypad: &mut Keypad, packet: &[u8; 16]) {
+        let mut pulse = |lines: u8| {
+            sgb.write_joypad(lines, keypad);
+            sgb.write_joypad(0x30, keypad);
+        };
+        pulse(0x00);
+        for bit in 0..PACKET_BITS {
+            pulse(if packet[bit / 8] & (1 << (bit % 8)) != 0 { 0x10 } else { 0x20 });
+        }
+        pulse(0x20);
+    }
+
+    fn packet(bytes: &[u8]) -> [u8; 16] {
+        let mut p = [0; 16];
+        p[..bytes.len()].copy_from_slice(bytes);
+        p
+    }
+
+    #[test]
+    fn palettes_and_attributes() {
+        let (mut sgb, mut keypad) = (Sgb::new(), Keypad::new());
+        // PAL01: shared color 0, palette 0 colors 1-3, palette 1 colors 1-3.
+        send(&mut sgb, &mut keypad, &packet(&[0x01, 0x1F, 0x00, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0]));
+        assert_eq!(sgb.palettes[0], [0x001F, 1, 2, 3]);
+        assert_eq!(sgb.palettes[1], [0x001F, 4, 5, 6]);
+        assert_eq!(sgb.palettes[2][0], 0x001F);
+
+        // ATTR_BLK: inside palette 1, border palette 2 around (2,2)-(6,5).
+        send(&mut sgb, &mut keypad, &packet(&[0x21, 1, 0x03, 0x09, 2, 2, 6, 5]));
+        assert_eq!(sgb.attributes[3 * ATTR_W + 3], 1);
+        assert_eq!(sgb.attributes[2 * ATTR_W + 4], 2);
+        assert_eq!(sgb.attributes[0], 0);
+
+        // ATTR_DIV: horizontal division at row 10, palette 3 below.
+        send(&mut sgb, &mut keypad, &packet(&[0x31, 0x43, 10]));
+        assert_eq!(sgb.attributes[17 * ATTR_W], 3);
+        assert_eq!(sgb.attributes[10 * ATTR_W], 0);
+
+        sgb.draw_game(&vec![255; SCREEN_W * SCREEN_H * 3]);
+        let i = ((GAME_Y + 8 * 17) * SGB_SCREEN_W + GAME_X) * 3;
+        assert_eq!(&sgb.screen[i..i + 3], &[0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn border_transfer_reads_screen_tiles() {
+        let (mut sgb, mut keypad) = (Sgb::new(), Keypad::new());
+        let mut gpu = GPU::new();
+        gpu.wb(0xFF40, 0x91);
+        gpu.wb(0xFF47, 0xE4);
+        for i in 0..256u16 {
+            gpu.wb(0x9800 + (i / 20) * 32 + i % 20, i as u8);
+        }
+        // Map entry 0 uses tile 1 with palette 5; palette 4 color 1 is blue.
+        gpu.wb(0x8000, 0x01);
+        gpu.wb(0x8001, 0x04);
+        gpu.wb(0x8802, 0x00);
+        gpu.wb(0x8803, 0x7C);
+        gpu.wb(0x8822, 0xE0);
+        gpu.wb(0x8823, 0x03);
+        send(&mut sgb, &mut keypad, &packet(&[0xA1]));
+        sgb.vblank(&gpu);
+        assert_eq!(&sgb.border_map[..2], &[0x01, 0x04]);
+        assert_eq!(sgb.border_palettes[0][1], 0x7C00);
+        assert_eq!(sgb.border_palettes[1][1], 0x03E0);
+    }
+
+    #[test]
+    fn multiplayer_ids() {
+        let (mut sgb, mut keypad) = (Sgb::new(), Keypad::new());
+        send(&mut sgb, &mut keypad, &packet(&[0x89, 0x01]));
+        keypad.wb(0x30);
+        assert_eq!(keypad.rb() & 0x0F, 0x0F);
+        keypad.wb(0x10);
+        keypad.wb(0x30);
+        assert_eq!(keypad.rb() & 0x0F, 0x0E);
+        keypad.wb(0x10);
+        keypad.wb(0x30);
+        assert_eq!(keypad.rb() & 0x0F, 0x0F);
+    }
+}
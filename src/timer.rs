@@ -1,43 +1,40 @@
+// TIMA counts falling edges of one bit of the 16-bit system counter (DIV is its upper
+// byte), selected by TAC and ANDed with the enable bit. Anything that drops that signal
+// counts, including writes to DIV and TAC.
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct Timer {
-    divider: u8,
+    system_counter: u16,
     counter: u8,
     modulo: u8,
-    enabled: bool,
-    step: u32,
-    internalcnt: u32,
-    internaldiv: u32,
+    tac: u8,
+    // TIMA overflowed last M-cycle and reads 0; it is reloaded from TMA on the next one
+    // unless TIMA is written first.
+    overflow: bool,
+    // TIMA was reloaded this M-cycle: writes to TIMA are ignored and writes to TMA also
+    // go to TIMA.
+    reloading: bool,
     pub interrupt: u8,
 }
 
 impl Timer {
     pub fn new() -> Timer {
         Timer {
-            divider: 0,
+            system_counter: 0,
             counter: 0,
             modulo: 0,
-            enabled: false,
-            step: 1024,
-            internalcnt: 0,
-            internaldiv: 0,
+            tac: 0,
+            overflow: false,
+            reloading: false,
             interrupt: 0,
         }
     }
 
     pub fn rb(&self, a: u16) -> u8 {
         match a {
-            0xFF04 => self.divider,
+            0xFF04 => (self.system_counter >> 8) as u8,
             0xFF05 => self.counter,
             0xFF06 => self.modulo,
-            0xFF07 => {
-                0xF8 | (if self.enabled { 0x4 } else { 0 })
-                    | (match self.step {
-                        16 => 1,
-                        64 => 2,
-                        256 => 3,
-                        _ => 0,
-                    })
-            }
+            0xFF07 => 0xF8 | self.tac,
             _ => panic!("Timer does not handler read {:4X}", a),
         }
     }
@@ -45,45 +42,125 @@ impl Timer {
     pub fn wb(&mut self, a: u16, v: u8) {
         match a {
             0xFF04 => {
-                self.divider = 0;
+                let old = self.signal();
+                self.system_counter = 0;
+                self.check_edge(old);
             }
             0xFF05 => {
-                self.counter = v;
+                if !self.reloading {
+                    self.counter = v;
+                    self.overflow = false;
+                }
             }
             0xFF06 => {
                 self.modulo = v;
+                if self.reloading {
+                    self.counter = v;
+                }
             }
             0xFF07 => {
-                self.enabled = v & 0x4 != 0;
-                self.step = match v & 0x3 {
-                    1 => 16,
-                    2 => 64,
-                    3 => 256,
-                    _ => 1024,
-                };
+                let old = self.signal();
+                self.tac = v & 0x07;
+                self.check_edge(old);
             }
             _ => panic!("Timer does not handler write {:4X}", a),
         };
     }
 
+    /// Advance by `ticks` CPU ticks, which always come in whole M-cycles of 4.
     pub fn do_cycle(&mut self, ticks: u32) {
-        self.internaldiv += ticks;
-        while self.internaldiv >= 256 {
-            self.divider = self.divider.wrapping_add(1);
-            self.internaldiv -= 256;
+        debug_assert!(ticks.is_multiple_of(4), "timer stepped by {} ticks", ticks);
+        for _ in 0..ticks / 4 {
+            self.reloading = false;
+            if self.overflow {
+                self.overflow = false;
+                self.counter = self.modulo;
+                self.interrupt |= 0x04;
+                self.reloading = true;
+            }
+            let old = self.signal();
+            self.system_counter = self.system_counter.wrapping_add(4);
+            self.check_edge(old);
         }
+    }
 
-        if self.enabled {
-            self.internalcnt += ticks;
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            1 => 3,
+            2 => 5,
+            3 => 7,
+            _ => 9,
+        };
+        self.tac & 0x04 != 0 && self.system_counter & (1 << bit) != 0
+    }
 
-            while self.internalcnt >= self.step {
-                self.counter = self.counter.wrapping_add(1);
-                if self.counter == 0 {
-                    self.counter = self.modulo;
-                    self.interrupt |= 0x04;
-                }
-                self.internalcnt -= self.step;
+    fn check_edge(&mut self, old: bool) {
+        if old && !self.signal() {
+            self.counter = self.counter.wrapping_add(1);
+            if self.counter == 0 {
+                self.overflow = true;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Timer;
+
+    fn m_cycles(timer: &mut Timer, n: u32) {
+        timer.do_cycle(n * 4);
+    }
+
+    #[test]
+    fn div_and_tac_writes_can_increment() {
+        let mut timer = Timer::new();
+        timer.wb(0xFF07, 0x05);
+        m_cycles(&mut timer, 2);
+        assert_eq!(timer.rb(0xFF05), 0);
+        // Bit 3 is set, so resetting the counter is a falling edge.
+        timer.wb(0xFF04, 0);
+        assert_eq!(timer.rb(0xFF05), 1);
+        m_cycles(&mut timer, 3);
+        assert_eq!(timer.rb(0xFF05), 1);
+        m_cycles(&mut timer, 1);
+        assert_eq!(timer.rb(0xFF05), 2);
+
+        m_cycles(&mut timer, 2);
+        timer.wb(0xFF07, 0x04);
+        assert_eq!(timer.rb(0xFF05), 3);
+        timer.wb(0xFF07, 0x00);
+        assert_eq!(timer.rb(0xFF05), 3);
+        assert_eq!(timer.rb(0xFF04), 0);
+        m_cycles(&mut timer, 64);
+        assert_eq!(timer.rb(0xFF04), 1);
+    }
+
+    #[test]
+    fn tima_reload_is_delayed() {
+        let mut timer = Timer::new();
+        timer.wb(0xFF06, 0x80);
+        timer.wb(0xFF05, 0xFF);
+        timer.wb(0xFF07, 0x05);
+        m_cycles(&mut timer, 4);
+        assert_eq!((timer.rb(0xFF05), timer.interrupt), (0x00, 0));
+        m_cycles(&mut timer, 1);
+        assert_eq!((timer.rb(0xFF05), timer.interrupt), (0x80, 0x04));
+        // Writes to TIMA are ignored on the reload cycle; TMA goes through.
+        timer.wb(0xFF05, 0x10);
+        timer.wb(0xFF06, 0x90);
+        assert_eq!(timer.rb(0xFF05), 0x90);
+    }
+
+    #[test]
+    fn tima_write_aborts_reload() {
+        let mut timer = Timer::new();
+        timer.wb(0xFF06, 0x80);
+        timer.wb(0xFF05, 0xFF);
+        timer.wb(0xFF07, 0x05);
+        m_cycles(&mut timer, 4);
+        timer.wb(0xFF05, 0x10);
+        m_cycles(&mut timer, 1);
+        assert_eq!((timer.rb(0xFF05), timer.interrupt), (0x10, 0));
+    }
+}
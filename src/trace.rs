@@ -0,0 +1,138 @@
+//! Instruction trace in the Gameboy Doctor log format, for diffing against other emulators:
+//! `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+use crate::debugger::CpuRegisters;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Restricts which instructions are logged. Both conditions must hold when set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Inclusive PC range.
+    pub pc: Option<(u16, u16)>,
+    /// ROM bank mapped at PC. Instructions outside 0x0000-0x7FFF ignore the bank.
+    pub bank: Option<usize>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, bank: usize) -> bool {
+        let in_range = self.pc.is_none_or(|(start, end)| (start..=end).contains(&pc));
+        let in_bank = pc >= 0x8000 || self.bank.is_none_or(|b| b == bank);
+        in_range && in_bank
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    filter: TraceFilter,
+    // First write error; logging stops once one occurs.
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, filter: TraceFilter) -> Tracer {
+        Tracer {
+            out: Box::new(out),
+            filter,
+            error: None,
+        }
+    }
+
+    pub fn to_file(path: impl AsRef<Path>, filter: TraceFilter) -> io::Result<Tracer> {
+        Ok(Tracer::new(BufWriter::new(File::create(path)?), filter))
+    }
+
+    pub fn filter(&self) -> TraceFilter {
+        self.filter
+    }
+
+    /// Logs the instruction about to execute at `regs.pc`; `pcmem` holds the 4 bytes at PC.
+    pub(crate) fn log(&mut self, regs: &CpuRegisters, bank: usize, pcmem: [u8; 4]) {
+        if self.error.is_some() || !self.filter.matches(regs.pc, bank) {
+            return;
+        }
+        let result = writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.a, regs.f, regs.b, regs.c, regs.d, regs.e, regs.h, regs.l, regs.sp, regs.pc,
+            pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+        );
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+
+    /// Flushes the log, reporting the first error hit while tracing.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn test_device() -> Device {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[
+            0x00, // NOP
+            0x3E, 0x42, // LD A,$42
+            0x18, 0xFB, // JR -5
+            0x76,
+        ]);
+        Device::new_from_buffer(rom, true, None).unwrap()
+    }
+
+    fn trace(filter: TraceFilter, instructions: usize) -> Vec<String> {
+        let buf = SharedBuf::default();
+        let mut device = test_device();
+        device.start_trace(Tracer::new(buf.clone(), filter));
+        for _ in 0..instructions {
+            device.do_cycle();
+        }
+        device.stop_trace().unwrap();
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn logs_gameboy_doctor_format() {
+        let lines = trace(TraceFilter::default(), 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,3E,42,18"
+        );
+        assert!(lines[2].starts_with("A:42 F:B0 "));
+        assert!(lines[2].ends_with("PC:0103 PCMEM:18,FB,76,00"));
+    }
+
+    #[test]
+    fn filters_by_pc_and_bank() {
+        let filter = TraceFilter { pc: Some((0x101, 0x102)), bank: None };
+        let lines = trace(filter, 6);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| l.contains("PC:0101")));
+
+        let filter = TraceFilter { pc: None, bank: Some(1) };
+        assert!(trace(filter, 6).is_empty());
+    }
+}